@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use ::analysis::{analyze, AnalysisConfig};
+use tak::{PtnPly, State, Tps};
+
+#[derive(Clone)]
+#[pyclass]
+pub struct Line {
+    #[pyo3(get)]
+    pub ply: String,
+    #[pyo3(get)]
+    pub evaluation: f32,
+    #[pyo3(get)]
+    pub principal_variation: Vec<String>,
+}
+
+/// Searches a position and returns the best `multi_pv` lines, best first.
+#[pyfunction]
+#[pyo3(signature = (tps_string, depth=None, time=None, multi_pv=1))]
+pub fn analyze_tps(
+    tps_string: String,
+    depth: Option<u32>,
+    time: Option<f64>,
+    multi_pv: usize,
+) -> PyResult<Vec<Line>> {
+    if depth.is_none() && time.is_none() {
+        return Err(PyValueError::new_err("a depth or time limit is required"));
+    }
+    if multi_pv == 0 {
+        return Err(PyValueError::new_err("multi_pv must be at least 1"));
+    }
+
+    let tps: Tps = tps_string
+        .parse()
+        .map_err(|_| PyValueError::new_err("could not parse tps"))?;
+
+    macro_rules! sized {
+        ($size:expr) => {{
+            let state: State<$size> = tps
+                .try_into()
+                .map_err(|_| PyValueError::new_err("could not create state from tps"))?;
+
+            let config = AnalysisConfig::<$size> {
+                depth_limit: depth,
+                time_limit: time.map(Duration::from_secs_f64),
+                multi_pv,
+                ..Default::default()
+            };
+
+            analyze(config, &state)
+                .lines
+                .into_iter()
+                .map(|line| {
+                    let mut state = state.clone();
+                    let principal_variation = line
+                        .principal_variation
+                        .into_iter()
+                        .map(|ply| {
+                            let validation = state.execute_ply(ply).expect("invalid ply in line");
+                            PtnPly::from((ply, validation)).to_string()
+                        })
+                        .collect::<Vec<_>>();
+
+                    Line {
+                        ply: principal_variation[0].clone(),
+                        evaluation: line.evaluation.into(),
+                        principal_variation,
+                    }
+                })
+                .collect()
+        }};
+    }
+
+    let lines = match tps.size() {
+        3 => sized!(3),
+        4 => sized!(4),
+        5 => sized!(5),
+        6 => sized!(6),
+        7 => sized!(7),
+        8 => sized!(8),
+        _ => unreachable!(),
+    };
+
+    Ok(lines)
+}
@@ -1,26 +1,48 @@
 use std::fmt::Write;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use tracing::{debug, error, info, trace, trace_span, warn};
 
-use tak::{Ply, State};
+use tak::{generation, History, Ply, RepetitionRule, State};
 
+use crate::book::{BookSelection, OpeningBook};
 use crate::evaluation::{AnnEvaluator, AnnModel, Evaluation, Evaluator};
-use crate::search::{minimax, SearchState};
+use crate::move_order::PlyHistory;
+use crate::search::{minimax, BranchResult, SearchState};
 use crate::statistics::{AtomicStatistics, Statistics};
-use crate::time::TimeControl;
-use crate::transposition_table::{Bound, TranspositionTable};
+use crate::time::{Clock, TimeControl, TimeManager};
+use crate::transposition_table::{
+    Bound, TranspositionTable, TranspositionTableError, DEFAULT_TABLE_SIZE,
+};
 use crate::util::Sender;
 
+/// The default number of plies to keep responding to tak past the search depth.
+pub const DEFAULT_QUIESCENCE_DEPTH: usize = 4;
+
+/// The default distance on either side of the previous iteration's evaluation that an
+/// iteration first searches within.
+pub const DEFAULT_ASPIRATION_WINDOW: f32 = 0.1;
+
 pub struct AnalysisConfig<'a, const N: usize> {
     pub depth_limit: Option<u32>,
     pub time_limit: Option<Duration>,
+    /// If set, time is managed between iterations: the search stops early when the best
+    /// ply is stable or forced, or when the next iteration wouldn't finish in time, and a
+    /// time control's search may go past the expected time for a move when it's unstable.
     pub early_stop: bool,
     pub time_control: Option<TimeControl>,
     pub interrupted: Arc<AtomicBool>,
+    /// While set, the search is pondering on the opponent's time, and its time limit
+    /// doesn't start until this is cleared by a ponder hit.
+    pub pondering: Arc<AtomicBool>,
+    /// If set by the time `pondering` is cleared, the time control for the rest of the
+    /// search in place of `time_control`, since the clock after the opponent's reply isn't
+    /// known when pondering starts.
+    pub ponder_hit_time_control: Arc<Mutex<Option<TimeControl>>>,
     /// A place to put data gathered during the search that could be
     /// useful to future searches. If none, this will be created internally.
     pub persistent_state: Option<&'a PersistentState<N>>,
@@ -34,6 +56,37 @@ pub struct AnalysisConfig<'a, const N: usize> {
     pub interim_analysis_sender: Option<Box<dyn Sender<Analysis<N>>>>,
     /// The number of threads to use during the search.
     pub threads: usize,
+    /// The positions leading up to the analyzed state, ending with it, used to score
+    /// repeated positions as draws. If none, only repetitions within the search itself
+    /// are detected, using the default rule.
+    pub history: Option<&'a History>,
+    /// An opening book to play from instead of searching, when it covers the position.
+    pub book: Option<&'a OpeningBook<N>>,
+    pub book_selection: BookSelection,
+    /// The size of the transposition table in megabytes, used when no persistent state is
+    /// given.
+    pub table_size: usize,
+    /// The maximum number of nodes to visit, counted across all threads.
+    pub node_limit: Option<u64>,
+    /// The number of root plies to find lines for, best first. Each line after the first
+    /// is searched without the root plies of the lines before it.
+    pub multi_pv: usize,
+    /// If given, only these plies are considered from the analyzed position.
+    pub included_plies: Option<Vec<Ply<N>>>,
+    /// Plies that aren't considered from the analyzed position.
+    pub excluded_plies: Vec<Ply<N>>,
+    /// If set, the same config, position and persistent state always give the same
+    /// result. The search uses a single thread, ignores time limits, and picks the best
+    /// book ply, so only the depth and node limits apply.
+    pub deterministic: bool,
+    /// How many plies past the search depth to keep responding to tak, so that road
+    /// threats just beyond the horizon aren't missed. Zero turns this off.
+    pub quiescence_depth: usize,
+    /// The distance on either side of the previous iteration's evaluation that each
+    /// iteration first searches within. The window is widened and the root searched again
+    /// whenever the evaluation falls outside of it. If none, every iteration is searched
+    /// with a full window.
+    pub aspiration_window: Option<Evaluation>,
 }
 
 impl<'a, const N: usize> Default for AnalysisConfig<'a, N> {
@@ -44,11 +97,24 @@ impl<'a, const N: usize> Default for AnalysisConfig<'a, N> {
             early_stop: Default::default(),
             time_control: Default::default(),
             interrupted: Default::default(),
+            pondering: Default::default(),
+            ponder_hit_time_control: Default::default(),
             persistent_state: Default::default(),
             exact_eval: Default::default(),
             evaluator: Default::default(),
             interim_analysis_sender: Default::default(),
             threads: 1,
+            history: Default::default(),
+            book: Default::default(),
+            book_selection: Default::default(),
+            table_size: DEFAULT_TABLE_SIZE,
+            node_limit: Default::default(),
+            multi_pv: 1,
+            included_plies: Default::default(),
+            excluded_plies: Default::default(),
+            deterministic: Default::default(),
+            quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+            aspiration_window: Some(DEFAULT_ASPIRATION_WINDOW.into()),
         }
     }
 }
@@ -58,10 +124,53 @@ pub struct PersistentState<const N: usize> {
     pub(crate) transposition_table: TranspositionTable<N>,
 }
 
+impl<const N: usize> PersistentState<N> {
+    /// Creates a persistent state with a transposition table of about the given number
+    /// of megabytes.
+    pub fn with_table_size(megabytes: usize) -> Self {
+        Self {
+            transposition_table: TranspositionTable::with_size(megabytes),
+        }
+    }
+
+    /// The transposition table, for inspecting what earlier searches have stored.
+    pub fn transposition_table(&self) -> &TranspositionTable<N> {
+        &self.transposition_table
+    }
+
+    /// The approximate size of the transposition table in megabytes.
+    pub fn table_size(&self) -> usize {
+        self.transposition_table.size()
+    }
+
+    /// Forgets everything learned from earlier searches.
+    pub fn clear(&self) {
+        self.transposition_table.clear();
+    }
+
+    /// Changes the size of the transposition table, forgetting everything learned from
+    /// earlier searches.
+    pub fn resize_table(&mut self, megabytes: usize) {
+        self.transposition_table.resize(megabytes);
+    }
+
+    /// Loads a transposition table saved by an earlier analysis.
+    pub fn load(filename: impl AsRef<Path>) -> Result<Self, TranspositionTableError> {
+        Ok(Self {
+            transposition_table: TranspositionTable::load(filename)?,
+        })
+    }
+
+    /// Saves the transposition table, so later analyses can pick up where this one left off.
+    pub fn save(&self, filename: impl AsRef<Path>) -> Result<(), TranspositionTableError> {
+        self.transposition_table.save(filename)
+    }
+}
+
 impl<const N: usize> Default for PersistentState<N> {
     fn default() -> Self {
         Self {
-            transposition_table: TranspositionTable::with_capacity(10_000_000),
+            transposition_table: TranspositionTable::with_size(DEFAULT_TABLE_SIZE),
         }
     }
 }
@@ -73,12 +182,32 @@ pub struct Analysis<const N: usize> {
     pub final_state: State<N>,
     pub evaluation: Evaluation,
     pub principal_variation: Vec<Ply<N>>,
+    /// The best lines found, one for each of the best root plies, ordered from best to
+    /// worst. The first line is the principal variation.
+    pub lines: Vec<Line<N>>,
     pub stats: Statistics,
     pub time: Duration,
+    /// How full the transposition table was after the search, in permille.
+    pub hashfull: u16,
+}
+
+/// A line of play starting with one of the root plies, and its evaluation.
+#[derive(Clone, Debug)]
+pub struct Line<const N: usize> {
+    pub ply: Ply<N>,
+    pub evaluation: Evaluation,
+    /// The expected continuation, starting with `ply`.
+    pub principal_variation: Vec<Ply<N>>,
 }
 
 /// Analyzes a position given a configuration, and returns an evaluation and principal variation.
 pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> Analysis<N> {
+    let config = if config.deterministic {
+        deterministic_config(config)
+    } else {
+        config
+    };
+
     info!(
         "Analyzing... depth_limit: {}, time_limit: {}, early_stop: {:?}",
         if let Some(depth_limit) = config.depth_limit {
@@ -94,36 +223,28 @@ pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> A
         config.early_stop,
     );
 
-    let mut time_limit = match (
-        config.time_limit,
-        config.time_control.map(|tc| tc.get_use_time(state)),
-    ) {
-        (Some(maximum_time), Some(use_time)) => Some(maximum_time.min(use_time)),
-        (Some(maximum_time), None) => Some(maximum_time),
-        (None, Some(use_time)) => Some(use_time),
-        (None, None) => None,
-    };
+    let mut time_limits = time_limits(config.time_limit, config.time_control, state);
 
     let mut max_depth = config.depth_limit.unwrap_or(u32::MAX) as usize;
 
     if let Some(tc) = config.time_control {
         info!("Using time control: {tc}");
 
-        if tc.time.as_millis() <= 3 {
+        if tc.limits(state).1.as_millis() <= 3 {
             trace!("Extremely low time remaining; limiting search depth to 1.");
-            time_limit = None;
+            time_limits = None;
             max_depth = 1;
         }
     }
 
-    let interrupt = time_limit.map(|time_limit| spawn_interrupt_thread(&config, time_limit));
+    let interrupt = time_limits.map(|limits| spawn_interrupt_thread(&config, state, limits));
 
     // Use the passed-in persistent state or create a local one for this analysis.
     let local_persistent_state;
     let persistent_state = if let Some(persistent_state) = config.persistent_state {
         persistent_state
     } else {
-        local_persistent_state = PersistentState::default();
+        local_persistent_state = PersistentState::with_table_size(config.table_size);
         &local_persistent_state
     };
 
@@ -137,99 +258,186 @@ pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> A
         _ => unreachable!(),
     });
 
+    let history = config
+        .history
+        .cloned()
+        .unwrap_or_else(|| History::new(RepetitionRule::default(), state));
+
     let mut analysis = Analysis {
         state: state.clone(),
         depth: 0,
         final_state: state.clone(),
         evaluation: evaluator.evaluate(state, state.resolution()),
         principal_variation: Vec::new(),
+        lines: Vec::new(),
         stats: Statistics::default(),
         time: Duration::ZERO,
+        hashfull: persistent_state.transposition_table.hashfull(),
     };
 
+    // The plies that may be played, if they're restricted.
+    let root_plies = root_plies(&config, state);
+
     let search_start_time = Instant::now();
 
+    if let Some(ply) = config
+        .book
+        .and_then(|book| book.select(state, config.book_selection))
+        .filter(|ply| root_plies.as_ref().is_none_or(|plies| plies.contains(ply)))
+    {
+        let mut final_state = state.clone();
+        final_state.execute_ply_unchecked(ply);
+
+        analysis.final_state = final_state;
+        analysis.principal_variation = vec![ply];
+        analysis.lines = vec![Line {
+            ply,
+            evaluation: analysis.evaluation,
+            principal_variation: vec![ply],
+        }];
+        analysis.time = search_start_time.elapsed();
+
+        info!(ply = ?ply, "Playing from the opening book.");
+
+        if let Some(sender) = &config.interim_analysis_sender {
+            if let Err(error) = sender.send(analysis.clone()) {
+                error!(?error, "Could not send interim analysis.");
+            }
+        }
+
+        return analysis;
+    }
+
+    // Entries from earlier searches are replaced first once the table fills up.
+    persistent_state.transposition_table.new_search();
+
+    let root_ply_count = root_plies
+        .as_ref()
+        .map_or_else(|| generation::legal_plies(state).len(), Vec::len);
+    let line_count = config.multi_pv.clamp(1, root_ply_count.max(1));
+
+    let mut time_manager = interrupt
+        .as_ref()
+        .zip(time_limits)
+        .filter(|_| config.early_stop)
+        .map(|(interrupt, (soft_limit, hard_limit))| {
+            TimeManager::new(interrupt, soft_limit, hard_limit, root_ply_count == 1)
+        });
+
+    let ply_history = PlyHistory::default();
+    let node_limit_reached = AtomicBool::default();
     let mut iteration_times = Vec::new();
 
     for iteration in 1..=max_depth {
         let iteration_start_time = Instant::now();
 
-        let depth_stats = AtomicStatistics::default();
-        let workers_terminated = AtomicBool::default();
+        if config
+            .node_limit
+            .is_some_and(|node_limit| analysis.stats.visited >= node_limit)
+        {
+            info!("Node limit reached. Stopping.");
+            break;
+        }
 
-        let search = SearchState {
-            start_ply: state.ply_count,
-            stats: &depth_stats,
-            interrupted: &config.interrupted,
-            workers_terminated: &workers_terminated,
-            persistent_state,
-            killer_moves: Default::default(),
-            exact_eval: config.exact_eval,
-            evaluator,
-        };
+        let depth_stats = AtomicStatistics::default();
+        let mut lines: Vec<Line<N>> = Vec::new();
+        let mut root = None;
 
         debug!(iteration, "Beginning analysis...");
 
-        let root = thread::scope(|scope| {
-            for i in 1..config.threads {
-                let mut search = search.clone();
-                let worker_depth = iteration + i;
-
-                thread::Builder::new()
-                    .name(format!("worker_{i}"))
-                    .spawn_scoped(scope, move || {
-                        let _worker_thread =
-                            trace_span!("thread", id = %thread::current().name().unwrap())
-                                .entered();
-
-                        let _ = minimax(
-                            &mut search,
-                            &state.clone(),
-                            worker_depth,
-                            Evaluation::MIN,
-                            Evaluation::MAX,
-                            true,
-                        );
-                    })
-                    .expect("could not spawn worker thread");
+        for line in 0..line_count {
+            let search = SearchState {
+                start_ply: state.ply_count,
+                stats: &depth_stats,
+                interrupted: &config.interrupted,
+                workers_terminated: &AtomicBool::default(),
+                node_limit: config
+                    .node_limit
+                    .map(|node_limit| node_limit - analysis.stats.visited),
+                node_limit_reached: &node_limit_reached,
+                persistent_state,
+                killer_moves: Default::default(),
+                ply_history: &ply_history,
+                previous_ply: None,
+                exact_eval: config.exact_eval,
+                evaluator,
+                history: history.clone(),
+                included_root_plies: root_plies.clone(),
+                excluded_root_plies: lines.iter().map(|line| line.ply).collect(),
+                root_ply: None,
+                quiescence_depth: config.quiescence_depth,
+            };
+
+            // Each line is expected to score about the same as the line in its place did in
+            // the previous iteration.
+            let window = config.aspiration_window.and_then(|width| {
+                let expected = analysis.lines.get(line)?.evaluation;
+                (!expected.is_terminal()).then_some((expected, width))
+            });
+
+            let (line_root, root_ply) =
+                search_root(&search, state, iteration, config.threads, window);
+
+            if search.is_stopped() {
+                break;
             }
 
-            let _main_thread = trace_span!("thread", id = %"main").entered();
+            root.get_or_insert(line_root);
 
-            let mut search = search.clone();
-            let root = minimax(
-                &mut search,
-                state,
-                iteration,
-                Evaluation::MIN,
-                Evaluation::MAX,
-                true,
+            // A finished game has no plies to search.
+            let Some(ply) = root_ply else {
+                break;
+            };
+
+            let mut after = state.clone();
+            after.execute_ply_unchecked(ply);
+
+            let (mut principal_variation, _) = fetch_pv(
+                &after,
+                &persistent_state.transposition_table,
+                line_root.depth - 1,
             );
-            search.workers_terminated.store(true, Ordering::Relaxed);
+            principal_variation.insert(0, ply);
 
-            root
-        });
+            lines.push(Line {
+                ply,
+                evaluation: line_root.evaluation,
+                principal_variation,
+            });
+        }
 
-        if config.interrupted.load(Ordering::Relaxed) {
+        if config.interrupted.load(Ordering::Relaxed) || node_limit_reached.load(Ordering::Relaxed)
+        {
             break;
         }
 
-        let (principal_variation, final_state) = fetch_pv(
-            state,
-            &search.persistent_state.transposition_table,
-            root.depth,
-        );
+        // Later lines can occasionally score better than earlier ones, since each is
+        // searched separately.
+        lines.sort_by(|a, b| f32::from(b.evaluation).total_cmp(&f32::from(a.evaluation)));
 
-        let search_stats = search.stats.load();
+        let root = root.expect("no lines were searched");
+        let (evaluation, principal_variation) = match lines.first() {
+            Some(line) => (line.evaluation, line.principal_variation.clone()),
+            None => (root.evaluation, Vec::new()),
+        };
+
+        let mut final_state = state.clone();
+        for &ply in &principal_variation {
+            final_state.execute_ply_unchecked(ply);
+        }
+
+        let search_stats = depth_stats.load();
 
         analysis = Analysis {
             state: analysis.state,
             depth: root.depth as u32,
             final_state,
-            evaluation: root.evaluation,
+            evaluation,
             principal_variation,
+            lines,
             stats: &analysis.stats + &search_stats,
             time: search_start_time.elapsed(),
+            hashfull: persistent_state.transposition_table.hashfull(),
         };
 
         if let Some(sender) = &config.interim_analysis_sender {
@@ -262,11 +470,10 @@ pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> A
             tt_store_fails = search_stats.tt_store_fails,
             tt_hits = search_stats.tt_hits,
             tt_saves = search_stats.tt_saves,
-            tt_full = %format!(
-                "{:05.2}%",
-                100.0 * search.persistent_state.transposition_table.len() as f64
-                    / search.persistent_state.transposition_table.capacity() as f64
-            ),
+            extended = search_stats.extended,
+            aspiration_fail_low = search_stats.aspiration_fail_low,
+            aspiration_fail_high = search_stats.aspiration_fail_high,
+            hashfull = analysis.hashfull,
             "Stats:",
         );
 
@@ -334,23 +541,56 @@ pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> A
             break;
         }
 
-        if let Some(time_limit) = time_limit {
-            let prediction = analysis.time + Duration::from_secs_f64(next_iteration_prediction);
-
-            // Stop if the next iteration is predicted to exceed the time limit and we've
-            // already used a third of our time.
-            if config.early_stop && analysis.time > time_limit / 3 && prediction > time_limit {
-                info!(
-                    time = %format!("{:.2}s", analysis.time.as_secs_f64()),
-                    limit = %format!("{:.2}s", time_limit.as_secs_f64()),
-                    prediction = %format!("{:.2}s", analysis.time.as_secs_f64() + next_iteration_prediction),
-                    "Next iteration is predicted to take too long. Stopping."
-                );
+        if let Some(time_manager) = &mut time_manager {
+            if let Some(&(soft_limit, hard_limit)) = interrupt.as_ref().and_then(|i| i.limits.get())
+            {
+                time_manager.set_limits(soft_limit, hard_limit);
+            }
+            if let Some(&ply) = analysis.principal_variation.first() {
+                time_manager.iteration_complete(ply, analysis.evaluation);
+            }
+
+            if time_manager.should_stop(Duration::from_secs_f64(next_iteration_prediction)) {
                 break;
             }
         }
     }
 
+    // If the search was stopped before its first iteration finished, fall back to the
+    // ply from an earlier search of this position, or failing that the first legal ply,
+    // so that there is always a ply to play.
+    if analysis.principal_variation.is_empty() && state.resolution().is_none() {
+        let ply = persistent_state
+            .transposition_table
+            .probe(state.metadata.hash)
+            .map(|entry| entry.ply())
+            .filter(|&ply| match &root_plies {
+                Some(plies) => plies.contains(&ply),
+                None => state.validate_ply(ply).is_ok(),
+            })
+            .unwrap_or_else(|| match &root_plies {
+                Some(plies) => plies[0],
+                None => generation::legal_plies(state)[0],
+            });
+
+        warn!(
+            ?ply,
+            "No iteration was completed. Falling back to an unsearched ply."
+        );
+
+        let mut final_state = state.clone();
+        final_state.execute_ply_unchecked(ply);
+
+        analysis.final_state = final_state;
+        analysis.principal_variation = vec![ply];
+        analysis.lines = vec![Line {
+            ply,
+            evaluation: analysis.evaluation,
+            principal_variation: vec![ply],
+        }];
+        analysis.time = search_start_time.elapsed();
+    }
+
     // If we started an interrupt timer, stop it.
     if let Some(interrupt) = interrupt {
         interrupt.cancel();
@@ -359,6 +599,167 @@ pub fn analyze<const N: usize>(config: AnalysisConfig<N>, state: &State<N>) -> A
     analysis
 }
 
+/// Removes everything from a config that could make its results vary between runs.
+fn deterministic_config<const N: usize>(config: AnalysisConfig<N>) -> AnalysisConfig<N> {
+    if config.time_limit.is_some() || config.time_control.is_some() {
+        warn!("Ignoring time limits for a deterministic search.");
+    }
+
+    AnalysisConfig {
+        time_limit: None,
+        time_control: None,
+        early_stop: false,
+        threads: 1,
+        book_selection: BookSelection::Best,
+        ..config
+    }
+}
+
+/// Finds the legal plies allowed by the included and excluded plies of the config, or none
+/// if they aren't restricted. The restrictions are ignored if they would leave nothing to
+/// play.
+fn root_plies<const N: usize>(config: &AnalysisConfig<N>, state: &State<N>) -> Option<Vec<Ply<N>>> {
+    if config.included_plies.is_none() && config.excluded_plies.is_empty() {
+        return None;
+    }
+
+    let plies: Vec<_> = generation::legal_plies(state)
+        .into_iter()
+        .filter(|ply| {
+            config
+                .included_plies
+                .as_ref()
+                .is_none_or(|included| included.contains(ply))
+                && !config.excluded_plies.contains(ply)
+        })
+        .collect();
+
+    if plies.is_empty() {
+        warn!("No legal plies are left after restricting them. Considering all plies.");
+        return None;
+    }
+
+    Some(plies)
+}
+
+/// Searches the root to the given depth. If given an expected evaluation and a width, the
+/// search starts with a window of that width on either side of the expectation, doubling
+/// the width on whichever side it fails and searching again until the evaluation falls
+/// inside the window. Returns the result of the final search and its best ply.
+fn search_root<const N: usize>(
+    search: &SearchState<N>,
+    state: &State<N>,
+    depth: usize,
+    threads: usize,
+    window: Option<(Evaluation, Evaluation)>,
+) -> (BranchResult, Option<Ply<N>>) {
+    let Some((expected, mut width)) = window else {
+        return search_root_window(
+            search,
+            state,
+            depth,
+            threads,
+            Evaluation::MIN,
+            Evaluation::MAX,
+        );
+    };
+
+    // Evaluations never go past a win or a loss, so a bound beyond them is a full window.
+    let lower = |width| {
+        let alpha = expected - width;
+        if alpha <= Evaluation::LOSS {
+            Evaluation::MIN
+        } else {
+            alpha
+        }
+    };
+    let upper = |width| {
+        let beta = expected + width;
+        if beta >= Evaluation::WIN {
+            Evaluation::MAX
+        } else {
+            beta
+        }
+    };
+
+    let (mut alpha, mut beta) = (lower(width), upper(width));
+
+    loop {
+        let (root, root_ply) = search_root_window(search, state, depth, threads, alpha, beta);
+
+        if search.is_stopped() {
+            return (root, root_ply);
+        }
+
+        width *= 2.0;
+
+        if root.evaluation <= alpha && alpha != Evaluation::MIN {
+            trace!(%alpha, %beta, "Failed low; widening the aspiration window.");
+            search
+                .stats
+                .aspiration_fail_low
+                .fetch_add(1, Ordering::Relaxed);
+            alpha = lower(width);
+        } else if root.evaluation >= beta && beta != Evaluation::MAX {
+            trace!(%alpha, %beta, "Failed high; widening the aspiration window.");
+            search
+                .stats
+                .aspiration_fail_high
+                .fetch_add(1, Ordering::Relaxed);
+            beta = upper(width);
+        } else {
+            return (root, root_ply);
+        }
+    }
+}
+
+/// Searches the root to the given depth within a window, with a worker for each extra
+/// thread searching deeper to fill the transposition table. Returns the result of the main
+/// search and its best ply.
+fn search_root_window<const N: usize>(
+    search: &SearchState<N>,
+    state: &State<N>,
+    depth: usize,
+    threads: usize,
+    alpha: Evaluation,
+    beta: Evaluation,
+) -> (BranchResult, Option<Ply<N>>) {
+    // Workers from an earlier search of the root were stopped once it finished.
+    search.workers_terminated.store(false, Ordering::Relaxed);
+
+    thread::scope(|scope| {
+        for i in 1..threads {
+            let mut search = search.clone();
+            let worker_depth = depth + i;
+
+            thread::Builder::new()
+                .name(format!("worker_{i}"))
+                .spawn_scoped(scope, move || {
+                    let _worker_thread =
+                        trace_span!("thread", id = %thread::current().name().unwrap()).entered();
+
+                    let _ = minimax(
+                        &mut search,
+                        &mut state.clone(),
+                        worker_depth,
+                        Evaluation::MIN,
+                        Evaluation::MAX,
+                        true,
+                    );
+                })
+                .expect("could not spawn worker thread");
+        }
+
+        let _main_thread = trace_span!("thread", id = %"main").entered();
+
+        let mut search = search.clone();
+        let root = minimax(&mut search, &mut state.clone(), depth, alpha, beta, true);
+        search.workers_terminated.store(true, Ordering::Relaxed);
+
+        (root, search.root_ply)
+    })
+}
+
 fn fetch_pv<const N: usize>(
     state: &State<N>,
     tt: &TranspositionTable<N>,
@@ -369,7 +770,11 @@ fn fetch_pv<const N: usize>(
 
     debug!("Fetching PV from transposition table.");
 
-    while let Some(entry) = tt.get(state.metadata.hash) {
+    while pv.len() < max_depth {
+        let Some(entry) = tt.probe(state.metadata.hash) else {
+            break;
+        };
+
         let old_state = state.clone();
         if let Err(err) = state.execute_ply(entry.ply()) {
             error!(error = ?err, ?entry, state = ?old_state, "Transposition table ply caused an error. Ending fetch");
@@ -382,7 +787,7 @@ fn fetch_pv<const N: usize>(
             pv.push(entry.ply());
 
             // Only grab as many as we've actually analyzed.
-            if pv.len() == max_depth || state.resolution().is_some() {
+            if state.resolution().is_some() {
                 break;
             }
         }
@@ -397,41 +802,428 @@ fn fetch_pv<const N: usize>(
     (pv, state)
 }
 
+/// The soft and hard time limits for a search, from the maximum time and the time
+/// control. The soft limit is the time to aim for, and the hard limit is the most to use.
+fn time_limits<const N: usize>(
+    time_limit: Option<Duration>,
+    time_control: Option<TimeControl>,
+    state: &State<N>,
+) -> Option<(Duration, Duration)> {
+    match (time_limit, time_control.map(|tc| tc.limits(state))) {
+        (Some(maximum_time), Some((soft_limit, hard_limit))) => {
+            Some((soft_limit.min(maximum_time), hard_limit.min(maximum_time)))
+        }
+        (Some(maximum_time), None) => Some((maximum_time, maximum_time)),
+        (None, Some(limits)) => Some(limits),
+        (None, None) => None,
+    }
+}
+
 fn spawn_interrupt_thread<const N: usize>(
     config: &AnalysisConfig<N>,
-    time_limit: Duration,
+    state: &State<N>,
+    limits: (Duration, Duration),
 ) -> InterruptHandle {
     let cancel = Arc::new(AtomicBool::new(false));
-    let start_time = Instant::now();
+    let started = Arc::new(OnceLock::new());
+    let final_limits = Arc::new(OnceLock::new());
+
+    if !config.pondering.load(Ordering::Relaxed) {
+        final_limits.set(limits).unwrap();
+        started.set(Instant::now()).unwrap();
+    }
 
     {
         let cancel = cancel.clone();
+        let started = started.clone();
+        let final_limits = final_limits.clone();
+        let pondering = config.pondering.clone();
+        let ponder_hit_time_control = config.ponder_hit_time_control.clone();
+        let maximum_time = config.time_limit;
+        let early_stop = config.early_stop;
+        let state = state.clone();
         let interrupted = config.interrupted.clone();
-        thread::spawn(move || loop {
-            if cancel.load(Ordering::Relaxed) {
-                break;
-            }
+        thread::spawn(move || {
+            // The time limit starts once pondering ends, with the clock as it is then.
+            let (start_time, (soft_limit, hard_limit)) = loop {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                if !pondering.load(Ordering::Acquire) {
+                    let limits = *final_limits.get_or_init(|| {
+                        let time_control = *ponder_hit_time_control.lock().unwrap();
+                        time_control
+                            .and_then(|tc| time_limits(maximum_time, Some(tc), &state))
+                            .unwrap_or(limits)
+                    });
+                    break (*started.get_or_init(Instant::now), limits);
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
 
-            let remaining_time = time_limit.saturating_sub(start_time.elapsed());
-            if !remaining_time.is_zero() {
-                // Check for cancels at least every 10th of a second.
-                let sleep_time = remaining_time.div_f64(2.0).min(Duration::from_millis(100));
-                thread::sleep(sleep_time);
-            } else {
-                info!("Time limit reached. Stopping.");
-                interrupted.store(true, Ordering::Relaxed);
-                break;
+            // Without early stopping, the search is only interrupted, so it runs to the
+            // soft limit.
+            let time_limit = if early_stop { hard_limit } else { soft_limit };
+
+            loop {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let remaining_time = time_limit.saturating_sub(start_time.elapsed());
+                if !remaining_time.is_zero() {
+                    // Check for cancels at least every 10th of a second.
+                    let sleep_time = remaining_time.div_f64(2.0).min(Duration::from_millis(100));
+                    thread::sleep(sleep_time);
+                } else {
+                    info!("Time limit reached. Stopping.");
+                    interrupted.store(true, Ordering::Relaxed);
+                    break;
+                }
             }
         });
     }
 
-    InterruptHandle(cancel)
+    InterruptHandle {
+        cancel,
+        started,
+        limits: final_limits,
+    }
 }
 
-struct InterruptHandle(Arc<AtomicBool>);
+struct InterruptHandle {
+    cancel: Arc<AtomicBool>,
+    /// When the time limit started, which is unset while pondering.
+    started: Arc<OnceLock<Instant>>,
+    /// The soft and hard limits, which are set before `started`, once the time control
+    /// after a ponder hit is known.
+    limits: Arc<OnceLock<(Duration, Duration)>>,
+}
 
 impl InterruptHandle {
     fn cancel(&self) {
-        self.0.store(true, Ordering::Relaxed);
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Clock for InterruptHandle {
+    fn elapsed(&self) -> Option<Duration> {
+        self.started.get().map(Instant::elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopped_search_still_returns_a_ply() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+
+        let config = AnalysisConfig::<5> {
+            interrupted: Arc::new(AtomicBool::new(true)),
+            table_size: 1,
+            ..Default::default()
+        };
+        let analysis = analyze(config, &state);
+
+        assert_eq!(analysis.depth, 0);
+        assert_eq!(analysis.principal_variation.len(), 1);
+        assert!(state.validate_ply(analysis.principal_variation[0]).is_ok());
+    }
+
+    #[test]
+    fn stopped_search_falls_back_to_earlier_ply() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let persistent_state = PersistentState::with_table_size(1);
+
+        let config = AnalysisConfig::<5> {
+            depth_limit: Some(3),
+            persistent_state: Some(&persistent_state),
+            ..Default::default()
+        };
+        let searched = analyze(config, &state);
+
+        let config = AnalysisConfig::<5> {
+            interrupted: Arc::new(AtomicBool::new(true)),
+            persistent_state: Some(&persistent_state),
+            ..Default::default()
+        };
+        let stopped = analyze(config, &state);
+
+        assert_eq!(
+            stopped.principal_variation[..],
+            searched.principal_variation[..1]
+        );
+    }
+
+    #[test]
+    fn repeated_search_keeps_principal_variation() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let persistent_state = PersistentState::with_table_size(1);
+
+        let search = || {
+            let config = AnalysisConfig::<5> {
+                depth_limit: Some(3),
+                persistent_state: Some(&persistent_state),
+                ..Default::default()
+            };
+            analyze(config, &state)
+        };
+
+        let first = search();
+        // Every iteration of the second search is answered by the transposition table.
+        let second = search();
+
+        assert_eq!(second.depth, first.depth);
+        assert_eq!(second.principal_variation, first.principal_variation);
+        assert_eq!(second.lines.len(), 1);
+    }
+
+    #[test]
+    fn multi_pv_finds_distinct_lines() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+
+        let config = AnalysisConfig::<5> {
+            depth_limit: Some(3),
+            multi_pv: 4,
+            table_size: 16,
+            ..Default::default()
+        };
+        let analysis = analyze(config, &state);
+
+        let lines = &analysis.lines;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].principal_variation, analysis.principal_variation);
+        assert_eq!(lines[0].evaluation, analysis.evaluation);
+
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line.principal_variation[0], line.ply);
+            assert!(lines[..i].iter().all(|earlier| earlier.ply != line.ply));
+        }
+        assert!(lines
+            .windows(2)
+            .all(|pair| pair[0].evaluation >= pair[1].evaluation));
+    }
+
+    #[test]
+    fn multi_pv_is_limited_to_legal_plies() {
+        let state: State<3> = "x3/x3/x3 1 1".parse().unwrap();
+
+        let config = AnalysisConfig::<3> {
+            depth_limit: Some(2),
+            multi_pv: 20,
+            table_size: 1,
+            ..Default::default()
+        };
+        let analysis = analyze(config, &state);
+
+        assert_eq!(analysis.lines.len(), 9);
+    }
+
+    #[test]
+    fn restricted_root_plies() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let plies: Vec<Ply<5>> = ["a1", "e5", "Sb2"]
+            .into_iter()
+            .map(|ply| ply.parse().unwrap())
+            .collect();
+
+        let config = AnalysisConfig::<5> {
+            depth_limit: Some(3),
+            multi_pv: 5,
+            included_plies: Some(plies.clone()),
+            excluded_plies: vec![plies[2]],
+            table_size: 16,
+            ..Default::default()
+        };
+        let analysis = analyze(config, &state);
+
+        assert_eq!(analysis.lines.len(), 2);
+        assert!(analysis
+            .lines
+            .iter()
+            .all(|line| plies[..2].contains(&line.ply)));
+
+        // The best ply of an unrestricted search isn't played once it's excluded.
+        let config = AnalysisConfig::<5> {
+            depth_limit: Some(3),
+            table_size: 16,
+            ..Default::default()
+        };
+        let best = analyze(config, &state).principal_variation[0];
+
+        let config = AnalysisConfig::<5> {
+            depth_limit: Some(3),
+            excluded_plies: vec![best],
+            table_size: 16,
+            ..Default::default()
+        };
+        assert_ne!(analyze(config, &state).principal_variation[0], best);
+    }
+
+    #[test]
+    fn node_limit_stops_every_thread() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let persistent_state = PersistentState::with_table_size(16);
+        let ply_history = PlyHistory::default();
+        let stats = AtomicStatistics::default();
+        let interrupted = AtomicBool::default();
+        let node_limit_reached = AtomicBool::default();
+        let threads = 3;
+
+        let search = SearchState {
+            start_ply: state.ply_count,
+            stats: &stats,
+            interrupted: &interrupted,
+            workers_terminated: &AtomicBool::default(),
+            node_limit: Some(20_000),
+            node_limit_reached: &node_limit_reached,
+            persistent_state: &persistent_state,
+            killer_moves: Default::default(),
+            ply_history: &ply_history,
+            previous_ply: None,
+            exact_eval: false,
+            evaluator: AnnModel::<5>::static_evaluator().as_ref(),
+            history: History::new(RepetitionRule::default(), &state),
+            included_root_plies: None,
+            excluded_root_plies: Vec::new(),
+            root_ply: None,
+            quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+        };
+
+        // Far too deep to finish, so the search only stops at the node limit.
+        search_root_window(
+            &search,
+            &state,
+            20,
+            threads,
+            Evaluation::MIN,
+            Evaluation::MAX,
+        );
+
+        // Each other thread may visit one more node before it sees the limit.
+        let visited = stats.visited.load(Ordering::Relaxed);
+        assert!(
+            (20_000..20_000 + threads as u64).contains(&visited),
+            "{visited}"
+        );
+        assert!(node_limit_reached.load(Ordering::Relaxed));
+        assert!(!interrupted.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn deterministic_search_is_repeatable() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+
+        let search = || {
+            let config = AnalysisConfig::<5> {
+                node_limit: Some(50_000),
+                time_limit: Some(Duration::from_millis(1)),
+                threads: 4,
+                multi_pv: 2,
+                deterministic: true,
+                table_size: 16,
+                ..Default::default()
+            };
+            analyze(config, &state)
+        };
+
+        let first = search();
+        let second = search();
+
+        assert!(first.depth > 1);
+        assert_eq!(first.depth, second.depth);
+        assert_eq!(first.principal_variation, second.principal_variation);
+        assert_eq!(first.evaluation, second.evaluation);
+        assert_eq!(first.stats.visited, second.stats.visited);
+        for (first, second) in first.lines.iter().zip(&second.lines) {
+            assert_eq!(first.principal_variation, second.principal_variation);
+        }
+    }
+
+    #[test]
+    fn aspiration_windows_are_widened() {
+        let state: State<5> = "x2,2,x2/x,2,1S,21C,x/12,x,1,12C,x/x,2,2S,1,x/x,1,x3 1 8"
+            .parse()
+            .unwrap();
+
+        let search = |aspiration_window: Option<f32>| {
+            // Without inexact pruning, the window only changes how much is searched, not
+            // the evaluation.
+            let config = AnalysisConfig::<5> {
+                depth_limit: Some(5),
+                exact_eval: true,
+                table_size: 16,
+                aspiration_window: aspiration_window.map(Into::into),
+                ..Default::default()
+            };
+            analyze(config, &state)
+        };
+
+        let full = search(None);
+        let narrow = search(Some(0.0001));
+
+        assert_eq!(full.stats.aspiration_fail_low, 0);
+        assert_eq!(full.stats.aspiration_fail_high, 0);
+        assert!(narrow.stats.aspiration_fail_low + narrow.stats.aspiration_fail_high > 0);
+
+        assert_eq!(narrow.depth, full.depth);
+        assert_eq!(narrow.evaluation, full.evaluation);
+    }
+
+    #[test]
+    fn time_limit_starts_after_ponder_hit() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let pondering = Arc::new(AtomicBool::new(true));
+
+        let config = AnalysisConfig::<5> {
+            time_limit: Some(Duration::from_millis(100)),
+            pondering: pondering.clone(),
+            table_size: 16,
+            ..Default::default()
+        };
+
+        let ponder_hit = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            pondering.store(false, Ordering::Relaxed);
+        });
+        let start_time = Instant::now();
+        let analysis = analyze(config, &state);
+        let elapsed = start_time.elapsed();
+        ponder_hit.join().unwrap();
+
+        assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+        assert!(!analysis.principal_variation.is_empty());
+    }
+
+    #[test]
+    fn ponder_hit_replaces_time_control() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let pondering = Arc::new(AtomicBool::new(true));
+        let ponder_hit_time_control = Arc::new(Mutex::new(None));
+
+        // This would leave two seconds for the move.
+        let config = AnalysisConfig::<5> {
+            time_control: Some("1:06".parse().unwrap()),
+            pondering: pondering.clone(),
+            ponder_hit_time_control: ponder_hit_time_control.clone(),
+            table_size: 16,
+            ..Default::default()
+        };
+
+        let ponder_hit = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            *ponder_hit_time_control.lock().unwrap() = Some("0:00.400".parse().unwrap());
+            pondering.store(false, Ordering::Release);
+        });
+        let start_time = Instant::now();
+        let analysis = analyze(config, &state);
+        let elapsed = start_time.elapsed();
+        ponder_hit.join().unwrap();
+
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1000), "{elapsed:?}");
+        assert!(!analysis.principal_variation.is_empty());
     }
 }
@@ -12,7 +12,10 @@ use analysis::{analyze, AnalysisConfig, PersistentState};
 use ann::linear_algebra::MatrixRowMajor;
 use ann::loss::{mse, mse_prime};
 use ann::shallow::ShallowAdam;
-use tak::{board_mask, generation, Color, PieceType, Ply, Resolution, State, Tps};
+use tak::{
+    board_mask, generation, Color, Game, History, PieceType, Ply, PtnReader, RepetitionRule,
+    Resolution, State, Tps,
+};
 
 const BATCH_SIZE: usize = 128;
 
@@ -45,6 +48,10 @@ struct Config {
     candidate_updates: usize,
     /// The search depth to use when building the positions the training samples are derived from.
     scaffold_search_depth: u32,
+    /// If set, take the positions the training samples are derived from out of this PTN archive
+    /// instead of building them with self-play. Games of other sizes are skipped.
+    #[serde(default)]
+    scaffold_archive: Option<String>,
     /// The number of consecutive samples to take from one starting position.
     samples_per_position: usize,
     /// The number of plies to play when calculating the temporal difference of the evaulations.
@@ -104,6 +111,11 @@ where
         return;
     }
 
+    let archive_positions = config
+        .scaffold_archive
+        .as_ref()
+        .map(|path| load_archive_positions::<N>(path));
+
     let mut best_training_state = load_best(&config).unwrap_or_else(|| {
         println!("Could not load best training state. Cloning current training state.");
 
@@ -150,7 +162,7 @@ where
             }
         }
 
-        do_update(&config, &mut training_state);
+        do_update(&config, &mut training_state, archive_positions.as_deref());
 
         if training_state.stage == Stage::Main
             && training_state.update % config.updates_per_checkpoint == 0
@@ -160,8 +172,11 @@ where
     }
 }
 
-fn do_update<const N: usize>(config: &Config, training_state: &mut TrainingState<N>)
-where
+fn do_update<const N: usize>(
+    config: &Config,
+    training_state: &mut TrainingState<N>,
+    archive_positions: Option<&[State<N>]>,
+) where
     TrainingState<N>: Train<N, State = State<N>>,
 {
     let mut rng = rand::thread_rng();
@@ -178,7 +193,10 @@ where
 
     let start_time = Instant::now();
 
-    let scaffolds = build_scaffold_positions(config, training_state);
+    let scaffolds = match archive_positions {
+        Some(positions) => positions.to_vec(),
+        None => build_scaffold_positions(config, training_state),
+    };
     let mut batch_samples = generate_batch_samples(config, training_state, scaffolds);
     batch_samples.shuffle(&mut rng);
 
@@ -306,15 +324,15 @@ where
     let evaluator = training_state.model_as_evaluator();
 
     let mut state = State::default();
+    let mut history = History::new(RepetitionRule::default(), &state);
 
-    while state.resolution().is_none() && state.ply_count < 300 {
+    while history.resolution(&state).is_none() {
         states.push(state.clone());
 
         // If the game has just started, or if a random number is below epsilon, make a random move.
         // Otherwise, use the principal variation from a search.
-        if state.ply_count < 2 || rng.gen::<f32>() < config.epsilon {
-            let ply = *generate_plies(&state).choose(&mut rng).unwrap();
-            state.execute_ply(ply).expect("error executing random ply");
+        let ply = if state.ply_count < 2 || rng.gen::<f32>() < config.epsilon {
+            *generate_plies(&state).choose(&mut rng).unwrap()
         } else {
             let config = AnalysisConfig::<N> {
                 depth_limit: Some(config.scaffold_search_depth),
@@ -323,25 +341,68 @@ where
                 persistent_state: Some(&persistent_state),
                 evaluator: Some(&*evaluator),
                 exact_eval: true,
+                history: Some(&history),
                 ..Default::default()
             };
 
             let analysis = analyze(config, &state);
 
-            state
-                .execute_ply(
-                    *analysis
-                        .principal_variation
-                        .first()
-                        .expect("no principal variation"),
-                )
-                .expect("error executing principal variation ply");
-        }
+            *analysis
+                .principal_variation
+                .first()
+                .expect("no principal variation")
+        };
+
+        state.execute_ply(ply).expect("error executing ply");
+        history.push(ply, &state);
     }
 
     states
 }
 
+/// Reads every unfinished position along the main line of each game in a PTN archive.
+fn load_archive_positions<const N: usize>(path: &str) -> Vec<State<N>> {
+    let reader = PtnReader::open(path).expect("could not open scaffold archive");
+
+    let mut positions = Vec::new();
+    let mut skipped = 0;
+
+    for game in reader {
+        let game = game.expect("could not read scaffold archive");
+        if game.get_size() != Some(N) {
+            skipped += 1;
+            continue;
+        }
+
+        let mut game = match Game::<N>::try_from(game) {
+            Ok(game) => game,
+            Err(err) => {
+                println!("Skipping invalid archive game: {err:?}");
+                skipped += 1;
+                continue;
+            }
+        };
+
+        game.to_start();
+        loop {
+            if game.state().resolution().is_none() {
+                positions.push(game.state().clone());
+            }
+            if !game.forward() {
+                break;
+            }
+        }
+    }
+
+    println!(
+        "Loaded {} scaffold positions from {path}, skipping {skipped} games.",
+        positions.len(),
+    );
+    assert!(!positions.is_empty(), "no positions in scaffold archive");
+
+    positions
+}
+
 fn generate_batch_samples<const N: usize>(
     config: &Config,
     training_state: &TrainingState<N>,
@@ -867,12 +928,14 @@ where
 
                     // Execute two random plies on a new board to start the game.
                     let mut state = State::default();
-                    let ply = *generate_plies(&state).choose(&mut rng).unwrap();
-                    state.execute_ply(ply).expect("error executing random ply");
-                    let ply = *generate_plies(&state).choose(&mut rng).unwrap();
-                    state.execute_ply(ply).expect("error executing random ply");
+                    let mut history = History::new(RepetitionRule::default(), &state);
+                    for _ in 0..2 {
+                        let ply = *generate_plies(&state).choose(&mut rng).unwrap();
+                        state.execute_ply(ply).expect("error executing random ply");
+                        history.push(ply, &state);
+                    }
 
-                    while state.resolution().is_none() && state.ply_count < 300 {
+                    while history.resolution(&state).is_none() {
                         let (player, persistent_state) = if state.ply_count % 2 == 0 {
                             (&**p1, &p1_persistent_state)
                         } else {
@@ -886,23 +949,24 @@ where
                             persistent_state: Some(persistent_state),
                             evaluator: Some(player),
                             exact_eval: true,
+                            history: Some(&history),
                             ..Default::default()
                         };
 
                         let analysis = analyze(config, &state);
 
+                        let ply = *analysis
+                            .principal_variation
+                            .first()
+                            .expect("no principal variation");
                         state
-                            .execute_ply(
-                                *analysis
-                                    .principal_variation
-                                    .first()
-                                    .expect("no principal variation"),
-                            )
+                            .execute_ply(ply)
                             .expect("error executing principal variation ply");
+                        history.push(ply, &state);
                     }
 
                     let mut results = results.lock().unwrap();
-                    match state.resolution() {
+                    match history.resolution(&state) {
                         Some(Resolution::Road(color)) | Some(Resolution::Flats { color, .. }) => {
                             match color {
                                 Color::White => {
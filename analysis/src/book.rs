@@ -0,0 +1,493 @@
+//! Opening books, which record how often each ply was played from a position and how
+//! those games turned out.
+//!
+//! Positions are keyed by Zobrist hash, so a book can only be used with the same Zobrist
+//! keys it was built with. Keys are deterministic with the `fixed-rng` feature of `tak`,
+//! and books record a check value so that a mismatch is detected when loading.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use fnv::FnvHashMap;
+use rand::Rng;
+
+use tak::{Color, PieceType, Ply, PtnError, PtnGame, State, Symmetry, ZobristHash};
+
+use crate::util::PackedPly;
+
+const MAGIC: &[u8; 4] = b"TKBK";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Default)]
+pub struct OpeningBook<const N: usize> {
+    /// Whether positions are stored in their canonical orientation, so that all
+    /// rotations and reflections of a position share statistics.
+    canonical: bool,
+    entries: FnvHashMap<ZobristHash, Vec<BookMove<N>>>,
+}
+
+/// The results of the games in which a ply was played, from the perspective of the
+/// player who played it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BookMove<const N: usize> {
+    pub ply: Ply<N>,
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl<const N: usize> BookMove<N> {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// The fraction of the available points won, counting draws as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+}
+
+/// How to choose between the plies in a book.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BookSelection {
+    /// The ply with the highest score, breaking ties by the number of games.
+    #[default]
+    Best,
+    /// A random ply, weighted by the points it has won.
+    Weighted,
+}
+
+#[derive(Debug)]
+pub enum BookError {
+    IoError(String),
+    InvalidFormat(String),
+    IncorrectSize(String),
+    /// The book was built with different Zobrist keys.
+    IncorrectKeys(String),
+}
+
+impl From<io::Error> for BookError {
+    fn from(error: io::Error) -> Self {
+        BookError::IoError(error.to_string())
+    }
+}
+
+impl<const N: usize> OpeningBook<N> {
+    pub fn new(canonical: bool) -> Self {
+        Self {
+            canonical,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// The number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The key for a state, along with the symmetries that bring it into the key's
+    /// orientation. There is more than one when the state is itself symmetric.
+    fn key(&self, state: &State<N>) -> (ZobristHash, Vec<Symmetry>) {
+        if self.canonical {
+            let hashes = Symmetry::ALL.map(|symmetry| (state.transformed_hash(symmetry), symmetry));
+            let key = hashes.iter().map(|&(hash, _)| hash).min().unwrap();
+            let symmetries = hashes
+                .into_iter()
+                .filter(|&(hash, _)| hash == key)
+                .map(|(_, symmetry)| symmetry)
+                .collect();
+            (key, symmetries)
+        } else {
+            (state.metadata.hash, vec![Symmetry::Identity])
+        }
+    }
+
+    /// Records one game in which `ply` was played from `state`, with the given winner.
+    pub fn record(&mut self, state: &State<N>, ply: Ply<N>, winner: Option<Color>) {
+        let (key, symmetries) = self.key(state);
+
+        // Equivalent plies from a symmetric state are stored as one of them.
+        let ply = symmetries
+            .into_iter()
+            .map(|symmetry| ply.transform(symmetry))
+            .min_by_key(|&ply| PackedPly::from(ply).to_bits())
+            .unwrap();
+
+        let moves = self.entries.entry(key).or_default();
+        let index = match moves.iter().position(|m| m.ply == ply) {
+            Some(index) => index,
+            None => {
+                moves.push(BookMove {
+                    ply,
+                    wins: 0,
+                    losses: 0,
+                    draws: 0,
+                });
+                moves.len() - 1
+            }
+        };
+
+        let book_move = &mut moves[index];
+        match winner {
+            Some(color) if color == state.to_move() => book_move.wins += 1,
+            Some(_) => book_move.losses += 1,
+            None => book_move.draws += 1,
+        }
+    }
+
+    /// Records the first `max_plies` plies of the main line of a finished game. Games
+    /// without a result are skipped, returning false.
+    pub fn add_game(&mut self, game: &PtnGame, max_plies: usize) -> Result<bool, PtnError> {
+        let winner = match game.result.as_deref() {
+            Some("R-0" | "F-0" | "1-0") => Some(Color::White),
+            Some("0-R" | "0-F" | "0-1") => Some(Color::Black),
+            Some("1/2-1/2") => None,
+            _ => return Ok(false),
+        };
+
+        let mut state = game.get_state_at_ply::<N>(0)?;
+
+        for ply in game.get_plies::<N>()?.into_iter().take(max_plies) {
+            self.record(&state, ply, winner);
+            state.execute_ply(ply)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Removes plies played in fewer than `min_games` games, and any positions left empty.
+    pub fn prune(&mut self, min_games: u32) {
+        self.entries.retain(|_, moves| {
+            moves.retain(|m| m.games() >= min_games);
+            !moves.is_empty()
+        });
+    }
+
+    /// The legal plies recorded for a state, in the state's orientation.
+    pub fn moves(&self, state: &State<N>) -> Vec<BookMove<N>> {
+        let (key, symmetries) = self.key(state);
+        let inverse = symmetries[0].inverse();
+
+        self.entries
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .map(|&m| BookMove {
+                ply: m.ply.transform(inverse),
+                ..m
+            })
+            // A hash collision could give plies from another position.
+            .filter(|m| state.validate_ply(m.ply).is_ok())
+            .collect()
+    }
+
+    /// Chooses a ply for a state, if the book covers it.
+    pub fn select(&self, state: &State<N>, selection: BookSelection) -> Option<Ply<N>> {
+        let moves = self.moves(state);
+
+        let best = || {
+            moves
+                .iter()
+                .max_by(|a, b| {
+                    a.score()
+                        .total_cmp(&b.score())
+                        .then(a.games().cmp(&b.games()))
+                })
+                .map(|m| m.ply)
+        };
+
+        match selection {
+            BookSelection::Best => best(),
+            BookSelection::Weighted => {
+                let weight = |m: &BookMove<N>| 2 * m.wins + m.draws;
+                let total: u32 = moves.iter().map(weight).sum();
+
+                if total == 0 {
+                    return best();
+                }
+
+                let mut choice = rand::thread_rng().gen_range(0..total);
+                moves
+                    .iter()
+                    .find(|&m| {
+                        let w = weight(m);
+                        if choice < w {
+                            true
+                        } else {
+                            choice -= w;
+                            false
+                        }
+                    })
+                    .map(|m| m.ply)
+            }
+        }
+    }
+
+    pub fn from_file(filename: impl AsRef<Path>) -> Result<Self, BookError> {
+        Self::read_from(BufReader::new(File::open(filename)?))
+    }
+
+    pub fn to_file(&self, filename: impl AsRef<Path>) -> Result<(), BookError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the book in a compact binary format. All numbers are little-endian.
+    ///
+    /// ```text
+    /// Header:   "TKBK", version: u8, size: u8, canonical: u8, key check: u64, positions: u32
+    /// Position: hash: u64, plies: u16, then for each ply:
+    ///           packed ply: [u8; 2], wins: u32, losses: u32, draws: u32
+    /// ```
+    pub fn write_to(&self, mut writer: impl Write) -> Result<(), BookError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, N as u8, self.canonical as u8])?;
+        writer.write_all(&key_check::<N>().to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+
+        // Sort the positions so the same book is always written the same way.
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_unstable_by_key(|(&hash, _)| hash);
+
+        for (hash, moves) in entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&(moves.len() as u16).to_le_bytes())?;
+
+            for m in moves {
+                writer.write_all(&PackedPly::from(m.ply).to_bits().to_ne_bytes())?;
+                writer.write_all(&m.wins.to_le_bytes())?;
+                writer.write_all(&m.losses.to_le_bytes())?;
+                writer.write_all(&m.draws.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<Self, BookError> {
+        let invalid = |message: &str| BookError::InvalidFormat(message.to_owned());
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not an opening book."));
+        }
+
+        let [version, size, canonical] = read_bytes(&mut reader)?;
+        if version != VERSION {
+            return Err(BookError::InvalidFormat(format!(
+                "Unsupported book version {version}."
+            )));
+        }
+        if size as usize != N {
+            return Err(BookError::IncorrectSize(format!(
+                "Book size is {size} but requested size is {N}."
+            )));
+        }
+
+        let check = u64::from_le_bytes(read_bytes(&mut reader)?);
+        if check != key_check::<N>() {
+            return Err(BookError::IncorrectKeys(
+                "Book was built with different Zobrist keys.".to_owned(),
+            ));
+        }
+
+        let mut book = Self::new(canonical != 0);
+
+        let positions = u32::from_le_bytes(read_bytes(&mut reader)?);
+        for _ in 0..positions {
+            let hash = u64::from_le_bytes(read_bytes(&mut reader)?);
+            let count = u16::from_le_bytes(read_bytes(&mut reader)?);
+
+            let moves = (0..count)
+                .map(|_| {
+                    let packed = PackedPly::from_bits(u16::from_ne_bytes(read_bytes(&mut reader)?));
+                    Ok(BookMove {
+                        ply: packed.try_into().map_err(|_| invalid("Invalid ply."))?,
+                        wins: u32::from_le_bytes(read_bytes(&mut reader)?),
+                        losses: u32::from_le_bytes(read_bytes(&mut reader)?),
+                        draws: u32::from_le_bytes(read_bytes(&mut reader)?),
+                    })
+                })
+                .collect::<Result<_, BookError>>()?;
+
+            book.entries.insert(hash, moves);
+        }
+
+        Ok(book)
+    }
+}
+
+fn read_bytes<const B: usize>(reader: &mut impl Read) -> Result<[u8; B], BookError> {
+    let mut bytes = [0; B];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// The hash of a fixed position, which differs between sets of Zobrist keys.
+fn key_check<const N: usize>() -> ZobristHash {
+    let mut state = State::<N>::default();
+    state.execute_ply_unchecked(Ply::Place {
+        x: 0,
+        y: 0,
+        piece_type: PieceType::Flatstone,
+    });
+    state.metadata.hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: [&str; 4] = [
+        "[Size \"5\"]\n1. a1 e5 2. c3 c4 3. d3 1-0",
+        "[Size \"5\"]\n1. a1 e5 2. c3 d3 3. c4 0-1",
+        "[Size \"5\"]\n1. e1 a5 2. c3 1/2-1/2",
+        "[Size \"5\"]\n1. a5 e1 2. b2",
+    ];
+
+    fn book(canonical: bool) -> OpeningBook<5> {
+        let mut book = OpeningBook::new(canonical);
+        for game in GAMES {
+            book.add_game(&game.parse().unwrap(), 4).unwrap();
+        }
+        book
+    }
+
+    fn ply(ptn: &str) -> Ply<5> {
+        ptn.parse().unwrap()
+    }
+
+    #[test]
+    fn statistics() {
+        let book = book(false);
+        let start = State::<5>::default();
+
+        let moves = book.moves(&start);
+        assert_eq!(moves.len(), 2);
+        let a1 = moves.iter().find(|m| m.ply == ply("a1")).unwrap();
+        assert_eq!((a1.wins, a1.losses, a1.draws), (1, 1, 0));
+
+        let mut state = start.clone();
+        for p in ["a1", "e5", "c3"] {
+            state.execute_ply(ply(p)).unwrap();
+        }
+        let moves = book.moves(&state);
+        let c4 = moves.iter().find(|m| m.ply == ply("c4")).unwrap();
+        assert_eq!((c4.wins, c4.losses), (0, 1));
+        let d3 = moves.iter().find(|m| m.ply == ply("d3")).unwrap();
+        assert_eq!((d3.wins, d3.losses), (1, 0));
+
+        assert_eq!(book.select(&state, BookSelection::Best), Some(ply("d3")));
+        assert_eq!(
+            book.select(&state, BookSelection::Weighted),
+            Some(ply("d3"))
+        );
+
+        // Only the first 4 plies of each game were recorded.
+        state.execute_ply(ply("d3")).unwrap();
+        assert!(book.moves(&state).is_empty());
+        assert_eq!(book.select(&state, BookSelection::Best), None);
+    }
+
+    #[test]
+    fn canonical_positions_share_statistics() {
+        let book = book(true);
+        let start = State::<5>::default();
+
+        // All corner openings are the same position.
+        let moves = book.moves(&start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].games(), 3);
+        assert!(["a1", "e1", "a5", "e5"].contains(&format!("{:?}", moves[0].ply).as_str()));
+
+        let mut state = start.clone();
+        state.execute_ply(ply("e5")).unwrap();
+        let moves = book.moves(&state);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].ply, ply("a1"));
+        assert_eq!(moves[0].games(), 3);
+    }
+
+    #[test]
+    fn prune() {
+        let mut book = book(false);
+        book.prune(2);
+
+        let moves = book.moves(&State::default());
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].ply, ply("a1"));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        for canonical in [false, true] {
+            let book = book(canonical);
+
+            let mut bytes = Vec::new();
+            book.write_to(&mut bytes).unwrap();
+            let read = OpeningBook::<5>::read_from(bytes.as_slice()).unwrap();
+
+            assert_eq!(read.is_canonical(), canonical);
+            assert_eq!(read.len(), book.len());
+            for (hash, moves) in &book.entries {
+                assert_eq!(&read.entries[hash], moves);
+            }
+
+            assert!(matches!(
+                OpeningBook::<6>::read_from(bytes.as_slice()),
+                Err(BookError::IncorrectSize(_))
+            ));
+            assert!(matches!(
+                OpeningBook::<5>::read_from(&bytes[..bytes.len() - 1]),
+                Err(BookError::IoError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn analyze_plays_book_moves() {
+        use crate::{analyze, AnalysisConfig};
+
+        let book = book(false);
+
+        let mut state = State::<5>::default();
+        for p in ["a1", "e5", "c3"] {
+            state.execute_ply(ply(p)).unwrap();
+        }
+
+        let analysis = analyze(
+            AnalysisConfig {
+                depth_limit: Some(2),
+                book: Some(&book),
+                ..Default::default()
+            },
+            &state,
+        );
+        assert_eq!(analysis.depth, 0);
+        assert_eq!(analysis.principal_variation, vec![ply("d3")]);
+
+        // Positions the book doesn't cover are searched as usual.
+        state.execute_ply(ply("d3")).unwrap();
+        let analysis = analyze(
+            AnalysisConfig {
+                depth_limit: Some(2),
+                book: Some(&book),
+                ..Default::default()
+            },
+            &state,
+        );
+        assert_eq!(analysis.depth, 2);
+    }
+}
@@ -1,16 +1,26 @@
-pub use self::analysis::{analyze, Analysis, AnalysisConfig, PersistentState};
+pub use self::analysis::{
+    analyze, Analysis, AnalysisConfig, Line, PersistentState, DEFAULT_ASPIRATION_WINDOW,
+    DEFAULT_QUIESCENCE_DEPTH,
+};
+pub use self::book::{BookError, BookMove, BookSelection, OpeningBook};
 pub use self::statistics::Statistics;
-pub use self::time::TimeControl;
-pub use self::transposition_table::{TranspositionTable, TranspositionTableEntry};
+pub use self::time::{GameClock, TimeControl};
+pub use self::tinue::{solve_tinue, TinueResult};
+pub use self::transposition_table::{
+    Bound, TranspositionTable, TranspositionTableEntry, TranspositionTableError,
+    TranspositionTableStats, DEFAULT_TABLE_SIZE,
+};
 pub use self::util::Sender;
 
 mod analysis;
+mod book;
 pub mod evaluation;
 mod move_order;
 mod ply_generator;
 mod search;
 mod statistics;
 mod time;
+mod tinue;
 mod transposition_table;
 mod util;
 
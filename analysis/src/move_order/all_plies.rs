@@ -1,5 +1,6 @@
 use tak::{board_mask, edge_masks, generation, Bitmap, Color, Direction, PieceType, Ply, State};
 
+use crate::move_order::PlyHistory;
 use crate::ply_generator::Continuation::*;
 use crate::ply_generator::Fallibility::*;
 use crate::ply_generator::GeneratedPly;
@@ -8,44 +9,44 @@ use Color::*;
 use Direction::*;
 use PieceType::*;
 
-pub(crate) struct AllPlies<'a, const N: usize> {
-    state: &'a State<N>,
-    plies: Option<Vec<ScoredPly<N>>>,
+pub(crate) struct AllPlies<const N: usize> {
+    plies: Vec<ScoredPly<N>>,
 }
 
-impl<'a, const N: usize> AllPlies<'a, N> {
-    pub fn new(state: &'a State<N>) -> Self {
-        Self { state, plies: None }
+impl<const N: usize> AllPlies<N> {
+    /// Generates and orders every ply in the state. `previous_ply` is the ply that led to
+    /// the state, used to look up its counter move.
+    pub fn new(
+        state: &State<N>,
+        ply_history: &PlyHistory<N>,
+        previous_ply: Option<Ply<N>>,
+    ) -> Self {
+        let mut plies = generate_all_plies(state);
+
+        // Don't really need a detailed ordering so early in the game.
+        if state.ply_count >= 6 {
+            score_plies(state, ply_history, previous_ply, &mut plies);
+            plies.sort_unstable_by_key(|scored_ply| scored_ply.score);
+        }
+
+        Self { plies }
     }
 }
 
-impl<'a, const N: usize> Iterator for AllPlies<'a, N> {
+impl<const N: usize> Iterator for AllPlies<N> {
     type Item = GeneratedPly<N>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(plies) = &mut self.plies {
-            plies.pop().map(|scored_ply| GeneratedPly {
-                ply: scored_ply.ply,
-                fallibility: Infallible,
-                continuation: Continue,
-            })
-        } else {
-            let mut plies = generate_all_plies(self.state);
-
-            // Don't really need a detailed ordering so early in the game.
-            if self.state.ply_count >= 6 {
-                score_plies(self.state, &mut plies);
-                plies.sort_unstable_by_key(|scored_ply| scored_ply.score);
-            }
-
-            self.plies = Some(plies);
-            self.next()
-        }
+        self.plies.pop().map(|scored_ply| GeneratedPly {
+            ply: scored_ply.ply,
+            fallibility: Infallible,
+            continuation: Continue,
+        })
     }
 }
 
 struct ScoredPly<const N: usize> {
-    score: u32,
+    score: u64,
     ply: Ply<N>,
 }
 
@@ -102,6 +103,7 @@ fn generate_all_plies<const N: usize>(state: &State<N>) -> Vec<ScoredPly<N>> {
 }
 
 /// Scores plies to achieve the following order (greatest to least):
+/// - The counter move to the previous ply
 /// - Capstone placements that create road threats
 /// - Flatstone placements that create road threats
 /// - Flatstone placements
@@ -116,7 +118,15 @@ fn generate_all_plies<const N: usize>(state: &State<N>) -> Vec<ScoredPly<N>> {
 /// - Spreads with flatstones that don't reveal opponent pieces
 /// - Spreads
 /// - Standing stone placements
-fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
+///
+/// Plies in the same group are ordered by their history scores.
+fn score_plies<const N: usize>(
+    state: &State<N>,
+    ply_history: &PlyHistory<N>,
+    previous_ply: Option<Ply<N>>,
+    plies: &mut [ScoredPly<N>],
+) {
+    const COUNTER_MOVE: u32 = 1 << 12;
     const ROAD_THREAT: u32 = 1 << 11;
     const ROAD_THREAT_CAPSTONE: u32 = 1 << 10;
     const FLATSTONE: u32 = 1 << 9;
@@ -183,10 +193,19 @@ fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
         !threats.is_empty()
     };
 
+    let color = state.to_move();
+    let counter_move = previous_ply.and_then(|ply| ply_history.counter_move(color, ply));
+
     for ScoredPly { score, ply } in plies {
+        let mut flags = 0;
+
+        if Some(*ply) == counter_move {
+            flags |= COUNTER_MOVE;
+        }
+
         match *ply {
             Ply::Place { x, y, piece_type } => {
-                *score |= match piece_type {
+                flags |= match piece_type {
                     Flatstone => FLATSTONE,
                     StandingStone => STANDING_STONE,
                     Capstone => CAPSTONE,
@@ -197,10 +216,10 @@ fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
                     let bit = Bitmap::from_coordinates(x as usize, y as usize);
 
                     if placement_threat(bit) {
-                        *score |= ROAD_THREAT;
+                        flags |= ROAD_THREAT;
 
                         if piece_type == Capstone {
-                            *score |= ROAD_THREAT_CAPSTONE;
+                            flags |= ROAD_THREAT_CAPSTONE;
                         }
                     }
                 }
@@ -211,10 +230,10 @@ fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
                     let neighbors = bit.dilate() ^ bit;
 
                     if !(neighbors & opponent_flatstones).is_empty() {
-                        *score |= BLOCKER_NEAR_OPPONENT_ROAD;
+                        flags |= BLOCKER_NEAR_OPPONENT_ROAD;
 
                         if piece_type == Capstone {
-                            *score |= BLOCKER_CAPSTONE_NEAR_OPPONENT_ROAD;
+                            flags |= BLOCKER_CAPSTONE_NEAR_OPPONENT_ROAD;
                         }
                     }
                 }
@@ -226,14 +245,14 @@ fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
                 drops,
                 ..
             } => {
-                *score |= SPREAD;
+                flags |= SPREAD;
 
                 let mut carry = drops.iter().sum::<u8>() as usize;
                 let stack = &state.board[x as usize][y as usize];
 
                 match stack.top_piece_type() {
-                    Some(Capstone) => *score |= SPREAD_CAPSTONE,
-                    Some(StandingStone) => *score |= SPREAD_STANDING_STONE,
+                    Some(Capstone) => flags |= SPREAD_CAPSTONE,
+                    Some(StandingStone) => flags |= SPREAD_STANDING_STONE,
                     _ => (),
                 }
 
@@ -295,13 +314,15 @@ fn score_plies<const N: usize>(state: &State<N>, plies: &mut [ScoredPly<N>]) {
                 }
 
                 if delta_fcd > 0 {
-                    *score |= SPREAD_INCREASES_FCD;
+                    flags |= SPREAD_INCREASES_FCD;
                 }
 
                 if !reveals_opponent {
-                    *score |= SPREAD_DOESNT_REVEAL_OPPONENT;
+                    flags |= SPREAD_DOESNT_REVEAL_OPPONENT;
                 }
             }
         }
+
+        *score = (flags as u64) << 32 | ply_history.score(color, *ply) as u64;
     }
 }
@@ -1,9 +1,11 @@
 pub(crate) use self::all_plies::AllPlies;
 pub(crate) use self::killer_moves::Killers;
 pub(crate) use self::placement_wins::PlacementWins;
+pub(crate) use self::ply_history::PlyHistory;
 pub(crate) use self::transposition_table::TtPly;
 
 mod all_plies;
 mod killer_moves;
 mod placement_wins;
+mod ply_history;
 mod transposition_table;
@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use tak::{Color, PieceType, Ply};
+
+use crate::util::PackedPly;
+
+/// The kinds of plies told apart by the tables: a placement of each piece type, and a
+/// spread in each direction.
+const SHAPES: usize = 7;
+
+/// Once a history score passes this, every score is halved, so that the table keeps
+/// favoring recent cutoffs and never overflows.
+const HISTORY_LIMIT: u32 = 1 << 20;
+
+/// Move ordering learned over the course of an analysis, shared by every thread:
+/// - The history table scores each shape of ply on each square by how often it has caused
+///   a beta cutoff, weighted towards cutoffs with more depth remaining.
+/// - The counter-move table keeps the reply that most recently refuted each shape of ply
+///   on each square.
+pub(crate) struct PlyHistory<const N: usize> {
+    history: Vec<AtomicU32>,
+    /// Packed plies, or zero for none, which isn't a valid packed ply.
+    counter_moves: Vec<AtomicU16>,
+}
+
+impl<const N: usize> Default for PlyHistory<N> {
+    fn default() -> Self {
+        let len = 2 * SHAPES * N * N;
+        Self {
+            history: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            counter_moves: (0..len).map(|_| AtomicU16::new(0)).collect(),
+        }
+    }
+}
+
+impl<const N: usize> PlyHistory<N> {
+    /// The history score of a ply by the given player.
+    pub fn score(&self, color: Color, ply: Ply<N>) -> u32 {
+        self.history[index(color, ply)].load(Ordering::Relaxed)
+    }
+
+    /// The ply that last refuted `previous_ply`, where the given player is replying.
+    pub fn counter_move(&self, color: Color, previous_ply: Ply<N>) -> Option<Ply<N>> {
+        let bits = self.counter_moves[index(color, previous_ply)].load(Ordering::Relaxed);
+        if bits == 0 {
+            return None;
+        }
+
+        PackedPly::from_bits(bits).try_into().ok()
+    }
+
+    /// Records a beta cutoff caused by a ply by the given player, in reply to the previous
+    /// ply if there was one.
+    pub fn cutoff(
+        &self,
+        color: Color,
+        ply: Ply<N>,
+        previous_ply: Option<Ply<N>>,
+        remaining_depth: usize,
+    ) {
+        let bonus = (remaining_depth * remaining_depth) as u32;
+        let score = self.history[index(color, ply)].fetch_add(bonus, Ordering::Relaxed) + bonus;
+
+        if score > HISTORY_LIMIT {
+            for entry in &self.history {
+                // Other threads may update an entry in between, which only loses a bonus.
+                entry.store(entry.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(previous_ply) = previous_ply {
+            self.counter_moves[index(color, previous_ply)]
+                .store(PackedPly::from(ply).to_bits(), Ordering::Relaxed);
+        }
+    }
+}
+
+fn index<const N: usize>(color: Color, ply: Ply<N>) -> usize {
+    let (shape, x, y) = match ply {
+        Ply::Place { x, y, piece_type } => {
+            let shape = match piece_type {
+                PieceType::Flatstone => 0,
+                PieceType::StandingStone => 1,
+                PieceType::Capstone => 2,
+            };
+            (shape, x, y)
+        }
+        Ply::Spread {
+            x, y, direction, ..
+        } => (3 + direction as usize, x, y),
+    };
+
+    let color = match color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+
+    ((color * SHAPES + shape) * N + x as usize) * N + y as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ply(ptn: &str) -> Ply<5> {
+        ptn.parse().unwrap()
+    }
+
+    #[test]
+    fn history_scores() {
+        let history = PlyHistory::<5>::default();
+
+        history.cutoff(Color::White, ply("c3"), None, 3);
+        history.cutoff(Color::White, ply("c3"), None, 2);
+
+        assert_eq!(history.score(Color::White, ply("c3")), 13);
+        assert_eq!(history.score(Color::Black, ply("c3")), 0);
+        assert_eq!(history.score(Color::White, ply("Sc3")), 0);
+
+        // Spreads are told apart by direction, but not by their drops.
+        history.cutoff(Color::Black, ply("3b2>12"), None, 1);
+        assert_eq!(history.score(Color::Black, ply("2b2>")), 1);
+        assert_eq!(history.score(Color::Black, ply("b2<")), 0);
+    }
+
+    #[test]
+    fn history_scores_are_halved_at_the_limit() {
+        let history = PlyHistory::<5>::default();
+
+        history.cutoff(Color::White, ply("a1"), None, 10);
+        history.cutoff(Color::White, ply("e5"), None, 1 << 10 | 1);
+
+        assert_eq!(history.score(Color::White, ply("a1")), 50);
+        assert!(history.score(Color::White, ply("e5")) <= HISTORY_LIMIT);
+    }
+
+    #[test]
+    fn counter_moves() {
+        let history = PlyHistory::<5>::default();
+        assert_eq!(history.counter_move(Color::Black, ply("c3")), None);
+
+        history.cutoff(Color::Black, ply("c4"), Some(ply("c3")), 1);
+        history.cutoff(Color::Black, ply("2d2<11"), Some(ply("c3")), 1);
+
+        assert_eq!(
+            history.counter_move(Color::Black, ply("c3")),
+            Some(ply("2d2<11"))
+        );
+        assert_eq!(history.counter_move(Color::White, ply("c3")), None);
+    }
+}
@@ -1,11 +1,10 @@
 use std::collections::HashSet;
-use std::iter::Chain;
 
 use fnv::FnvBuildHasher;
 
 use tak::{Ply, State};
 
-use crate::move_order::{AllPlies, Killers, PlacementWins, TtPly};
+use crate::move_order::{AllPlies, Killers, PlacementWins, PlyHistory, TtPly};
 use crate::search::KillerMoves;
 
 #[derive(PartialEq)]
@@ -21,57 +20,79 @@ pub(crate) enum Continuation {
 }
 use Continuation::*;
 
+/// Generates plies in the order they should be searched. The state is passed to each call
+/// rather than held, so that plies can be made and undone on it in between, but it must be
+/// back to the same position every time.
 pub(crate) struct PlyGenerator<'a, const N: usize> {
     used_plies: HashSet<Ply<N>, FnvBuildHasher>,
-    plies: PlyIterator<'a, N>,
+    /// Whether a placement that completes a road has been looked for yet.
+    placement_wins_checked: bool,
+    tt_ply: TtPly<N>,
+    killers: Killers<N>,
+    /// Every other ply, only generated once the earlier ones have been tried.
+    all_plies: Option<AllPlies<N>>,
+    ply_history: &'a PlyHistory<N>,
+    previous_ply: Option<Ply<N>>,
     continuation: Continuation,
 }
 
 impl<'a, const N: usize> PlyGenerator<'a, N> {
     pub(crate) fn new(
-        state: &'a State<N>,
         tt_ply: Option<Ply<N>>,
         killer_moves: &KillerMoves<N>,
+        ply_history: &'a PlyHistory<N>,
+        previous_ply: Option<Ply<N>>,
     ) -> Self {
         Self {
             used_plies: HashSet::default(),
-            plies: PlacementWins::new(state)
-                .chain(TtPly::new(tt_ply))
-                .chain(Killers::new(killer_moves))
-                .chain(AllPlies::new(state)),
+            placement_wins_checked: false,
+            tt_ply: TtPly::new(tt_ply),
+            killers: Killers::new(killer_moves),
+            all_plies: None,
+            ply_history,
+            previous_ply,
             continuation: Continue,
         }
     }
-}
-
-pub(crate) struct GeneratedPly<const N: usize> {
-    pub ply: Ply<N>,
-    pub fallibility: Fallibility,
-    pub continuation: Continuation,
-}
-
-type PlyIterator<'a, const N: usize> =
-    Chain<Chain<Chain<PlacementWins<'a, N>, TtPly<N>>, Killers<N>>, AllPlies<'a, N>>;
 
-impl<'a, const N: usize> Iterator for PlyGenerator<'a, N> {
-    type Item = (Fallibility, Ply<N>);
-
-    fn next(&mut self) -> Option<Self::Item> {
+    pub(crate) fn next(&mut self, state: &State<N>) -> Option<(Fallibility, Ply<N>)> {
         if self.continuation == Stop {
             return None;
         }
 
-        'generate: loop {
-            if let Some(next_ply) = self.plies.next() {
-                if !self.used_plies.insert(next_ply.ply) {
-                    // We've already seen this ply, so get another.
-                    continue 'generate;
-                }
+        loop {
+            let next_ply = self.next_generated(state)?;
+            if self.used_plies.insert(next_ply.ply) {
                 self.continuation = next_ply.continuation;
                 return Some((next_ply.fallibility, next_ply.ply));
-            } else {
-                return None;
+            }
+            // We've already seen this ply, so get another.
+        }
+    }
+
+    fn next_generated(&mut self, state: &State<N>) -> Option<GeneratedPly<N>> {
+        if !self.placement_wins_checked {
+            self.placement_wins_checked = true;
+            if let Some(placement_win) = PlacementWins::new(state).next() {
+                return Some(placement_win);
             }
         }
+
+        self.tt_ply
+            .next()
+            .or_else(|| self.killers.next())
+            .or_else(|| {
+                self.all_plies
+                    .get_or_insert_with(|| {
+                        AllPlies::new(state, self.ply_history, self.previous_ply)
+                    })
+                    .next()
+            })
     }
 }
+
+pub(crate) struct GeneratedPly<const N: usize> {
+    pub ply: Ply<N>,
+    pub fallibility: Fallibility,
+    pub continuation: Continuation,
+}
@@ -6,10 +6,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::error;
 use tracing::{instrument, trace, trace_span, warn};
 
-use tak::{zobrist_advance_move, Ply, State};
+use tak::{generation, zobrist_advance_move, History, Ply, RepetitionRule, Resolution, State};
 
 use crate::analysis::PersistentState;
 use crate::evaluation::{Evaluation, Evaluator};
+use crate::move_order::PlyHistory;
 use crate::ply_generator::{Fallibility, PlyGenerator};
 use crate::statistics::AtomicStatistics;
 use crate::transposition_table::{Bound, TranspositionTableEntry};
@@ -21,10 +22,55 @@ pub(crate) struct SearchState<'a, const N: usize> {
     pub stats: &'a AtomicStatistics,
     pub interrupted: &'a AtomicBool,
     pub workers_terminated: &'a AtomicBool,
+    /// The number of nodes this search may visit before it's stopped.
+    pub node_limit: Option<u64>,
+    /// Set once the node limit is reached. This stops the search like `interrupted`, which
+    /// belongs to the caller and is left alone.
+    pub node_limit_reached: &'a AtomicBool,
     pub persistent_state: &'a PersistentState<N>,
     pub killer_moves: DepthKillerMoves<N>,
+    /// History and counter-move tables, shared by every thread and iteration.
+    pub ply_history: &'a PlyHistory<N>,
+    /// The ply that led to the current node, if it wasn't a null move.
+    pub previous_ply: Option<Ply<N>>,
     pub exact_eval: bool,
     pub evaluator: &'a dyn Evaluator<N>,
+    /// The positions leading to the current node.
+    pub history: History,
+    /// If given, only these plies are searched at the root.
+    pub included_root_plies: Option<Vec<Ply<N>>>,
+    /// Plies that aren't searched at the root, such as the best plies of earlier lines.
+    pub excluded_root_plies: Vec<Ply<N>>,
+    /// The best ply found at the root, once the search is finished.
+    pub root_ply: Option<Ply<N>>,
+    /// How many plies past the search depth to keep responding to tak before evaluating
+    /// a leaf. If zero, leaves are evaluated without looking for road threats.
+    pub quiescence_depth: usize,
+}
+
+impl<'a, const N: usize> SearchState<'a, N> {
+    /// Whether the search was interrupted or reached its node limit.
+    pub fn is_stopped(&self) -> bool {
+        self.interrupted.load(Ordering::Relaxed) || self.node_limit_reached.load(Ordering::Relaxed)
+    }
+
+    /// Counts a visited node, stopping the search once the node limit is reached.
+    fn visit(&self) {
+        let visited = self.stats.visited.fetch_add(1, Ordering::Relaxed) + 1;
+        if self
+            .node_limit
+            .is_some_and(|node_limit| visited >= node_limit)
+        {
+            self.node_limit_reached.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn is_root_ply_allowed(&self, ply: Ply<N>) -> bool {
+        self.included_root_plies
+            .as_ref()
+            .is_none_or(|included| included.contains(&ply))
+            && !self.excluded_root_plies.contains(&ply)
+    }
 }
 
 #[derive(Clone, Default)]
@@ -85,10 +131,12 @@ impl Neg for BranchResult {
 
 /// Computes the minimax function with common enhancements: alpha-beta pruning,
 /// principal variation search, transposition table, null move pruning, killer moves.
+/// Repeated positions are scored as draws. Plies are made and undone on the state, which
+/// is left as it was on return.
 #[instrument(level = "trace", skip_all, fields(rd = remaining_depth, %alpha, %beta, pv_node = alpha.next_up() != beta))]
 pub(crate) fn minimax<const N: usize>(
     search: &mut SearchState<'_, N>,
-    state: &State<N>,
+    state: &mut State<N>,
     remaining_depth: usize,
     mut alpha: Evaluation,
     beta: Evaluation,
@@ -96,7 +144,33 @@ pub(crate) fn minimax<const N: usize>(
 ) -> BranchResult {
     let search_depth = (state.ply_count - search.start_ply) as usize;
 
-    search.stats.visited.fetch_add(1, Ordering::Relaxed);
+    // Once the search has been stopped, unwind as quickly as possible. Nothing is stored
+    // in the transposition table on the way out, and the result is discarded.
+    if search.is_stopped() || search.workers_terminated.load(Ordering::Relaxed) {
+        return BranchResult {
+            depth: 0,
+            evaluation: alpha,
+        };
+    }
+
+    search.visit();
+
+    // Check for repetitions ====================
+
+    // A position repeated within the search is scored as a draw, since either player can
+    // keep repeating it. Earlier repetitions only count once the rule is satisfied. This
+    // happens before the transposition table is consulted, because its entries don't know
+    // how a position was reached.
+    if search_depth > 0 && is_repetition(&search.history, search_depth) {
+        trace!("Repetition");
+        search.stats.terminal.fetch_add(1, Ordering::Relaxed);
+        search.stats.evaluated.fetch_add(1, Ordering::Relaxed);
+
+        return BranchResult {
+            depth: 0,
+            evaluation: search.evaluator.evaluate(state, Some(Resolution::Draw)),
+        };
+    }
 
     let resolution = state.resolution();
 
@@ -104,6 +178,17 @@ pub(crate) fn minimax<const N: usize>(
         search.stats.terminal.fetch_add(1, Ordering::Relaxed);
     }
 
+    if remaining_depth == 0 && resolution.is_none() && search.quiescence_depth > 0 {
+        return quiescence(
+            search,
+            state,
+            search_depth,
+            search.quiescence_depth,
+            alpha,
+            beta,
+        );
+    }
+
     if remaining_depth == 0 || resolution.is_some() {
         let evaluation = search.evaluator.evaluate(state, resolution);
 
@@ -116,6 +201,11 @@ pub(crate) fn minimax<const N: usize>(
         };
     }
 
+    // A root with restricted plies can't use or replace the transposition table entry for
+    // the position, since the best ply might not be allowed.
+    let restricted_root = search_depth == 0
+        && (search.included_root_plies.is_some() || !search.excluded_root_plies.is_empty());
+
     let pv_node = alpha.next_up() != beta;
     if !pv_node {
         search.stats.scouted.fetch_add(1, Ordering::Relaxed);
@@ -126,7 +216,7 @@ pub(crate) fn minimax<const N: usize>(
     let tt_entry = search
         .persistent_state
         .transposition_table
-        .get(state.metadata.hash);
+        .probe(state.metadata.hash);
 
     if let Some(entry) = tt_entry {
         search.stats.tt_hits.fetch_add(1, Ordering::Relaxed);
@@ -145,9 +235,13 @@ pub(crate) fn minimax<const N: usize>(
             error!(?entry, ?state, error = ?err, "Invalid tt ply");
         }
 
-        if is_save || is_terminal {
+        if (is_save || is_terminal) && !restricted_root {
             search.stats.tt_saves.fetch_add(1, Ordering::Relaxed);
 
+            if search_depth == 0 {
+                search.root_ply = Some(entry.ply());
+            }
+
             return BranchResult {
                 depth: entry.depth(),
                 evaluation: match entry.bound() {
@@ -163,21 +257,27 @@ pub(crate) fn minimax<const N: usize>(
 
     if !search.exact_eval && null_move_allowed && remaining_depth >= 3 {
         let _null_move_span = trace_span!("null_move").entered();
-        let mut state = state.clone();
 
         // Apply a null move.
         state.ply_count += 1;
         state.metadata.hash ^= zobrist_advance_move::<N>();
+        search.history.push_irreversible(state.metadata.hash);
+        let previous_ply = search.previous_ply.take();
 
         let BranchResult { depth, evaluation } = -minimax(
             search,
-            &state,
+            state,
             remaining_depth - 3,
             -beta,
             (-beta).next_up(),
             false,
         );
 
+        search.history.pop();
+        search.previous_ply = previous_ply;
+        state.ply_count -= 1;
+        state.metadata.hash ^= zobrist_advance_move::<N>();
+
         if evaluation >= beta {
             trace!("Null move cutoff");
             search.stats.null_cutoff.fetch_add(1, Ordering::Relaxed);
@@ -191,12 +291,16 @@ pub(crate) fn minimax<const N: usize>(
 
     // Ply search ===============================
 
-    let ply_generator = PlyGenerator::new(
-        state,
+    let mut ply_generator = PlyGenerator::new(
         tt_entry.map(|entry| entry.ply()),
         search.killer_moves.depth(search_depth),
+        search.ply_history,
+        search.previous_ply,
     );
 
+    // Cutoffs are recorded for the player making the ply, not the one to move after it.
+    let color = state.to_move();
+
     let mut best = BranchResult {
         depth: 0,
         evaluation: Evaluation::MIN,
@@ -206,26 +310,32 @@ pub(crate) fn minimax<const N: usize>(
     let mut moves_searched = 0;
     let mut raised_alpha = false;
 
-    for (i, (fallibility, ply)) in ply_generator.enumerate() {
+    for i in 0.. {
+        let Some((fallibility, ply)) = ply_generator.next(state) else {
+            break;
+        };
+
+        if search_depth == 0 && !search.is_root_ply_allowed(ply) {
+            continue;
+        }
+
         let _move_span = trace_span!("move", ?ply).entered();
-        let mut state = state.clone();
 
         use Fallibility::*;
-        match fallibility {
-            Fallible => {
-                if state.execute_ply(ply).is_err() {
-                    continue;
-                }
-            }
-            Infallible => state.execute_ply_unchecked(ply),
+        if fallibility == Fallible && state.validate_ply(ply).is_err() {
+            continue;
         }
+        let undo_info = state.execute_ply_unchecked(ply);
 
         moves_searched += 1;
 
+        search.history.push(ply, state);
+        let previous_ply = search.previous_ply.replace(ply);
+
         let next = if moves_searched == 1 {
             let _leftmost_span = trace_span!("leftmost").entered();
             // On the first iteration, perform a full-window search.
-            -minimax(search, &state, remaining_depth - 1, -beta, -alpha, true)
+            -minimax(search, state, remaining_depth - 1, -beta, -alpha, true)
         } else {
             // Afterwards, perform a null-window search, expecting to fail low (counting
             // on our move ordering to have already led us to the "best" move).
@@ -246,7 +356,7 @@ pub(crate) fn minimax<const N: usize>(
 
             let mut scout = -minimax(
                 search,
-                &state,
+                state,
                 remaining_depth - reduction,
                 (-alpha).next_down(),
                 -alpha,
@@ -259,7 +369,7 @@ pub(crate) fn minimax<const N: usize>(
                 let _rescouted_span = trace_span!("re-scouted").entered();
                 scout = -minimax(
                     search,
-                    &state,
+                    state,
                     remaining_depth - 1,
                     (-alpha).next_down(),
                     -alpha,
@@ -272,12 +382,16 @@ pub(crate) fn minimax<const N: usize>(
                 trace!(%alpha, %beta, %scout.evaluation, "Re-searching");
                 let _researched_span = trace_span!("re-searched").entered();
                 search.stats.re_searched.fetch_add(1, Ordering::Relaxed);
-                -minimax(search, &state, remaining_depth - 1, -beta, -alpha, true)
+                -minimax(search, state, remaining_depth - 1, -beta, -alpha, true)
             } else {
                 scout
             }
         };
 
+        search.history.pop();
+        search.previous_ply = previous_ply;
+        state.undo_ply(ply, undo_info);
+
         if next.evaluation > best.evaluation {
             best = next;
             best.depth += 1;
@@ -294,14 +408,15 @@ pub(crate) fn minimax<const N: usize>(
                 search.stats.beta_cutoff.fetch_add(1, Ordering::Relaxed);
 
                 search.killer_moves.depth(search_depth).push(ply);
+                search
+                    .ply_history
+                    .cutoff(color, ply, search.previous_ply, remaining_depth);
 
                 break;
             }
         }
 
-        if search.interrupted.load(Ordering::Relaxed)
-            || search.workers_terminated.load(Ordering::Relaxed)
-        {
+        if search.is_stopped() || search.workers_terminated.load(Ordering::Relaxed) {
             return BranchResult {
                 depth: best.depth,
                 evaluation: alpha,
@@ -325,11 +440,22 @@ pub(crate) fn minimax<const N: usize>(
         search.stats.all_ply_order[i.min(5)].fetch_add(1, Ordering::Relaxed);
     }
 
+    if search_depth == 0 {
+        search.root_ply = Some(best_ply);
+    }
+
+    if restricted_root {
+        return BranchResult {
+            depth: best.depth,
+            evaluation: alpha,
+        };
+    }
+
     // Store in transposition table =============
 
     let inserted = search.persistent_state.transposition_table.insert(
         state.metadata.hash,
-        TranspositionTableEntry::new(best_ply, alpha, bound, best.depth, state.ply_count),
+        TranspositionTableEntry::new(best_ply, alpha, bound, best.depth),
     );
 
     if inserted {
@@ -343,3 +469,355 @@ pub(crate) fn minimax<const N: usize>(
         evaluation: alpha,
     }
 }
+
+/// Resolves forcing sequences at a leaf before evaluating it. A player with a road in one
+/// wins, and a player in tak searches every response until the remaining depth runs out.
+/// Anything else is evaluated as it is. Like `minimax`, the state is left as it was.
+fn quiescence<const N: usize>(
+    search: &mut SearchState<'_, N>,
+    state: &mut State<N>,
+    search_depth: usize,
+    remaining_depth: usize,
+    mut alpha: Evaluation,
+    beta: Evaluation,
+) -> BranchResult {
+    let color = state.to_move();
+
+    if state.is_in_tak(color.other()) {
+        trace!("Road in one");
+        search.stats.terminal.fetch_add(1, Ordering::Relaxed);
+        search.stats.evaluated.fetch_add(1, Ordering::Relaxed);
+
+        // Evaluate the win as if the road had been made with the next ply.
+        state.ply_count += 1;
+        let evaluation = -search
+            .evaluator
+            .evaluate(state, Some(Resolution::Road(color)));
+        state.ply_count -= 1;
+
+        return BranchResult {
+            depth: 0,
+            evaluation,
+        };
+    }
+
+    if remaining_depth == 0 || !state.is_in_tak(color) {
+        let evaluation = search.evaluator.evaluate(state, None);
+
+        trace!(%evaluation, "Leaf");
+        search.stats.evaluated.fetch_add(1, Ordering::Relaxed);
+
+        return BranchResult {
+            depth: 0,
+            evaluation,
+        };
+    }
+
+    let _tak_span = trace_span!("tak").entered();
+    search.stats.extended.fetch_add(1, Ordering::Relaxed);
+
+    // Only a few plies stop a road, so every ply has to be tried.
+    let mut best = Evaluation::MIN;
+
+    for ply in generation::legal_plies(state) {
+        // As in `minimax`, a stopped search unwinds and its result is discarded.
+        if search.is_stopped() || search.workers_terminated.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let undo_info = state.execute_ply_unchecked(ply);
+        search.history.push(ply, state);
+
+        search.visit();
+
+        // Repetitions are checked as in `minimax`, so perpetual tak is scored as a draw.
+        let next = if is_repetition(&search.history, search_depth + 1) {
+            trace!("Repetition");
+            search.stats.terminal.fetch_add(1, Ordering::Relaxed);
+            search.stats.evaluated.fetch_add(1, Ordering::Relaxed);
+            -search.evaluator.evaluate(state, Some(Resolution::Draw))
+        } else if let Some(resolution) = state.resolution() {
+            search.stats.terminal.fetch_add(1, Ordering::Relaxed);
+            search.stats.evaluated.fetch_add(1, Ordering::Relaxed);
+            -search.evaluator.evaluate(state, Some(resolution))
+        } else {
+            -quiescence(
+                search,
+                state,
+                search_depth + 1,
+                remaining_depth - 1,
+                -beta,
+                -alpha,
+            )
+            .evaluation
+        };
+
+        search.history.pop();
+        state.undo_ply(ply, undo_info);
+
+        if next > best {
+            best = next;
+        }
+
+        if next > alpha {
+            alpha = next;
+
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+
+    BranchResult {
+        depth: 0,
+        evaluation: best,
+    }
+}
+
+fn is_repetition(history: &History, search_depth: usize) -> bool {
+    match history.rule {
+        RepetitionRule::Disabled => false,
+        RepetitionRule::Count(_) => {
+            history.is_draw()
+                || history
+                    .plies_since_repetition()
+                    .is_some_and(|plies| plies <= search_depth)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{analyze, Analysis, AnalysisConfig, DEFAULT_QUIESCENCE_DEPTH};
+    use crate::evaluation::{AnnEvaluator, AnnModel};
+    use tak::Color;
+
+    #[test]
+    fn repetitions() {
+        let mut state: State<5> = "x5/x5/x5/x5/x5 1 1".parse().unwrap();
+        let mut history = History::new(RepetitionRule::default(), &state);
+
+        for ply in ["a1", "e5", "a2", "e4", "a2-", "e4+", "a1+", "e5-"] {
+            let ply: Ply<5> = ply.parse().unwrap();
+            state.execute_ply(ply).unwrap();
+            history.push(ply, &state);
+        }
+
+        // Repeated within the search.
+        assert!(is_repetition(&history, 4));
+        // Repeated only before the search began, and not often enough to be a draw.
+        assert!(!is_repetition(&history, 3));
+
+        history.rule = RepetitionRule::Count(2);
+        assert!(is_repetition(&history, 3));
+
+        history.rule = RepetitionRule::Disabled;
+        assert!(!is_repetition(&history, 4));
+    }
+
+    static NEVER_SET: AtomicBool = AtomicBool::new(false);
+
+    fn search_state<'a>(
+        state: &State<5>,
+        persistent_state: &'a PersistentState<5>,
+        ply_history: &'a PlyHistory<5>,
+        stats: &'a AtomicStatistics,
+        interrupted: &'a AtomicBool,
+    ) -> SearchState<'a, 5> {
+        SearchState {
+            start_ply: state.ply_count,
+            stats,
+            interrupted,
+            workers_terminated: &NEVER_SET,
+            node_limit: None,
+            node_limit_reached: &NEVER_SET,
+            persistent_state,
+            killer_moves: Default::default(),
+            ply_history,
+            previous_ply: None,
+            exact_eval: true,
+            evaluator: AnnModel::<5>::static_evaluator().as_ref(),
+            history: History::new(RepetitionRule::default(), state),
+            included_root_plies: None,
+            excluded_root_plies: Vec::new(),
+            root_ply: None,
+            quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+        }
+    }
+
+    #[test]
+    fn cutoffs_are_recorded_for_the_moving_player() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let persistent_state = PersistentState::with_table_size(1);
+        let ply_history = PlyHistory::default();
+        let stats = AtomicStatistics::default();
+        let interrupted = AtomicBool::default();
+        let mut search = search_state(
+            &state,
+            &persistent_state,
+            &ply_history,
+            &stats,
+            &interrupted,
+        );
+
+        // Every ply beats a window this low, so Black cuts off on the first ply at the root.
+        minimax(
+            &mut search,
+            &mut state.clone(),
+            1,
+            Evaluation::MIN,
+            Evaluation::MIN.next_up(),
+            false,
+        );
+
+        let plies = generation::legal_plies(&state);
+        assert!(plies
+            .iter()
+            .any(|&ply| ply_history.score(Color::Black, ply) > 0));
+        assert!(plies
+            .iter()
+            .all(|&ply| ply_history.score(Color::White, ply) == 0));
+    }
+
+    #[test]
+    fn search_leaves_the_state_unchanged() {
+        // Black threatens to crush the wall at e3 with the capstone, so the quiescence
+        // search is used at the leaves too.
+        let state: State<5> = "1,1,1,x2/1,1,x2,2C/2,2,2,2,1S/1,x4/x,2,x,2,x 1 9"
+            .parse()
+            .unwrap();
+        let persistent_state = PersistentState::with_table_size(1);
+        let ply_history = PlyHistory::default();
+        let stats = AtomicStatistics::default();
+        let interrupted = AtomicBool::default();
+        let mut search = search_state(
+            &state,
+            &persistent_state,
+            &ply_history,
+            &stats,
+            &interrupted,
+        );
+
+        let mut searched = state.clone();
+        minimax(
+            &mut search,
+            &mut searched,
+            3,
+            Evaluation::MIN,
+            Evaluation::MAX,
+            true,
+        );
+
+        assert!(searched == state);
+        assert!(stats.extended.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn quiescence_scores_repetitions_as_draws() {
+        // Black threatens to place at a1 and at e3. Moving c5 to d5 doesn't stop either
+        // threat, but it repeats the position from four plies ago.
+        let mut state: State<5> = "1,1,x,1,2/1,1,1,x2/2,2,2,2,x/1,1,x3/x,2,2,2,2 2 9"
+            .parse()
+            .unwrap();
+        let mut history = History::new(RepetitionRule::default(), &state);
+        for ply in ["e5-", "d5<", "e4+"] {
+            let ply: Ply<5> = ply.parse().unwrap();
+            state.execute_ply(ply).unwrap();
+            history.push(ply, &state);
+        }
+
+        let persistent_state = PersistentState::with_table_size(1);
+        let ply_history = PlyHistory::default();
+        let stats = AtomicStatistics::default();
+        let interrupted = AtomicBool::default();
+        let mut search = search_state(
+            &state,
+            &persistent_state,
+            &ply_history,
+            &stats,
+            &interrupted,
+        );
+
+        let quiescence_at = |search: &mut SearchState<'_, 5>| {
+            quiescence(
+                search,
+                &mut state.clone(),
+                3,
+                DEFAULT_QUIESCENCE_DEPTH,
+                Evaluation::MIN,
+                Evaluation::MAX,
+            )
+            .evaluation
+        };
+
+        let evaluation = quiescence_at(&mut search);
+        assert!(evaluation.is_terminal());
+        assert!(evaluation < Evaluation::ZERO);
+
+        search.history = history;
+        let evaluation = quiescence_at(&mut search);
+        assert!(!evaluation.is_terminal());
+    }
+
+    fn analyze_at_depth<const N: usize>(
+        tps: &str,
+        depth: u32,
+        quiescence_depth: usize,
+    ) -> Analysis<N> {
+        let state: State<N> = tps.parse().unwrap();
+
+        let config = AnalysisConfig::<N> {
+            depth_limit: Some(depth),
+            quiescence_depth,
+            table_size: 16,
+            ..Default::default()
+        };
+        analyze(config, &state)
+    }
+
+    #[test]
+    fn quiescence_sees_double_threats() {
+        // Black threatens to place at a1 and at e3.
+        let placements = "1,1,1,x2/1,1,1,x2/2,2,2,2,x/1,1,x3/x,2,2,2,2 1 9";
+        // Black threatens to place at a1, and to crush the wall at e3 with the capstone.
+        let crush = "1,1,1,x2/1,1,x2,2C/2,2,2,2,1S/1,x4/x,2,2,2,2 1 9";
+
+        for tps in [placements, crush] {
+            let analysis = analyze_at_depth::<5>(tps, 1, 0);
+            assert!(!analysis.evaluation.is_terminal(), "{tps}");
+
+            let analysis = analyze_at_depth::<5>(tps, 1, DEFAULT_QUIESCENCE_DEPTH);
+            assert!(analysis.evaluation.is_terminal(), "{tps}");
+            assert!(analysis.evaluation < Evaluation::ZERO, "{tps}");
+        }
+    }
+
+    #[test]
+    fn quiescence_blocks_road_threats() {
+        // Black threatens to crush the wall at e3 with the capstone.
+        let tps = "1,1,1,x2/1,1,x2,2C/2,2,2,2,1S/1,x4/x,2,x,2,x 1 9";
+        let analysis = analyze_at_depth::<5>(tps, 1, DEFAULT_QUIESCENCE_DEPTH);
+
+        assert!(!analysis.evaluation.is_terminal());
+        assert!(!analysis.final_state.is_in_tak(Color::White));
+    }
+
+    #[test]
+    fn quiescence_finds_wins_past_the_horizon() {
+        // Placing at d3 threatens roads at d5 and e3, which Black can't both stop.
+        let tps = "2,2,2,x2/x3,1,x/1,1,1,x2/x3,1,x/2,2,x,1,x 1 6";
+
+        let analysis = analyze_at_depth::<5>(tps, 1, 0);
+        assert!(!analysis.evaluation.is_terminal());
+
+        let analysis = analyze_at_depth::<5>(tps, 1, DEFAULT_QUIESCENCE_DEPTH);
+        assert!(analysis.evaluation.is_terminal());
+        assert!(analysis.evaluation > Evaluation::ZERO);
+        // Either a flatstone or the capstone at d3.
+        assert!(matches!(
+            analysis.principal_variation[0],
+            Ply::Place { x: 3, y: 2, .. }
+        ));
+    }
+}
@@ -15,6 +15,12 @@ pub struct Statistics {
     pub tt_store_fails: u64,
     pub tt_hits: u64,
     pub tt_saves: u64,
+    /// Leaves searched further because they were in tak.
+    pub extended: u64,
+    /// Root searches repeated after failing below their aspiration window.
+    pub aspiration_fail_low: u64,
+    /// Root searches repeated after failing above their aspiration window.
+    pub aspiration_fail_high: u64,
     /// Best-ply ordering of PV-Nodes (exact bound nodes).
     pub pv_ply_order: [u64; 6],
     /// Best-ply ordering of All-Nodes (fail-low nodes).
@@ -48,6 +54,9 @@ impl Add for &Statistics {
             tt_store_fails: self.tt_store_fails + other.tt_store_fails,
             tt_hits: self.tt_hits + other.tt_hits,
             tt_saves: self.tt_saves + other.tt_saves,
+            extended: self.extended + other.extended,
+            aspiration_fail_low: self.aspiration_fail_low + other.aspiration_fail_low,
+            aspiration_fail_high: self.aspiration_fail_high + other.aspiration_fail_high,
             pv_ply_order,
             all_ply_order,
         }
@@ -74,6 +83,12 @@ pub(crate) struct AtomicStatistics {
     pub tt_store_fails: AtomicU64,
     pub tt_hits: AtomicU64,
     pub tt_saves: AtomicU64,
+    /// Leaves searched further because they were in tak.
+    pub extended: AtomicU64,
+    /// Root searches repeated after failing below their aspiration window.
+    pub aspiration_fail_low: AtomicU64,
+    /// Root searches repeated after failing above their aspiration window.
+    pub aspiration_fail_high: AtomicU64,
     /// Best-ply ordering of PV-Nodes (exact bound nodes).
     pub pv_ply_order: [AtomicU64; 6],
     /// Best-ply ordering of All-Nodes (fail-low nodes).
@@ -103,6 +118,9 @@ impl AtomicStatistics {
             tt_store_fails: self.tt_store_fails.load(Ordering::Relaxed),
             tt_hits: self.tt_hits.load(Ordering::Relaxed),
             tt_saves: self.tt_saves.load(Ordering::Relaxed),
+            extended: self.extended.load(Ordering::Relaxed),
+            aspiration_fail_low: self.aspiration_fail_low.load(Ordering::Relaxed),
+            aspiration_fail_high: self.aspiration_fail_high.load(Ordering::Relaxed),
             pv_ply_order: load_ply_order(&self.pv_ply_order),
             all_ply_order: load_ply_order(&self.all_ply_order),
         }
@@ -1,44 +1,728 @@
 use std::fmt;
+use std::str::FromStr;
 use std::time::Duration;
 
-use tak::{Color, State};
+use tracing::{debug, info};
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct TimeControl {
-    pub time: Duration,
-    pub increment: Duration,
+use tak::{Color, Ply, State};
+
+use crate::evaluation::Evaluation;
+
+/// The fewest moves that a game is expected to last, so that time isn't spent too quickly
+/// as the reserves run out.
+const MIN_MOVES_REMAINING: f32 = 8.0;
+
+/// How many times the time per move the hard limit allows, for when the search is unstable.
+const HARD_LIMIT_FACTOR: u32 = 4;
+
+/// How much of the soft limit to use after the best ply has stayed the same for a number of
+/// iterations, starting from the iteration that changed it.
+const STABILITY_SCALES: [f64; 5] = [1.6, 1.2, 1.0, 0.85, 0.7];
+
+/// A drop in evaluation between iterations that makes the search use a quarter more time,
+/// up to twice as much.
+const EVALUATION_DROP: f32 = 0.1;
+
+/// A player's clock, as of the start of their move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+    /// Every remaining move must be made within the time.
+    SuddenDeath { time: Duration },
+    /// The increment is added after each move.
+    Increment { time: Duration, increment: Duration },
+    /// The clock only starts once the delay has passed on each move.
+    Delay { time: Duration, delay: Duration },
+    /// The time used on each move is added back afterwards, up to the delay.
+    Bronstein { time: Duration, delay: Duration },
+    /// Once the time runs out, each move must be made within the period.
+    ByoYomi { time: Duration, period: Duration },
+    /// The time must last for the given number of moves, after which more time is added.
+    /// The increment is added after each move.
+    MovesToGo {
+        time: Duration,
+        moves_to_go: u32,
+        increment: Duration,
+    },
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl::SuddenDeath {
+            time: Duration::ZERO,
+        }
+    }
 }
 
 impl TimeControl {
-    pub(crate) fn get_use_time<const N: usize>(&self, state: &State<N>) -> Duration {
+    /// The time left on the clock, not counting any delay or period.
+    pub fn time(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath { time }
+            | TimeControl::Increment { time, .. }
+            | TimeControl::Delay { time, .. }
+            | TimeControl::Bronstein { time, .. }
+            | TimeControl::ByoYomi { time, .. }
+            | TimeControl::MovesToGo { time, .. } => time,
+        }
+    }
+
+    /// The same time control with a different time left on the clock.
+    pub fn with_time(self, time: Duration) -> Self {
+        match self {
+            TimeControl::SuddenDeath { .. } => TimeControl::SuddenDeath { time },
+            TimeControl::Increment { increment, .. } => TimeControl::Increment { time, increment },
+            TimeControl::Delay { delay, .. } => TimeControl::Delay { time, delay },
+            TimeControl::Bronstein { delay, .. } => TimeControl::Bronstein { time, delay },
+            TimeControl::ByoYomi { period, .. } => TimeControl::ByoYomi { time, period },
+            TimeControl::MovesToGo {
+                moves_to_go,
+                increment,
+                ..
+            } => TimeControl::MovesToGo {
+                time,
+                moves_to_go,
+                increment,
+            },
+        }
+    }
+
+    /// The soft and hard time limits for the next move. The soft limit is the time the move
+    /// is expected to take, and the hard limit is the most that it can take.
+    pub(crate) fn limits<const N: usize>(&self, state: &State<N>) -> (Duration, Duration) {
         let reserves = match state.to_move() {
             Color::White => (state.p1_flatstones + state.p1_capstones) as f32,
             Color::Black => (state.p2_flatstones + state.p2_capstones) as f32,
         };
 
-        // XXX Come up with a better reserves -> moves estimation function than this.
-        let moves_remaining = 3.0 * reserves / 2.0;
+        // Spreads don't use up reserves, so games tend to last longer than the reserves
+        // alone would suggest.
+        let mut moves_remaining = (3.0 * reserves / 2.0).max(MIN_MOVES_REMAINING);
 
-        let mut use_time = Duration::from_secs_f32(
-            self.time.as_secs_f32() / moves_remaining + 4.0 * self.increment.as_secs_f32() / 5.0,
-        );
+        // Time that can be used on this move without taking from the clock, and time that's
+        // given back after it.
+        let (free, refund) = match *self {
+            TimeControl::SuddenDeath { .. } => (Duration::ZERO, Duration::ZERO),
+            TimeControl::Increment { increment, .. } => (Duration::ZERO, increment),
+            TimeControl::Delay { delay, .. } => (delay, Duration::ZERO),
+            TimeControl::Bronstein { delay, .. } => (Duration::ZERO, delay),
+            TimeControl::ByoYomi { period, .. } => (period, Duration::ZERO),
+            TimeControl::MovesToGo {
+                moves_to_go,
+                increment,
+                ..
+            } => {
+                moves_remaining = moves_remaining.min(moves_to_go.max(1) as f32);
+                (Duration::ZERO, increment)
+            }
+        };
+
+        // Only count on most of the extra time, to leave a margin for communication.
+        let extra = (free + refund).mul_f32(4.0 / 5.0);
+        let use_time = self.time().div_f32(moves_remaining) + extra;
 
         // Never use more than half of the remaining time.
-        use_time = use_time.min(self.time / 2);
+        let hard_limit =
+            (use_time * HARD_LIMIT_FACTOR).min(self.time() / 2 + free.mul_f32(4.0 / 5.0));
 
-        use_time
+        (use_time.min(hard_limit), hard_limit)
     }
 }
 
+/// Converts parsed seconds to a duration, rounded to the millisecond so that float error doesn't
+/// show up when the time control is written back out.
+fn parse_seconds(seconds: f32) -> Option<Duration> {
+    let time = Duration::try_from_secs_f32(seconds).ok()?;
+    Some(Duration::from_millis(
+        (time.as_secs_f64() * 1000.0).round() as u64
+    ))
+}
+
+/// Parses a time control from the form `[moves/]time[kind]`. The time is in minutes, or
+/// minutes and seconds separated by a colon, and the kind is one of:
+/// - nothing, for sudden death: `10`
+/// - `+seconds`, for an increment: `10+5`
+/// - `dseconds`, for a simple delay: `10d5`
+/// - `bseconds`, for a Bronstein delay: `10b5`
+/// - `yseconds`, for byo-yomi: `10y30`
+///
+/// With a number of moves, the time is added again every that many moves: `40/90+30`.
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time control: {s:?}.");
+
+        let (moves, rest) = match s.split_once('/') {
+            Some((moves, rest)) => (
+                Some(
+                    moves
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|&moves| moves > 0)
+                        .ok_or_else(invalid)?,
+                ),
+                rest,
+            ),
+            None => (None, s),
+        };
+
+        let (time, kind) = match rest.find(['+', 'd', 'b', 'y']) {
+            Some(index) => {
+                let (time, kind) = rest.split_at(index);
+                let seconds = kind[1..].parse::<f32>().map_err(|_| invalid())?;
+                let extra = parse_seconds(seconds).ok_or_else(invalid)?;
+                (time, Some((kind.as_bytes()[0], extra)))
+            }
+            None => (rest, None),
+        };
+
+        let minutes = match time.split_once(':') {
+            Some((minutes, seconds)) => {
+                let minutes = minutes.parse::<u32>().map_err(|_| invalid())?;
+                let seconds = seconds.parse::<f32>().map_err(|_| invalid())?;
+                minutes as f32 + seconds / 60.0
+            }
+            None => time.parse::<f32>().map_err(|_| invalid())?,
+        };
+        let time = parse_seconds(60.0 * minutes).ok_or_else(invalid)?;
+
+        match (moves, kind) {
+            (Some(moves_to_go), None) => Ok(TimeControl::MovesToGo {
+                time,
+                moves_to_go,
+                increment: Duration::ZERO,
+            }),
+            (Some(moves_to_go), Some((b'+', increment))) => Ok(TimeControl::MovesToGo {
+                time,
+                moves_to_go,
+                increment,
+            }),
+            (Some(_), Some(_)) => Err(invalid()),
+            (None, None) => Ok(TimeControl::SuddenDeath { time }),
+            (None, Some((b'+', increment))) => Ok(TimeControl::Increment { time, increment }),
+            (None, Some((b'd', delay))) => Ok(TimeControl::Delay { time, delay }),
+            (None, Some((b'b', delay))) => Ok(TimeControl::Bronstein { time, delay }),
+            (None, Some((_, period))) => Ok(TimeControl::ByoYomi { time, period }),
+        }
+    }
+}
+
+/// Writes a time control in the form that it's parsed from, with the time in minutes and
+/// seconds: `40/90:00+30`.
 impl fmt::Display for TimeControl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let minutes = self.time.as_secs() / 60;
-        let seconds = self.time.as_secs_f32() - (60 * minutes) as f32;
+        if let TimeControl::MovesToGo { moves_to_go, .. } = *self {
+            write!(f, "{moves_to_go}/")?;
+        }
+
+        let millis = self.time().as_millis();
+        let (minutes, millis) = (millis / 60_000, millis % 60_000);
+        write!(f, "{minutes}:{:02}", millis / 1000)?;
+        if millis % 1000 != 0 {
+            write!(f, ".{:03}", millis % 1000)?;
+        }
+
+        let (kind, extra) = match *self {
+            TimeControl::SuddenDeath { .. } => return Ok(()),
+            TimeControl::Increment { increment, .. } | TimeControl::MovesToGo { increment, .. } => {
+                ('+', increment)
+            }
+            TimeControl::Delay { delay, .. } => ('d', delay),
+            TimeControl::Bronstein { delay, .. } => ('b', delay),
+            TimeControl::ByoYomi { period, .. } => ('y', period),
+        };
+
+        write!(f, "{kind}{}", extra.as_secs_f32())
+    }
+}
+
+/// A player's clock over the course of a game, following a time control.
+#[derive(Clone, Copy, Debug)]
+pub struct GameClock {
+    /// The time control the game started with.
+    start: TimeControl,
+    remaining: Duration,
+    moves: u32,
+}
 
-        write!(
-            f,
-            "{minutes:02}:{seconds:04.2}, inc: +{:.2}s",
-            self.increment.as_secs_f32(),
+impl GameClock {
+    pub fn new(time_control: TimeControl) -> Self {
+        Self {
+            start: time_control,
+            remaining: time_control.time(),
+            moves: 0,
+        }
+    }
+
+    /// The time left on the clock, not counting any delay or period.
+    pub fn remaining(&self) -> Duration {
+        self.remaining
+    }
+
+    /// The clock as of the start of the next move.
+    pub fn time_control(&self) -> TimeControl {
+        match self.start {
+            TimeControl::MovesToGo {
+                moves_to_go,
+                increment,
+                ..
+            } => TimeControl::MovesToGo {
+                time: self.remaining,
+                moves_to_go: moves_to_go - self.moves % moves_to_go,
+                increment,
+            },
+            start => start.with_time(self.remaining),
+        }
+    }
+
+    /// The most time that the next move can take without running out of time.
+    pub fn allowance(&self) -> Duration {
+        match self.start {
+            TimeControl::Delay { delay, .. } => self.remaining + delay,
+            TimeControl::ByoYomi { period, .. } => self.remaining + period,
+            _ => self.remaining,
+        }
+    }
+
+    /// The clock partway through a move that has taken the given time so far. Any delay or
+    /// period left is counted as main time, since only this move can still use it.
+    pub fn time_control_after(&self, used: Duration) -> TimeControl {
+        let time = self.allowance().saturating_sub(used);
+        match self.start {
+            TimeControl::Delay { .. } | TimeControl::ByoYomi { .. } => {
+                TimeControl::SuddenDeath { time }
+            }
+            _ => self.time_control().with_time(time),
+        }
+    }
+
+    /// Charges the clock for a move that took the given time. Returns false if the time
+    /// ran out, in which case the clock is left at zero.
+    pub fn finish_move(&mut self, used: Duration) -> bool {
+        if used > self.allowance() {
+            self.remaining = Duration::ZERO;
+            return false;
+        }
+
+        self.moves += 1;
+        self.remaining = match self.start {
+            TimeControl::SuddenDeath { .. } | TimeControl::ByoYomi { .. } => {
+                self.remaining.saturating_sub(used)
+            }
+            TimeControl::Increment { increment, .. } => self.remaining - used + increment,
+            TimeControl::Delay { delay, .. } => {
+                self.remaining.saturating_sub(used.saturating_sub(delay))
+            }
+            TimeControl::Bronstein { delay, .. } => self.remaining - used + used.min(delay),
+            TimeControl::MovesToGo {
+                time,
+                moves_to_go,
+                increment,
+            } => {
+                let remaining = self.remaining - used + increment;
+                if self.moves.is_multiple_of(moves_to_go) {
+                    remaining + time
+                } else {
+                    remaining
+                }
+            }
+        };
+
+        true
+    }
+}
+
+/// The time used from a time limit.
+pub(crate) trait Clock {
+    /// The time used so far, or none if the time hasn't started, such as while pondering.
+    fn elapsed(&self) -> Option<Duration>;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn elapsed(&self) -> Option<Duration> {
+        (**self).elapsed()
+    }
+}
+
+/// Decides when to stop starting new iterations of a search.
+///
+/// The search aims to stop around the soft limit, and is interrupted at the hard limit. The
+/// soft limit is scaled after each iteration: it's extended while the best ply keeps changing
+/// or the evaluation drops, and cut while the best ply stays the same. A forced move is
+/// played after the first iteration.
+pub(crate) struct TimeManager<C, const N: usize> {
+    clock: C,
+    soft_limit: Duration,
+    hard_limit: Duration,
+    forced: bool,
+    /// How much of the soft limit to use.
+    scale: f64,
+    /// The best ply and evaluation of the last iteration.
+    previous: Option<(Ply<N>, Evaluation)>,
+    /// The number of iterations since the best ply last changed.
+    stable_iterations: usize,
+}
+
+impl<C: Clock, const N: usize> TimeManager<C, N> {
+    pub fn new(clock: C, soft_limit: Duration, hard_limit: Duration, forced: bool) -> Self {
+        Self {
+            clock,
+            soft_limit: soft_limit.min(hard_limit),
+            hard_limit,
+            forced,
+            scale: 1.0,
+            previous: None,
+            stable_iterations: 0,
+        }
+    }
+
+    /// Replaces the limits, such as once the clock is known after a ponder hit.
+    pub fn set_limits(&mut self, soft_limit: Duration, hard_limit: Duration) {
+        self.soft_limit = soft_limit.min(hard_limit);
+        self.hard_limit = hard_limit;
+    }
+
+    /// The soft limit, scaled by how stable the search has been.
+    pub fn target(&self) -> Duration {
+        if self.forced {
+            return Duration::ZERO;
+        }
+
+        self.soft_limit.mul_f64(self.scale).min(self.hard_limit)
+    }
+
+    /// Updates the target with the result of a completed iteration.
+    pub fn iteration_complete(&mut self, ply: Ply<N>, evaluation: Evaluation) {
+        let mut drop_scale = 1.0;
+
+        if let Some((previous_ply, previous_evaluation)) = self.previous {
+            if ply == previous_ply {
+                self.stable_iterations += 1;
+            } else {
+                self.stable_iterations = 0;
+            }
+
+            if !evaluation.is_terminal() && !previous_evaluation.is_terminal() {
+                let drop = f32::from(previous_evaluation) - f32::from(evaluation);
+                drop_scale += (drop / EVALUATION_DROP).clamp(0.0, 4.0) as f64 / 4.0;
+            }
+
+            let stability_scale =
+                STABILITY_SCALES[self.stable_iterations.min(STABILITY_SCALES.len() - 1)];
+            self.scale = stability_scale * drop_scale;
+        }
+
+        self.previous = Some((ply, evaluation));
+
+        debug!(
+            stable_iterations = self.stable_iterations,
+            scale = %format!("{:.2}", self.scale),
+            target = %format!("{:.2}s", self.target().as_secs_f64()),
+            "Time:",
+        );
+    }
+
+    /// Whether to stop instead of starting another iteration, which is predicted to take
+    /// the given time.
+    pub fn should_stop(&self, next_iteration_prediction: Duration) -> bool {
+        // While pondering, none of the time has been used yet.
+        let Some(used_time) = self.clock.elapsed() else {
+            return false;
+        };
+        let target = self.target();
+
+        if used_time >= target {
+            info!(
+                time = %format!("{:.2}s", used_time.as_secs_f64()),
+                target = %format!("{:.2}s", target.as_secs_f64()),
+                "Target time reached. Stopping."
+            );
+            return true;
+        }
+
+        // Stop if the next iteration is predicted to be cut off by the hard limit, and
+        // we've already used a third of the target.
+        let prediction = used_time + next_iteration_prediction;
+        if used_time > target / 3 && prediction > self.hard_limit {
+            info!(
+                time = %format!("{:.2}s", used_time.as_secs_f64()),
+                limit = %format!("{:.2}s", self.hard_limit.as_secs_f64()),
+                prediction = %format!("{:.2}s", prediction.as_secs_f64()),
+                "Next iteration is predicted to take too long. Stopping."
+            );
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeClock(Cell<Option<Duration>>);
+
+    impl FakeClock {
+        fn set(&self, millis: u64) {
+            self.0.set(Some(Duration::from_millis(millis)));
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn elapsed(&self) -> Option<Duration> {
+            self.0.get()
+        }
+    }
+
+    fn ply(ptn: &str) -> Ply<5> {
+        ptn.parse().unwrap()
+    }
+
+    fn millis(duration: Duration) -> u64 {
+        (duration.as_secs_f64() * 1000.0).round() as u64
+    }
+
+    fn manager(clock: &FakeClock, forced: bool) -> TimeManager<&FakeClock, 5> {
+        TimeManager::new(
+            clock,
+            Duration::from_millis(1000),
+            Duration::from_millis(4000),
+            forced,
         )
     }
+
+    #[test]
+    fn limits() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        let limits = |time_control: &str| {
+            let (soft, hard) = time_control.parse::<TimeControl>().unwrap().limits(&state);
+            (millis(soft), millis(hard))
+        };
+
+        // 22 reserves are expected to last 33 moves.
+        assert_eq!(limits("1:06"), (2000, 8000));
+        assert_eq!(limits("1:06+1.25"), (3000, 12000));
+        assert_eq!(limits("1:06b1.25"), (3000, 12000));
+
+        // Low on time, neither limit is more than half of what's left.
+        assert_eq!(limits("0:02+2"), (1000, 1000));
+
+        // Unless some of the move's time doesn't come off the clock.
+        assert_eq!(limits("0:02d2.5"), (2061, 3000));
+        assert_eq!(limits("0:00y10"), (8000, 8000));
+
+        // The time has to last until the next period, or the end of the game.
+        assert_eq!(limits("10/1:00"), (6000, 24000));
+        assert_eq!(limits("40/1:06"), (2000, 8000));
+    }
+
+    #[test]
+    fn parse_time_controls() {
+        let parse = |s: &str| s.parse::<TimeControl>();
+        let seconds = Duration::from_secs;
+
+        assert_eq!(
+            parse("10"),
+            Ok(TimeControl::SuddenDeath { time: seconds(600) })
+        );
+        assert_eq!(
+            parse("2:30+5"),
+            Ok(TimeControl::Increment {
+                time: seconds(150),
+                increment: seconds(5)
+            })
+        );
+        assert_eq!(
+            parse("0.5d3"),
+            Ok(TimeControl::Delay {
+                time: seconds(30),
+                delay: seconds(3)
+            })
+        );
+        assert_eq!(
+            parse("5b2"),
+            Ok(TimeControl::Bronstein {
+                time: seconds(300),
+                delay: seconds(2)
+            })
+        );
+        assert_eq!(
+            parse("10y30"),
+            Ok(TimeControl::ByoYomi {
+                time: seconds(600),
+                period: seconds(30)
+            })
+        );
+        assert_eq!(
+            parse("40/90+30"),
+            Ok(TimeControl::MovesToGo {
+                time: seconds(5400),
+                moves_to_go: 40,
+                increment: seconds(30)
+            })
+        );
+
+        for time_control in ["10:00", "2:30+5", "0:05.250d3", "5:00b0.5", "40/90:00+30"] {
+            assert_eq!(parse(time_control).unwrap().to_string(), time_control);
+        }
+        assert_eq!(parse("0.02+0.1").unwrap().to_string(), "0:01.200+0.1");
+
+        for invalid in ["", "ten", "10+", "10x5", "-1", "0/10", "40/90d5", "10:xx"] {
+            assert!(parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn game_clocks() {
+        let clock = |s: &str| GameClock::new(s.parse().unwrap());
+        let seconds = Duration::from_secs;
+
+        let mut increment = clock("1+5");
+        assert!(increment.finish_move(seconds(10)));
+        assert_eq!(increment.remaining(), seconds(55));
+        assert!(!increment.finish_move(seconds(56)));
+        assert_eq!(increment.remaining(), Duration::ZERO);
+
+        let mut delay = clock("1d5");
+        assert_eq!(
+            delay.time_control_after(seconds(15)),
+            TimeControl::SuddenDeath { time: seconds(50) }
+        );
+        assert!(delay.finish_move(seconds(3)));
+        assert_eq!(delay.remaining(), seconds(60));
+        assert!(delay.finish_move(seconds(10)));
+        assert_eq!(delay.remaining(), seconds(55));
+        assert!(delay.finish_move(seconds(60)));
+        assert_eq!(delay.remaining(), Duration::ZERO);
+
+        let mut bronstein = clock("1b5");
+        assert!(bronstein.finish_move(seconds(3)));
+        assert_eq!(bronstein.remaining(), seconds(60));
+        assert!(bronstein.finish_move(seconds(10)));
+        assert_eq!(bronstein.remaining(), seconds(55));
+        assert!(!bronstein.finish_move(seconds(56)));
+
+        let mut byo_yomi = clock("1y10");
+        assert!(byo_yomi.finish_move(seconds(65)));
+        assert_eq!(byo_yomi.remaining(), Duration::ZERO);
+        assert!(byo_yomi.finish_move(seconds(10)));
+        assert!(!byo_yomi.finish_move(seconds(11)));
+
+        let mut periods = clock("2/1+1");
+        assert_eq!(
+            periods.time_control(),
+            "2/1+1".parse::<TimeControl>().unwrap()
+        );
+        assert!(periods.finish_move(seconds(20)));
+        assert_eq!(
+            periods.time_control_after(seconds(30)),
+            TimeControl::MovesToGo {
+                time: seconds(11),
+                moves_to_go: 1,
+                increment: seconds(1),
+            }
+        );
+        assert_eq!(
+            periods.time_control(),
+            TimeControl::MovesToGo {
+                time: seconds(41),
+                moves_to_go: 1,
+                increment: seconds(1),
+            }
+        );
+        assert!(periods.finish_move(seconds(20)));
+        assert_eq!(periods.remaining(), seconds(82));
+        assert_eq!(
+            periods.time_control(),
+            TimeControl::MovesToGo {
+                time: seconds(82),
+                moves_to_go: 2,
+                increment: seconds(1),
+            }
+        );
+    }
+
+    #[test]
+    fn stable_search_stops_early() {
+        let clock = FakeClock::default();
+        let mut manager = manager(&clock, false);
+
+        for _ in 0..5 {
+            manager.iteration_complete(ply("c3"), 0.5.into());
+        }
+        assert_eq!(millis(manager.target()), 700);
+
+        clock.set(600);
+        assert!(!manager.should_stop(Duration::from_millis(100)));
+        clock.set(701);
+        assert!(manager.should_stop(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn changing_ply_extends_time() {
+        let clock = FakeClock::default();
+        let mut manager = manager(&clock, false);
+
+        manager.iteration_complete(ply("c3"), 0.5.into());
+        assert_eq!(millis(manager.target()), 1000);
+
+        manager.iteration_complete(ply("d3"), 0.5.into());
+        assert_eq!(millis(manager.target()), 1600);
+
+        clock.set(1200);
+        assert!(!manager.should_stop(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn evaluation_drop_extends_time() {
+        let clock = FakeClock::default();
+        let mut manager = manager(&clock, false);
+
+        manager.iteration_complete(ply("c3"), 0.5.into());
+        manager.iteration_complete(ply("c3"), 0.45.into());
+        manager.iteration_complete(ply("c3"), 0.25.into());
+
+        // Stable for two iterations, but the evaluation dropped by 0.2.
+        assert_eq!(millis(manager.target()), 1500);
+
+        // Rising evaluations don't cut time.
+        manager.iteration_complete(ply("c3"), 0.75.into());
+        assert_eq!(millis(manager.target()), 850);
+    }
+
+    #[test]
+    fn forced_move_stops_after_first_iteration() {
+        let clock = FakeClock::default();
+        let mut manager = manager(&clock, true);
+
+        clock.set(1);
+        manager.iteration_complete(ply("c3"), 0.0.into());
+        assert!(manager.should_stop(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn predicted_overrun_stops() {
+        let clock = FakeClock::default();
+        let manager = manager(&clock, false);
+
+        // Too early to give up on the next iteration.
+        clock.set(300);
+        assert!(!manager.should_stop(Duration::from_millis(5000)));
+
+        clock.set(400);
+        assert!(!manager.should_stop(Duration::from_millis(3000)));
+        assert!(manager.should_stop(Duration::from_millis(4000)));
+    }
+
+    #[test]
+    fn time_does_not_run_while_pondering() {
+        let clock = FakeClock::default();
+        let manager = manager(&clock, true);
+
+        assert!(!manager.should_stop(Duration::from_secs(100)));
+    }
 }
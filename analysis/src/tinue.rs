@@ -0,0 +1,231 @@
+//! Proving forced road wins, or tinuë, with a search that only considers plies that keep
+//! the opponent in tak.
+
+use fnv::FnvHashMap;
+use tracing::{debug, info};
+
+use tak::{generation, Color, Ply, Resolution, State, ZobristHash};
+
+use crate::move_order::PlacementWins;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TinueResult<const N: usize> {
+    /// The player to move can force a road. The line alternates between the winner's plies
+    /// and the replies that hold out the longest, and ends with the road.
+    Win(Vec<Ply<N>>),
+    /// There's no forced road within the number of moves searched.
+    NoTinue,
+}
+
+/// Searches for a tinuë for the player to move, taking at most `max_moves` of their plies
+/// including the one that completes the road. Shorter wins are found first.
+pub fn solve_tinue<const N: usize>(state: &State<N>, max_moves: usize) -> TinueResult<N> {
+    if state.resolution().is_some() {
+        return TinueResult::NoTinue;
+    }
+
+    let mut solver = Solver::default();
+
+    for moves in 1..=max_moves {
+        debug!(moves, "Searching for tinuë...");
+
+        if let Some(line) = solver.attack(state, moves) {
+            info!(moves, nodes = solver.nodes, ?line, "Tinuë found.");
+            return TinueResult::Win(line);
+        }
+    }
+
+    info!(max_moves, nodes = solver.nodes, "No tinuë found.");
+    TinueResult::NoTinue
+}
+
+struct Solver<const N: usize> {
+    /// The most moves that the attacker is known not to be able to win within from each
+    /// position.
+    no_win: FnvHashMap<ZobristHash, usize>,
+    nodes: u64,
+}
+
+impl<const N: usize> Default for Solver<N> {
+    fn default() -> Self {
+        Self {
+            no_win: Default::default(),
+            nodes: 0,
+        }
+    }
+}
+
+impl<const N: usize> Solver<N> {
+    /// Finds a winning line for the player to move, who is the attacker.
+    fn attack(&mut self, state: &State<N>, moves: usize) -> Option<Vec<Ply<N>>> {
+        self.nodes += 1;
+
+        let attacker = state.to_move();
+        let defender = attacker.other();
+
+        if state.is_in_tak(defender) {
+            return road_in_one(state).map(|ply| vec![ply]);
+        }
+
+        if moves <= 1 {
+            return None;
+        }
+
+        let hash = state.metadata.hash;
+        if self.no_win.get(&hash).is_some_and(|&known| known >= moves) {
+            return None;
+        }
+
+        for ply in generation::legal_plies(state) {
+            let mut next = state.clone();
+            if next.execute_ply(ply).is_err() {
+                continue;
+            }
+
+            // Only threats are considered. Any ply that ends the game without a road
+            // was already ruled out, since a road in one would have been found above.
+            if next.resolution().is_some() || !next.is_in_tak(defender) {
+                continue;
+            }
+
+            if let Some(mut line) = self.defend(&next, moves - 1) {
+                line.insert(0, ply);
+                return Some(line);
+            }
+        }
+
+        let known = self.no_win.entry(hash).or_default();
+        *known = (*known).max(moves);
+
+        None
+    }
+
+    /// Finds a winning line for the attacker against every reply from the player to move,
+    /// who is in tak. The line continues with the reply that holds out the longest.
+    fn defend(&mut self, state: &State<N>, moves: usize) -> Option<Vec<Ply<N>>> {
+        self.nodes += 1;
+
+        let attacker = state.to_move().other();
+        let mut longest: Option<Vec<Ply<N>>> = None;
+
+        for ply in generation::legal_plies(state) {
+            let mut next = state.clone();
+            if next.execute_ply(ply).is_err() {
+                continue;
+            }
+
+            let mut line = match next.resolution() {
+                // Making the attacker's road for them.
+                Some(Resolution::Road(color)) if color == attacker => Vec::new(),
+                Some(_) => return None,
+                None => self.attack(&next, moves)?,
+            };
+            line.insert(0, ply);
+
+            if longest
+                .as_ref()
+                .is_none_or(|longest| line.len() > longest.len())
+            {
+                longest = Some(line);
+            }
+        }
+
+        longest
+    }
+}
+
+/// Finds a ply that completes a road for the player to move.
+fn road_in_one<const N: usize>(state: &State<N>) -> Option<Ply<N>> {
+    if let Some(generated) = PlacementWins::new(state).next() {
+        return Some(generated.ply);
+    }
+
+    let color = state.to_move();
+    let pieces = match color {
+        Color::White => state.metadata.p1_pieces,
+        Color::Black => state.metadata.p2_pieces,
+    };
+
+    generation::spreads(state, pieces).find(|&ply| {
+        let mut state = state.clone();
+        state.execute_ply(ply).is_ok() && state.resolution() == Some(Resolution::Road(color))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that a line is legal and ends with a road for the player to move.
+    fn assert_wins<const N: usize>(state: &State<N>, line: &[Ply<N>]) {
+        let winner = state.to_move();
+        let mut state = state.clone();
+
+        for &ply in line {
+            assert!(state.resolution().is_none(), "{line:?}");
+            state.execute_ply(ply).unwrap();
+        }
+
+        assert_eq!(
+            state.resolution(),
+            Some(Resolution::Road(winner)),
+            "{line:?}"
+        );
+    }
+
+    #[test]
+    fn road_in_one() {
+        let state: State<5> = "2,2,2,2,x/x5/x5/1,1,1,1,x/x4,2 2 9".parse().unwrap();
+
+        match solve_tinue(&state, 1) {
+            TinueResult::Win(line) => {
+                assert_eq!(line.len(), 1);
+                assert_wins(&state, &line);
+            }
+            result => panic!("expected a win, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn double_threat() {
+        // Placing at d3 or e1 makes two threats, which Black can't both stop.
+        let state: State<5> = "2,2,2,x2/x3,1,x/1,1,1,x2/x3,1,x/2,2,x,1,x 1 6"
+            .parse()
+            .unwrap();
+
+        assert_eq!(solve_tinue(&state, 1), TinueResult::NoTinue);
+
+        match solve_tinue(&state, 3) {
+            TinueResult::Win(line) => {
+                assert_eq!(line.len(), 3);
+                assert_wins(&state, &line);
+            }
+            result => panic!("expected a win, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn capstone_crush() {
+        // Black threatens to place at a1, and to crush the wall at e3 with the capstone.
+        let state: State<5> = "1,1,1,x2/1,1,x2,2C/2,2,2,2,1S/1,x4/x,2,2,2,2 2 9"
+            .parse()
+            .unwrap();
+
+        match solve_tinue(&state, 1) {
+            TinueResult::Win(line) => assert_wins(&state, &line),
+            result => panic!("expected a win, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn no_tinue() {
+        let state: State<5> = "x5/x5/x2,1,x2/x5/x5 2 1".parse().unwrap();
+        assert_eq!(solve_tinue(&state, 3), TinueResult::NoTinue);
+
+        // White can block the only threat.
+        let state: State<5> = "1,1,1,x2/1,1,x2,2C/2,2,2,2,1S/1,x4/x,2,x,2,x 1 9"
+            .parse()
+            .unwrap();
+        assert_eq!(solve_tinue(&state, 2), TinueResult::NoTinue);
+    }
+}
@@ -1,26 +1,87 @@
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering};
 use std::{fmt, mem};
 
-use tak::{Ply, ZobristHash};
+use tak::{zobrist_seed, Ply, ZobristHash};
 
 use crate::evaluation::Evaluation;
 use crate::util::PackedPly;
 
-const MAX_PROBE_DEPTH: usize = 5;
+/// The number of entries that share an index, which together fill a cache line.
+const BUCKET_SIZE: usize = 4;
 
+/// The default size of a transposition table, in megabytes.
+pub const DEFAULT_TABLE_SIZE: usize = 128;
+/// The largest table that will be read from a file, in megabytes.
+const MAX_LOADED_TABLE_SIZE: usize = 65536;
+
+const MAGIC: &[u8; 4] = b"TKTT";
+const VERSION: u8 = 2;
+
+/// A lock-free hash table of search results, shared by every thread of a search.
+///
+/// Entries are grouped into buckets that each fill a cache line, and a hash picks its
+/// bucket with a mask, so there's always a power of two of them. When a bucket is full,
+/// entries from earlier searches are replaced first, then the shallowest.
 pub struct TranspositionTable<const N: usize> {
     len: AtomicUsize,
-    values: Vec<Slot<N>>,
+    /// The generation of the current search, which new entries are stamped with.
+    generation: AtomicU16,
+    buckets: Vec<Bucket<N>>,
 }
 
 impl<const N: usize> TranspositionTable<N> {
+    /// Creates a table with room for about the given number of entries, rounded down to
+    /// a power of two buckets.
     pub fn with_capacity(capacity: usize) -> Self {
-        let mut values = Vec::with_capacity(capacity);
-        values.resize_with(capacity, Default::default);
+        let buckets = 1 << (capacity / BUCKET_SIZE).max(1).ilog2();
+
+        let mut values = Vec::with_capacity(buckets);
+        values.resize_with(buckets, Default::default);
 
         Self {
             len: AtomicUsize::new(0),
-            values,
+            generation: AtomicU16::new(0),
+            buckets: values,
+        }
+    }
+
+    /// Creates a table that takes up about the given number of megabytes.
+    pub fn with_size(megabytes: usize) -> Self {
+        Self::with_capacity(Self::capacity_for_size(megabytes))
+    }
+
+    fn capacity_for_size(megabytes: usize) -> usize {
+        megabytes * 1024 * 1024 / mem::size_of::<Slot<N>>()
+    }
+
+    /// The approximate size of the table in megabytes.
+    pub fn size(&self) -> usize {
+        self.buckets.len() * mem::size_of::<Bucket<N>>() / (1024 * 1024)
+    }
+
+    /// Removes all entries, keeping the capacity.
+    pub fn clear(&self) {
+        for slot in self.slots() {
+            slot.clear();
+        }
+        self.len.store(0, Ordering::Release);
+        self.generation.store(0, Ordering::Release);
+    }
+
+    /// Changes the size of the table to about the given number of megabytes, removing all
+    /// entries.
+    pub fn resize(&mut self, megabytes: usize) {
+        let resized = Self::with_capacity(Self::capacity_for_size(megabytes));
+
+        if resized.capacity() == self.capacity() {
+            self.clear();
+        } else {
+            *self = resized;
         }
     }
 
@@ -33,72 +94,277 @@ impl<const N: usize> TranspositionTable<N> {
     }
 
     pub fn capacity(&self) -> usize {
-        self.values.len()
+        self.buckets.len() * BUCKET_SIZE
     }
 
+    /// The generation that entries stored by the current search are stamped with.
+    pub fn generation(&self) -> u16 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Starts a new generation, so that entries stored by earlier searches are replaced
+    /// before those of the new search. This should be called once before each search.
+    pub fn new_search(&self) {
+        let generation = (self.generation() + 1) % ENTRY_GENERATIONS;
+        self.generation.store(generation, Ordering::Release);
+    }
+
+    /// How many searches ago an entry was stored or last refreshed.
+    pub fn age(&self, entry: &TranspositionTableEntry<N>) -> u16 {
+        (self.generation() + ENTRY_GENERATIONS - entry.generation()) % ENTRY_GENERATIONS
+    }
+
+    /// Stores an entry, returning whether it was stored. An existing entry for the same
+    /// position is only replaced by a deeper or more exact one, though an entry from an
+    /// earlier search is still refreshed to the current generation.
     pub fn insert(&self, hash: ZobristHash, entry: TranspositionTableEntry<N>) -> bool {
-        let start_index = self.index(hash);
-
-        let mut current_index = start_index;
-        let mut target_index = start_index;
-        let mut target_score = (usize::MAX, u16::MAX, Bound::Lower);
-
-        for _ in 0..MAX_PROBE_DEPTH {
-            if let Some(slot) = self.values[current_index].load() {
-                if hash != slot.hash {
-                    let slot_score = (
-                        slot.entry.depth(),
-                        slot.entry.ply_count(),
-                        slot.entry.bound(),
-                    );
-
-                    // Find the lowest score to replace.
-                    if slot_score < target_score {
-                        target_index = current_index;
-                        target_score = slot_score;
-                    }
-                } else {
-                    let replace =
-                        (entry.depth(), entry.bound()) > (slot.entry.depth(), slot.entry.bound());
-
-                    if replace {
-                        self.values[current_index].store(hash, entry);
-                    }
-
-                    return replace;
+        let generation = self.generation();
+        let entry = entry.with_generation(generation);
+
+        let mut empty = None;
+        let mut victim = None;
+
+        for slot in &self.buckets[self.index(hash)].slots {
+            let Some(loaded) = slot.load() else {
+                empty = empty.or(Some(slot));
+                continue;
+            };
+
+            let age = self.age(&loaded.entry);
+
+            if loaded.hash == hash {
+                let replace =
+                    (entry.depth(), entry.bound()) > (loaded.entry.depth(), loaded.entry.bound());
+
+                if replace {
+                    slot.store(hash, entry);
+                } else if age > 0 {
+                    slot.store(hash, loaded.entry.with_generation(generation));
                 }
-            } else {
-                // Always overwrite an empty slot.
-                target_index = current_index;
-                self.len.fetch_add(1, Ordering::AcqRel);
-                break;
+
+                return replace;
             }
 
-            current_index = self.next_index(current_index);
+            // Find the oldest, then shallowest entry to replace.
+            let score = (Reverse(age), loaded.entry.depth(), loaded.entry.bound());
+            if victim.is_none_or(|(_, victim_score)| score < victim_score) {
+                victim = Some((slot, score));
+            }
         }
 
-        self.values[target_index].store(hash, entry);
+        if let Some(slot) = empty {
+            self.len.fetch_add(1, Ordering::AcqRel);
+            slot.store(hash, entry);
+        } else if let Some((slot, _)) = victim {
+            slot.store(hash, entry);
+        }
 
         true
     }
 
-    pub fn get(&self, hash: ZobristHash) -> Option<TranspositionTableEntry<N>> {
-        let mut current_index = self.index(hash);
+    /// Looks up the entry for a position, if the table has one.
+    pub fn probe(&self, hash: ZobristHash) -> Option<TranspositionTableEntry<N>> {
+        self.buckets[self.index(hash)]
+            .slots
+            .iter()
+            .filter_map(Slot::load)
+            .find(|loaded| loaded.hash == hash)
+            .map(|loaded| loaded.entry)
+    }
 
-        for _ in 0..MAX_PROBE_DEPTH {
-            if let Some(slot) = self.values[current_index].load() {
-                if hash == slot.hash {
-                    return Some(slot.entry);
-                }
-            } else {
-                return None;
+    /// How full the table is, in permille of its capacity.
+    pub fn hashfull(&self) -> u16 {
+        (self.len() * 1000 / self.capacity()) as u16
+    }
+
+    /// Counts the entries in the table by bound, depth and age. This visits every slot,
+    /// so it's too slow to call during a search.
+    pub fn stats(&self) -> TranspositionTableStats {
+        let mut stats = TranspositionTableStats {
+            capacity: self.capacity(),
+            ..Default::default()
+        };
+
+        for loaded in self.slots().filter_map(Slot::load) {
+            let entry = loaded.entry;
+
+            stats.entries += 1;
+            match entry.bound() {
+                Bound::Lower => stats.lower_bounds += 1,
+                Bound::Upper => stats.upper_bounds += 1,
+                Bound::Exact => stats.exact += 1,
             }
+            *stats.depths.entry(entry.depth()).or_default() += 1;
+            *stats.ages.entry(self.age(&entry)).or_default() += 1;
+        }
+
+        stats
+    }
+}
+
+/// A summary of the entries in a transposition table.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TranspositionTableStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub lower_bounds: usize,
+    pub upper_bounds: usize,
+    pub exact: usize,
+    /// The number of entries searched to each depth.
+    pub depths: BTreeMap<usize, usize>,
+    /// The number of entries of each age, in searches since they were stored.
+    pub ages: BTreeMap<u16, usize>,
+}
+
+#[derive(Debug)]
+pub enum TranspositionTableError {
+    IoError(String),
+    InvalidFormat(String),
+    IncorrectSize(String),
+    /// The table was saved with different Zobrist keys, or the keys are random.
+    IncorrectKeys(String),
+}
+
+impl From<io::Error> for TranspositionTableError {
+    fn from(error: io::Error) -> Self {
+        TranspositionTableError::IoError(error.to_string())
+    }
+}
 
-            current_index = self.next_index(current_index);
+impl<const N: usize> TranspositionTable<N> {
+    pub fn load(filename: impl AsRef<Path>) -> Result<Self, TranspositionTableError> {
+        Self::read_from(BufReader::new(File::open(filename)?))
+    }
+
+    pub fn save(&self, filename: impl AsRef<Path>) -> Result<(), TranspositionTableError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the occupied slots of the table in a binary format. Header numbers are
+    /// little-endian, and entries are in the native layout of the machine that wrote them.
+    /// Slots are indexed as if the buckets were laid end to end.
+    ///
+    /// ```text
+    /// Header: "TKTT", version: u8, size: u8, Zobrist seed: u64, capacity: u64,
+    ///         generation: u16, entries: u64
+    /// Entry:  index: u64, hash: u64, entry: u64
+    /// ```
+    ///
+    /// Only tables using the fixed Zobrist keys can be saved, since random keys would
+    /// make the hashes meaningless in another process.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<(), TranspositionTableError> {
+        let seed = zobrist_seed().ok_or_else(|| {
+            TranspositionTableError::IncorrectKeys(
+                "Zobrist keys are random, so the table could not be loaded again.".to_owned(),
+            )
+        })?;
+
+        let slots: Vec<_> = self
+            .slots()
+            .enumerate()
+            .filter_map(|(index, slot)| Some((index, slot.load()?)))
+            .collect();
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, N as u8])?;
+        writer.write_all(&seed.to_le_bytes())?;
+        writer.write_all(&(self.capacity() as u64).to_le_bytes())?;
+        writer.write_all(&self.generation().to_le_bytes())?;
+        writer.write_all(&(slots.len() as u64).to_le_bytes())?;
+
+        for (index, slot) in slots {
+            writer.write_all(&(index as u64).to_le_bytes())?;
+            writer.write_all(&slot.hash.to_le_bytes())?;
+            writer.write_all(&slot.entry.to_bits().to_ne_bytes())?;
         }
 
-        None
+        Ok(())
     }
+
+    /// Reads a table written by `write_to`, with the capacity it was saved with.
+    pub fn read_from(mut reader: impl Read) -> Result<Self, TranspositionTableError> {
+        let invalid = |message: &str| TranspositionTableError::InvalidFormat(message.to_owned());
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not a transposition table."));
+        }
+
+        let [version, size] = read_bytes(&mut reader)?;
+        if version != VERSION {
+            return Err(TranspositionTableError::InvalidFormat(format!(
+                "Unsupported transposition table version {version}."
+            )));
+        }
+        if size as usize != N {
+            return Err(TranspositionTableError::IncorrectSize(format!(
+                "Transposition table size is {size} but requested size is {N}."
+            )));
+        }
+
+        let seed = u64::from_le_bytes(read_bytes(&mut reader)?);
+        if zobrist_seed() != Some(seed) {
+            return Err(TranspositionTableError::IncorrectKeys(format!(
+                "Transposition table was saved with Zobrist seed {seed}."
+            )));
+        }
+
+        let capacity = u64::from_le_bytes(read_bytes(&mut reader)?) as usize;
+        let generation = u16::from_le_bytes(read_bytes(&mut reader)?);
+        let len = u64::from_le_bytes(read_bytes(&mut reader)?) as usize;
+
+        // Check the capacity before allocating, since it could be anything.
+        if !capacity.is_multiple_of(BUCKET_SIZE)
+            || !(capacity / BUCKET_SIZE).is_power_of_two()
+            || capacity > Self::capacity_for_size(MAX_LOADED_TABLE_SIZE)
+            || len > capacity
+        {
+            return Err(invalid("Invalid capacity."));
+        }
+        if generation >= ENTRY_GENERATIONS {
+            return Err(invalid("Invalid generation."));
+        }
+
+        let table = Self::with_capacity(capacity);
+
+        for _ in 0..len {
+            let index = u64::from_le_bytes(read_bytes(&mut reader)?) as usize;
+            let hash = u64::from_le_bytes(read_bytes(&mut reader)?);
+            let entry =
+                TranspositionTableEntry::from_bits(u64::from_ne_bytes(read_bytes(&mut reader)?));
+
+            // Check the bound and ply here, since reading invalid ones later would panic.
+            if entry.info.0 >> 14 > Bound::Exact as u16 || Ply::<N>::try_from(entry.ply).is_err() {
+                return Err(invalid("Invalid entry."));
+            }
+
+            // Entries go back in the same slots, since inserting them again could
+            // replace some with others from the same bucket.
+            let slot = table
+                .buckets
+                .get(index / BUCKET_SIZE)
+                .map(|bucket| &bucket.slots[index % BUCKET_SIZE])
+                .ok_or_else(|| invalid("Invalid index."))?;
+            if slot.load().is_some() {
+                return Err(invalid("Duplicate index."));
+            }
+            slot.store(hash, entry);
+        }
+        table.len.store(len, Ordering::Release);
+        table.generation.store(generation, Ordering::Release);
+
+        Ok(table)
+    }
+}
+
+fn read_bytes<const B: usize>(reader: &mut impl Read) -> Result<[u8; B], TranspositionTableError> {
+    let mut bytes = [0; B];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
 }
 
 impl<const N: usize> fmt::Debug for TranspositionTable<N> {
@@ -106,25 +372,32 @@ impl<const N: usize> fmt::Debug for TranspositionTable<N> {
         f.debug_struct("TranspositionTable")
             .field("occupancy", &self.len)
             .field("capacity", &self.capacity())
+            .field("generation", &self.generation)
             .finish()
     }
 }
 
 impl<const N: usize> TranspositionTable<N> {
     fn index(&self, hash: ZobristHash) -> usize {
-        hash as usize % self.capacity()
+        hash as usize & (self.buckets.len() - 1)
     }
 
-    fn next_index(&self, index: usize) -> usize {
-        let next = index + 1;
-        if next < self.capacity() {
-            next
-        } else {
-            next - self.capacity()
-        }
+    /// Every slot in the table, with the buckets laid end to end.
+    fn slots(&self) -> impl Iterator<Item = &Slot<N>> {
+        self.buckets.iter().flat_map(|bucket| &bucket.slots)
     }
 }
 
+/// Slots for entries that share an index, aligned so that they fill one cache line.
+#[repr(align(64))]
+#[derive(Debug, Default)]
+struct Bucket<const N: usize> {
+    slots: [Slot<N>; BUCKET_SIZE],
+}
+
+/// An entry stored without locks. The key is the hash XORed with the data, so an entry
+/// torn by writes from two threads fails to match either hash instead of being read as
+/// the wrong position's.
 #[derive(Debug, Default)]
 struct Slot<const N: usize> {
     key: AtomicU64,
@@ -146,6 +419,11 @@ impl<const N: usize> Slot<N> {
         }
     }
 
+    fn clear(&self) {
+        self.key.store(0, Ordering::Release);
+        self.data.store(0, Ordering::Release);
+    }
+
     fn store(&self, hash: ZobristHash, entry: TranspositionTableEntry<N>) {
         let data = entry.to_bits();
         let key = hash ^ data;
@@ -193,17 +471,12 @@ pub struct TranspositionTableEntry<const N: usize> {
 }
 
 impl<const N: usize> TranspositionTableEntry<N> {
-    pub fn new(
-        ply: Ply<N>,
-        evaluation: Evaluation,
-        bound: Bound,
-        depth: usize,
-        ply_count: u16,
-    ) -> Self {
+    /// Creates an entry, which is stamped with the table's generation when it's stored.
+    pub fn new(ply: Ply<N>, evaluation: Evaluation, bound: Bound, depth: usize) -> Self {
         Self {
             ply: ply.into(),
             evaluation,
-            info: EntryInfo::new(bound, depth, ply_count),
+            info: EntryInfo::new(bound, depth, 0),
         }
     }
 
@@ -223,8 +496,16 @@ impl<const N: usize> TranspositionTableEntry<N> {
         self.info.depth()
     }
 
-    pub fn ply_count(&self) -> u16 {
-        self.info.ply_count()
+    /// The generation of the search that stored the entry.
+    pub fn generation(&self) -> u16 {
+        self.info.generation()
+    }
+
+    fn with_generation(self, generation: u16) -> Self {
+        Self {
+            info: EntryInfo::new(self.bound(), self.depth(), generation),
+            ..self
+        }
     }
 }
 
@@ -242,33 +523,30 @@ impl<const N: usize> TranspositionTableEntry<N> {
     }
 }
 
-/// Bit-packed bound, depth, and ply_count information. Representation:
+/// Bit-packed bound, depth, and generation information. Representation:
 /// ```text
-///     Bound  ┊  Depth   ┊   Ply count
+///     Bound  ┊  Depth   ┊   Generation
 ///       ├─┐ ┌─────┴─┐ ┌───────┴───────┐
-/// MSB - b b d d d d d p p p p p p p p p - LSB
+/// MSB - b b d d d d d g g g g g g g g g - LSB
 /// ```
-/// This does impose limits on the possible depth and ply count.
-/// The maximum depth is 32, and the maximum ply count is 511.
+/// This does impose limits on the possible depth and generation.
+/// The maximum depth is 32, and generations wrap around after 511.
 #[derive(Clone, Copy, Eq, PartialEq)]
 struct EntryInfo(u16);
 
 const ENTRY_MAX_DEPTH: usize = 32;
-const ENTRY_MAX_PLY_COUNT: u16 = 511;
+const ENTRY_GENERATIONS: u16 = 512;
 
 impl EntryInfo {
-    fn new(bound: Bound, depth: usize, ply_count: u16) -> Self {
+    fn new(bound: Bound, depth: usize, generation: u16) -> Self {
         assert!(depth > 0, "transposition table entry depth cannot be 0");
         assert!(
             depth <= ENTRY_MAX_DEPTH,
             "transposition table entry depth cannot be greater than {ENTRY_MAX_DEPTH}"
         );
-        assert!(
-            ply_count <= ENTRY_MAX_PLY_COUNT,
-            "transposition table entry ply count cannot be greater than {ENTRY_MAX_PLY_COUNT}"
-        );
+        debug_assert!(generation < ENTRY_GENERATIONS);
 
-        Self(((bound as u16) << 14) | ((depth as u16 - 1) << 9) | ply_count)
+        Self(((bound as u16) << 14) | ((depth as u16 - 1) << 9) | generation)
     }
 
     fn bound(self) -> Bound {
@@ -281,7 +559,7 @@ impl EntryInfo {
         ((self.0 & 0x3E00) >> 9) as usize + 1
     }
 
-    fn ply_count(self) -> u16 {
+    fn generation(self) -> u16 {
         self.0 & 0x01FF
     }
 }
@@ -291,7 +569,7 @@ impl fmt::Debug for EntryInfo {
         f.debug_struct("EntryInfo")
             .field("bound", &self.bound())
             .field("depth", &self.depth())
-            .field("ply_count", &self.ply_count())
+            .field("generation", &self.generation())
             .finish()
     }
 }
@@ -312,19 +590,11 @@ mod tests {
             0.0.into(),
             Bound::Exact,
             value,
-            0,
         )
     }
 
-    fn test_slot<const N: usize>(hash: ZobristHash, value: usize) -> Option<LoadedSlot<N>> {
-        Some(LoadedSlot {
-            hash,
-            entry: test_entry(value),
-        })
-    }
-
-    fn load<const N: usize>(values: &[Slot<N>]) -> Vec<Option<LoadedSlot<N>>> {
-        values.iter().map(|slot| slot.load()).collect()
+    fn load<const N: usize>(tt: &TranspositionTable<N>) -> Vec<Option<LoadedSlot<N>>> {
+        tt.slots().map(|slot| slot.load()).collect()
     }
 
     #[test]
@@ -397,120 +667,234 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bucket_fills_a_cache_line() {
+        assert_eq!(mem::size_of::<Bucket<5>>(), 64);
+        assert_eq!(mem::align_of::<Bucket<5>>(), 64);
+    }
+
     #[test]
     fn insert_and_get() {
-        // Probe to find an empty slot.
-        let tt = TranspositionTable::<6>::with_capacity(10);
-        assert!(tt.insert(3, test_entry(1)));
-        assert!(tt.insert(13, test_entry(2)));
-        assert_eq!(
-            load(&tt.values),
-            vec![
-                None,
-                None,
-                None,
-                test_slot(3, 1),
-                test_slot(13, 2),
-                None,
-                None,
-                None,
-                None,
-                None
-            ]
-        );
+        // 4 buckets of 4, so hashes 1, 5, 9, 13 and so on share a bucket.
+        let tt = TranspositionTable::<6>::with_capacity(16);
+        assert_eq!(tt.capacity(), 16);
 
-        // Overwrite entries with the same hash but a higher score.
-        assert!(tt.insert(3, test_entry(3)));
-        assert_eq!(
-            load(&tt.values),
-            vec![
-                None,
-                None,
-                None,
-                test_slot(3, 3),
-                test_slot(13, 2),
-                None,
-                None,
-                None,
-                None,
-                None
-            ]
-        );
+        for (hash, depth) in [(1, 1), (5, 2), (9, 3), (13, 4)] {
+            assert!(tt.insert(hash, test_entry(depth)));
+        }
+        assert_eq!(tt.len(), 4);
+        assert_eq!(tt.probe(9), Some(test_entry(3)));
+        assert_eq!(tt.probe(2), None);
+        assert_eq!(tt.probe(17), None);
+
+        // Entries with the same hash are only replaced by deeper ones.
+        assert!(!tt.insert(5, test_entry(1)));
+        assert_eq!(tt.probe(5), Some(test_entry(2)));
+        assert!(tt.insert(5, test_entry(5)));
+        assert_eq!(tt.probe(5), Some(test_entry(5)));
 
-        assert!(tt.insert(23, test_entry(4)));
-        assert!(tt.insert(33, test_entry(5)));
-        assert!(tt.insert(43, test_entry(6)));
+        // A full bucket replaces its shallowest entry.
+        assert!(tt.insert(17, test_entry(2)));
+        assert_eq!(tt.len(), 4);
+        assert_eq!(tt.probe(1), None);
+        assert_eq!(tt.probe(17), Some(test_entry(2)));
 
-        // When the probe depth is reached, insert should replace the lowest score entry.
-        assert!(tt.insert(53, test_entry(1)));
+        // Other buckets are untouched.
+        assert!(tt.insert(2, test_entry(1)));
+        assert_eq!(tt.len(), 5);
+        assert_eq!(tt.probe(2), Some(test_entry(1)));
+    }
+
+    #[test]
+    fn stale_entries_are_replaced_first() {
+        let tt = TranspositionTable::<6>::with_capacity(16);
+
+        for (hash, depth) in [(1, 1), (5, 8), (9, 7), (13, 6)] {
+            tt.insert(hash, test_entry(depth));
+        }
+
+        tt.new_search();
+        assert_eq!(tt.generation(), 1);
+        assert_eq!(tt.age(&tt.probe(5).unwrap()), 1);
+
+        // Failing to replace an entry from an earlier search still keeps it around.
+        assert!(!tt.insert(9, test_entry(1)));
+        assert_eq!(tt.probe(9).unwrap().depth(), 7);
+        assert_eq!(tt.age(&tt.probe(9).unwrap()), 0);
+
+        // The stale entries go first, shallowest first, even if they're deeper than the
+        // current ones.
+        assert!(tt.insert(17, test_entry(2)));
+        assert_eq!(tt.probe(1), None);
+        assert!(tt.insert(21, test_entry(2)));
+        assert_eq!(tt.probe(13), None);
+        assert!(tt.insert(25, test_entry(3)));
+        assert_eq!(tt.probe(5), None);
+
+        // Then the shallowest current entry.
+        assert!(tt.insert(29, test_entry(3)));
+        assert_eq!(tt.probe(17), None);
+        assert!(tt.probe(9).is_some());
+        assert!(tt.probe(21).is_some());
+    }
+
+    #[test]
+    fn generations_wrap_around() {
+        let tt = TranspositionTable::<5>::with_capacity(4);
+        tt.insert(1, test_entry(1));
+
+        for _ in 0..ENTRY_GENERATIONS - 1 {
+            tt.new_search();
+        }
+        assert_eq!(tt.generation(), ENTRY_GENERATIONS - 1);
+        assert_eq!(tt.age(&tt.probe(1).unwrap()), ENTRY_GENERATIONS - 1);
+
+        tt.new_search();
+        assert_eq!(tt.generation(), 0);
+        assert_eq!(tt.age(&tt.probe(1).unwrap()), 0);
+    }
+
+    #[test]
+    fn stats_and_hashfull() {
+        let tt = TranspositionTable::<5>::with_capacity(16);
+        assert_eq!(tt.hashfull(), 0);
         assert_eq!(
-            load(&tt.values),
-            vec![
-                None,
-                None,
-                None,
-                test_slot(3, 3),
-                test_slot(53, 1),
-                test_slot(23, 4),
-                test_slot(33, 5),
-                test_slot(43, 6),
-                None,
-                None
-            ]
+            tt.stats(),
+            TranspositionTableStats {
+                capacity: 16,
+                ..Default::default()
+            }
         );
 
-        // Adjacent indices probe further.
-        assert!(tt.insert(4, test_entry(2)));
-        assert_eq!(
-            load(&tt.values),
-            vec![
-                None,
-                None,
-                None,
-                test_slot(3, 3),
-                test_slot(53, 1),
-                test_slot(23, 4),
-                test_slot(33, 5),
-                test_slot(43, 6),
-                test_slot(4, 2),
-                None
-            ]
+        tt.insert(1, test_entry(3));
+        tt.insert(2, test_entry(3));
+        tt.new_search();
+        tt.insert(3, test_entry(1));
+        tt.insert(
+            4,
+            TranspositionTableEntry::new(
+                Ply::Place {
+                    x: 1,
+                    y: 2,
+                    piece_type: PieceType::Capstone,
+                },
+                0.5.into(),
+                Bound::Lower,
+                2,
+            ),
         );
 
-        // Probed indices wrap around.
-        assert!(tt.insert(9, test_entry(1)));
-        assert!(tt.insert(19, test_entry(2)));
-        assert!(tt.insert(29, test_entry(3)));
+        assert_eq!(tt.hashfull(), 250);
         assert_eq!(
-            load(&tt.values),
-            vec![
-                test_slot(19, 2),
-                test_slot(29, 3),
-                None,
-                test_slot(3, 3),
-                test_slot(53, 1),
-                test_slot(23, 4),
-                test_slot(33, 5),
-                test_slot(43, 6),
-                test_slot(4, 2),
-                test_slot(9, 1),
-            ]
+            tt.stats(),
+            TranspositionTableStats {
+                entries: 4,
+                capacity: 16,
+                lower_bounds: 1,
+                upper_bounds: 0,
+                exact: 3,
+                depths: BTreeMap::from([(1, 1), (2, 1), (3, 2)]),
+                ages: BTreeMap::from([(0, 2), (1, 2)]),
+            }
         );
 
-        // Get works when there's an exact match right away.
-        assert_eq!(tt.get(3), Some(test_entry(3)));
+        let entry = tt.probe(4).unwrap();
+        assert_eq!(entry.ply(), "Cb3".parse().unwrap());
+        assert_eq!(entry.evaluation(), 0.5.into());
+        assert_eq!(entry.bound(), Bound::Lower);
+        assert_eq!(entry.depth(), 2);
+        assert_eq!(entry.generation(), 1);
+    }
+
+    #[test]
+    fn clear_and_resize() {
+        let mut tt = TranspositionTable::<5>::with_size(1);
+        assert_eq!(tt.size(), 1);
+        assert_eq!(tt.capacity(), 1024 * 1024 / mem::size_of::<Slot<5>>());
+
+        tt.insert(3, test_entry(1));
+        tt.insert(13, test_entry(2));
+        tt.clear();
+        assert!(tt.is_empty());
+        assert_eq!(tt.probe(3), None);
+
+        tt.insert(3, test_entry(1));
+        tt.resize(2);
+        assert_eq!(tt.size(), 2);
+        assert!(tt.is_empty());
+        assert_eq!(tt.probe(3), None);
+
+        assert!(tt.insert(3, test_entry(1)));
+        assert_eq!(tt.probe(3), Some(test_entry(1)));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let tt = TranspositionTable::<5>::with_capacity(10);
+        assert_eq!(tt.capacity(), 8);
 
-        // Get works when we have to probe for it.
-        assert_eq!(tt.get(33), Some(test_entry(5)));
+        for (hash, depth) in [(3, 1), (13, 2), (24, 3)] {
+            tt.insert(hash, test_entry(depth));
+        }
+        tt.new_search();
+        for (hash, depth) in [(9, 4), (20, 5)] {
+            tt.insert(hash, test_entry(depth));
+        }
 
-        // Get returns none when there's an empty slot.
-        assert_eq!(tt.get(2), None);
+        let mut bytes = Vec::new();
+        tt.write_to(&mut bytes).unwrap();
+
+        let read = TranspositionTable::<5>::read_from(bytes.as_slice()).unwrap();
+        assert_eq!(read.capacity(), 8);
+        assert_eq!(read.len(), 5);
+        assert_eq!(read.generation(), 1);
+        assert_eq!(load(&read), load(&tt));
+        assert_eq!(read.age(&read.probe(3).unwrap()), 1);
+
+        assert!(matches!(
+            TranspositionTable::<6>::read_from(bytes.as_slice()),
+            Err(TranspositionTableError::IncorrectSize(_))
+        ));
+        assert!(matches!(
+            TranspositionTable::<5>::read_from(&bytes[..bytes.len() - 1]),
+            Err(TranspositionTableError::IoError(_))
+        ));
+
+        // Change the capacity in the header.
+        for capacity in [0, 6, 12, 1 << 62, u64::MAX] {
+            let mut bytes = bytes.clone();
+            bytes[14..22].copy_from_slice(&capacity.to_le_bytes());
+            assert!(matches!(
+                TranspositionTable::<5>::read_from(bytes.as_slice()),
+                Err(TranspositionTableError::InvalidFormat(_))
+            ));
+        }
 
-        // Get returns none when no match is found within the probe range.
-        assert_eq!(tt.get(63), None);
+        // Change the ply of the first entry, which is written after its index and hash. The
+        // first is a placement of an invalid piece type, and the second flips every bit of
+        // the original placement, making a spread off the board.
+        let flip_all = |ply: PackedPly| PackedPly::from_bits(!ply.to_bits());
+        let invalid_piece = |_| PackedPly::from_bits(0b11000000_11000000);
+        for corrupt in [invalid_piece, flip_all] {
+            let mut bytes = bytes.clone();
+            let range = 48..56;
+            let mut entry = TranspositionTableEntry::<5>::from_bits(u64::from_ne_bytes(
+                bytes[range.clone()].try_into().unwrap(),
+            ));
+            entry.ply = corrupt(entry.ply);
+            bytes[range].copy_from_slice(&entry.to_bits().to_ne_bytes());
+            assert!(matches!(
+                TranspositionTable::<5>::read_from(bytes.as_slice()),
+                Err(TranspositionTableError::InvalidFormat(_))
+            ));
+        }
 
-        // Get works when probing has to wrap around.
-        assert_eq!(tt.get(29), Some(test_entry(3)));
+        // Change the seed in the header.
+        bytes[6] ^= 1;
+        assert!(matches!(
+            TranspositionTable::<5>::read_from(bytes.as_slice()),
+            Err(TranspositionTableError::IncorrectKeys(_))
+        ));
     }
 
     #[test]
@@ -519,6 +903,6 @@ mod tests {
         assert_eq!(entry_info.0, 0xBFFF);
         assert_eq!(entry_info.bound(), Bound::Exact);
         assert_eq!(entry_info.depth(), 32);
-        assert_eq!(entry_info.ply_count(), 511);
+        assert_eq!(entry_info.generation(), 511);
     }
 }
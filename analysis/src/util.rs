@@ -60,7 +60,7 @@ impl<const N: usize> TryFrom<PackedPly> for Ply<N> {
                 y: packed.1 & 0x07,
                 piece_type: (0x01 << ((packed.1 >> 6) + 4))
                     .try_into()
-                    .expect("invalid packed piece type"),
+                    .map_err(|_| PlyError::InvalidPieceType)?,
             }
         } else {
             Ply::Spread {
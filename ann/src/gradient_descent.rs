@@ -162,7 +162,7 @@ mod tests {
 
         let mut w = MatrixColumnMajor::<1, 1>::zeros();
 
-        let mut simple = SimpleGradientDescent::default();
+        let mut simple = SimpleGradientDescent;
 
         for t in 1..=500 {
             let mut gradient = w;
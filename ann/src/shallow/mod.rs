@@ -1,5 +1,6 @@
 pub use self::ann::ShallowAnn;
 pub use self::gradient_descent::{ShallowAdam, ShallowGradientDescent};
+pub use crate::gradient_descent::SimpleGradientDescent;
 
 mod ann;
 mod gradient_descent;
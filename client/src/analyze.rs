@@ -1,25 +1,37 @@
 use std::fmt::Write;
 use std::fs::File;
+use std::io;
 use std::mem;
+use std::path::Path;
 use std::time::Duration;
 
 use tracing::error;
 
 use analysis::evaluation::{AnnEvaluator, AnnModel, Evaluator};
-use analysis::{analyze, AnalysisConfig};
-use tak::{PtnGame, PtnHeader, State, Tps};
+use analysis::{analyze, AnalysisConfig, BookError, OpeningBook, PersistentState};
+use tak::{Ply, PtnError, PtnGame, PtnHeader, PtnPly, PtnReader, RepetitionRule, State, Tps};
 
 use crate::args::{Ai, AnalyzeConfig};
 
 pub fn run_analysis(config: AnalyzeConfig) {
-    let game = match (&config.file, &config.tps) {
-        (Some(filename), None) => match PtnGame::from_file(filename) {
-            Ok(game) => game,
-            Err(err) => {
-                error!(error = ?err, "Invalid PTN file.");
-                return;
+    match (&config.file, &config.tps) {
+        (Some(filename), None) => {
+            let games = match read_games(filename) {
+                Ok(games) => games,
+                Err(err) => {
+                    error!(error = ?err, "Could not open PTN file.");
+                    return;
+                }
+            };
+
+            // Each game in a database is analyzed in turn, skipping any that are invalid.
+            for (i, game) in games.enumerate() {
+                match game {
+                    Ok(game) => run_analysis_game(&config, game),
+                    Err(err) => error!(game = i + 1, error = ?err, "Invalid PTN game."),
+                }
             }
-        },
+        }
         (None, Some(tps_string)) => {
             let tps = match tps_string.parse::<Tps>() {
                 Ok(tps) => tps,
@@ -29,14 +41,29 @@ pub fn run_analysis(config: AnalyzeConfig) {
                 }
             };
 
-            PtnGame {
+            let game = PtnGame {
                 headers: vec![PtnHeader::new("TPS", tps)],
                 ..Default::default()
-            }
+            };
+
+            run_analysis_game(&config, game);
         }
         _ => unreachable!(),
-    };
+    }
+}
 
+/// Reads the games in a PTN file, or from stdin if the filename is "-".
+pub fn read_games(
+    filename: &str,
+) -> Result<Box<dyn Iterator<Item = Result<PtnGame, PtnError>>>, io::Error> {
+    if filename == "-" {
+        Ok(Box::new(PtnReader::new(io::stdin().lock())))
+    } else {
+        Ok(Box::new(PtnReader::open(filename)?))
+    }
+}
+
+fn run_analysis_game(config: &AnalyzeConfig, game: PtnGame) {
     if let Some(size) = game.get_size() {
         match size {
             3 => run_analysis_sized::<3>(config, game),
@@ -52,7 +79,14 @@ pub fn run_analysis(config: AnalyzeConfig) {
     }
 }
 
-fn run_analysis_sized<const N: usize>(config: AnalyzeConfig, game: PtnGame) {
+fn run_analysis_sized<const N: usize>(config: &AnalyzeConfig, game: PtnGame) {
+    let history = match game.get_history::<N>(RepetitionRule::default()) {
+        Ok(history) => history,
+        Err(err) => {
+            error!(error = ?err, "Could not create history.");
+            return;
+        }
+    };
     let state: State<N> = match game.try_into() {
         Ok(state) => state,
         Err(err) => {
@@ -68,9 +102,50 @@ fn run_analysis_sized<const N: usize>(config: AnalyzeConfig, game: PtnGame) {
         exact_eval,
         threads,
         model_file,
-    } = config.ai;
+        book_file,
+        book_selection,
+        hash_size,
+        deterministic,
+        quiescence_depth,
+        aspiration_window,
+        ..
+    } = config.ai.clone();
+
+    let parse_plies = |plies: &[String]| {
+        plies
+            .iter()
+            .map(|ply| ply.parse::<Ply<N>>())
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let (search_plies, excluded_plies) = match (
+        parse_plies(&config.search_plies),
+        parse_plies(&config.excluded_plies),
+    ) {
+        (Ok(search_plies), Ok(excluded_plies)) => (search_plies, excluded_plies),
+        (Err(err), _) | (_, Err(err)) => {
+            error!(error = ?err, "Invalid ply.");
+            return;
+        }
+    };
 
-    let evaluator = model_file.as_deref().map(load_model);
+    let evaluator = match model_file.as_deref().map(load_model).transpose() {
+        Ok(evaluator) => evaluator,
+        Err(err) => {
+            error!(error = %err, "Could not load model.");
+            return;
+        }
+    };
+    let book = match book_file.as_deref().map(load_book).transpose() {
+        Ok(book) => book,
+        Err(err) => {
+            error!(error = ?err, "Could not load opening book.");
+            return;
+        }
+    };
+    let persistent_state = config
+        .tt_file
+        .as_deref()
+        .and_then(|tt_file| load_persistent_state(tt_file, hash_size));
 
     let analysis_config = AnalysisConfig::<N> {
         depth_limit,
@@ -79,11 +154,28 @@ fn run_analysis_sized<const N: usize>(config: AnalyzeConfig, game: PtnGame) {
         exact_eval,
         evaluator: evaluator.as_deref(),
         threads,
+        book: book.as_ref(),
+        book_selection,
+        persistent_state: persistent_state.as_ref(),
+        table_size: hash_size,
+        multi_pv: config.multi_pv as usize,
+        included_plies: (!search_plies.is_empty()).then_some(search_plies),
+        excluded_plies,
+        deterministic,
+        quiescence_depth,
+        aspiration_window: aspiration_window.map(Into::into),
+        history: Some(&history),
         ..Default::default()
     };
 
     let analysis = analyze(analysis_config, &state);
 
+    if let (Some(tt_file), Some(persistent_state)) = (&config.tt_file, &persistent_state) {
+        if let Err(err) = persistent_state.save(tt_file) {
+            error!(error = ?err, "Could not save transposition table.");
+        }
+    }
+
     let game = {
         let tps: Tps = state.clone().into();
 
@@ -115,6 +207,24 @@ fn run_analysis_sized<const N: usize>(config: AnalyzeConfig, game: PtnGame) {
         println!("  {result}");
     }
 
+    if analysis.lines.len() > 1 {
+        println!("\nLines:");
+        for (i, line) in analysis.lines.iter().enumerate() {
+            let mut state = state.clone();
+            let plies = line
+                .principal_variation
+                .iter()
+                .map(|&ply| {
+                    let validation = state.execute_ply(ply).expect("invalid ply in line");
+                    PtnPly::from((ply, validation)).to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            println!("  {:>2}. {:<6} {plies}", i + 1, line.evaluation);
+        }
+    }
+
     println!("\nStatistics:");
     println!("  Depth: {} plies", analysis.depth);
     println!("  Time: {}", format_time(analysis.time));
@@ -128,6 +238,10 @@ fn run_analysis_sized<const N: usize>(config: AnalyzeConfig, game: PtnGame) {
         punctuate(analysis.stats.evaluated),
         punctuate((analysis.stats.evaluated as f64 / analysis.time.as_secs_f64()) as u64),
     );
+    println!(
+        "  Hash full: {:>12.1}%",
+        f64::from(analysis.hashfull) / 10.0
+    );
 
     let resulting_state: State<N> = match game.try_into() {
         Ok(state) => state,
@@ -177,7 +291,7 @@ fn punctuate(value: u64) -> String {
     for (i, d) in value.chars().enumerate() {
         let x = value.len() - i;
 
-        if x % 3 == 0 && i > 0 && x > 0 {
+        if x.is_multiple_of(3) && i > 0 && x > 0 {
             write!(buffer, ",").unwrap();
         }
         write!(buffer, "{d}").unwrap();
@@ -186,19 +300,21 @@ fn punctuate(value: u64) -> String {
     buffer
 }
 
-pub fn load_model<const N: usize>(model_file: &str) -> Box<dyn Evaluator<N>> {
+pub fn load_model<const N: usize>(model_file: &str) -> Result<Box<dyn Evaluator<N>>, String> {
     macro_rules! sized {
         ($size:expr) => {{
-            let file = File::open(model_file).expect("could not open model file");
+            let file = File::open(model_file)
+                .map_err(|err| format!("Could not open model file {model_file:?}: {err}."))?;
 
             let evaluator: <AnnModel<$size> as AnnEvaluator<$size>>::Evaluator =
-                serde_json::from_reader(file).expect("could not deserialize model");
+                serde_json::from_reader(file)
+                    .map_err(|err| format!("Could not read model {model_file:?}: {err}."))?;
 
             Box::new(evaluator) as Box<dyn Evaluator<$size>>
         }};
     }
 
-    match N {
+    Ok(match N {
         3 => cast_size(sized!(3)),
         4 => cast_size(sized!(4)),
         5 => cast_size(sized!(5)),
@@ -206,6 +322,29 @@ pub fn load_model<const N: usize>(model_file: &str) -> Box<dyn Evaluator<N>> {
         7 => cast_size(sized!(7)),
         8 => cast_size(sized!(8)),
         _ => unreachable!(),
+    })
+}
+
+pub fn load_book<const N: usize>(book_file: &str) -> Result<OpeningBook<N>, BookError> {
+    OpeningBook::from_file(book_file)
+}
+
+/// Loads the transposition table from a file if it exists, or starts a new one of the
+/// given size. Returns none if the file can't be used, so that it isn't overwritten.
+fn load_persistent_state<const N: usize>(
+    tt_file: &str,
+    table_size: usize,
+) -> Option<PersistentState<N>> {
+    if !Path::new(tt_file).exists() {
+        return Some(PersistentState::with_table_size(table_size));
+    }
+
+    match PersistentState::load(tt_file) {
+        Ok(persistent_state) => Some(persistent_state),
+        Err(err) => {
+            error!(error = ?err, "Could not load transposition table.");
+            None
+        }
     }
 }
 
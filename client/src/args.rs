@@ -6,6 +6,10 @@ use clap::{
     Arg, ArgAction, ArgGroup, ArgMatches, Args as ArgsTrait, FromArgMatches, Parser, Subcommand,
 };
 
+use analysis::{
+    BookSelection, TimeControl, DEFAULT_ASPIRATION_WINDOW, DEFAULT_QUIESCENCE_DEPTH,
+    DEFAULT_TABLE_SIZE,
+};
 use tak::{Color, Komi};
 
 #[derive(Debug, Parser)]
@@ -21,8 +25,14 @@ pub enum Command {
     Play(PlayConfig),
     /// Analyzes a given position.
     Analyze(AnalyzeConfig),
-    /// Runs in TEI mode, using the limited subset of TEI that is supported by Racetrack. (https://github.com/MortenLohne/racetrack)
+    /// Runs in TEI mode, for use with engine managers such as Racetrack. (https://github.com/MortenLohne/racetrack)
     Tei(TeiConfig),
+    /// Counts the positions reachable from a given position at a fixed depth, split by the first ply.
+    Perft(PerftConfig),
+    /// Builds an opening book from games in PTN format.
+    Book(BookConfig),
+    /// Searches a position for a forced road win for the player to move.
+    Tinue(TinueConfig),
 }
 
 #[derive(ArgsTrait, Clone, Debug)]
@@ -38,6 +48,13 @@ pub struct PlayConfig {
     #[arg(short, long, verbatim_doc_comment)]
     pub file: Option<String>,
 
+    /// A clock for each player, who loses if their time runs out. The time is in minutes:
+    /// 10 for sudden death, 10+5 for a 5 second increment, 10d5 for a simple delay,
+    /// 10b5 for a Bronstein delay, 10y30 for 30 seconds of byo-yomi, or 40/90+30 for
+    /// 90 minutes every 40 moves.
+    #[arg(long, verbatim_doc_comment)]
+    pub time_control: Option<TimeControl>,
+
     #[command(flatten)]
     pub p1: Player1,
 
@@ -48,7 +65,8 @@ pub struct PlayConfig {
 #[derive(ArgsTrait, Clone, Debug)]
 #[command(group(ArgGroup::new("input").required(true).args(["file", "tps"])))]
 pub struct AnalyzeConfig {
-    /// The name of a file in PTN format to analyze.
+    /// The name of a file in PTN format to analyze, or "-" to read from stdin.
+    /// The file may contain many games, which are analyzed in turn.
     #[arg(short, long, verbatim_doc_comment)]
     pub file: Option<String>,
 
@@ -56,10 +74,83 @@ pub struct AnalyzeConfig {
     #[arg(short, long, verbatim_doc_comment)]
     pub tps: Option<String>,
 
+    /// A file to keep the transposition table in between runs. It is loaded before
+    /// analyzing if it exists, and saved afterwards, so deep analysis can be resumed.
+    #[arg(long, verbatim_doc_comment)]
+    pub tt_file: Option<String>,
+
+    /// The number of best moves to show lines for.
+    #[arg(long = "multipv", verbatim_doc_comment, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub multi_pv: u64,
+
+    /// Only consider these moves, in PTN, from the analyzed position.
+    #[arg(long = "searchmoves", verbatim_doc_comment, num_args = 1.., value_name = "PLY")]
+    pub search_plies: Vec<String>,
+
+    /// Don't consider these moves, in PTN, from the analyzed position.
+    #[arg(long = "excludemoves", verbatim_doc_comment, num_args = 1.., value_name = "PLY")]
+    pub excluded_plies: Vec<String>,
+
     #[command(flatten)]
     pub ai: Ai,
 }
 
+#[derive(ArgsTrait, Clone, Debug)]
+#[command(group(ArgGroup::new("position").required(true).args(["tps", "size"])))]
+pub struct PerftConfig {
+    /// A position in TPS format to start from.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub tps: Option<String>,
+
+    /// The board size, to start from an empty board.
+    #[arg(short, long, verbatim_doc_comment, value_parser = clap::value_parser!(u8).range(3..=8))]
+    pub size: Option<u8>,
+
+    /// The number of plies to search.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub depth: usize,
+}
+
+#[derive(ArgsTrait, Clone, Debug)]
+pub struct TinueConfig {
+    /// The position to search, in TPS format.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub tps: String,
+
+    /// The most moves to search, counting only the winning player's moves.
+    #[arg(short, long, verbatim_doc_comment, default_value_t = 5)]
+    pub moves: usize,
+}
+
+#[derive(ArgsTrait, Clone, Debug)]
+pub struct BookConfig {
+    /// Files in PTN format to read games from, or "-" to read from stdin.
+    /// Each file may contain many games. Games without a result are skipped.
+    #[arg(required = true, verbatim_doc_comment)]
+    pub files: Vec<String>,
+
+    /// The file to write the opening book to.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub output: String,
+
+    /// The board size of the book. Games of other sizes are skipped.
+    #[arg(short, long, verbatim_doc_comment, value_parser = clap::value_parser!(u8).range(3..=8))]
+    pub size: u8,
+
+    /// The number of plies from the start of each game to record.
+    #[arg(short, long, verbatim_doc_comment, default_value_t = 16)]
+    pub plies: usize,
+
+    /// Leave out plies that were played in fewer than this many games.
+    #[arg(short, long, verbatim_doc_comment, default_value_t = 1)]
+    pub min_games: u32,
+
+    /// Store positions in their canonical orientation, so that all rotations
+    /// and reflections of a position share statistics.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub canonical: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct TeiConfig {
     pub ai: Ai,
@@ -277,19 +368,46 @@ pub struct Ai {
     pub exact_eval: bool,
     pub threads: usize,
     pub model_file: Option<String>,
+    pub book_file: Option<String>,
+    pub book_selection: BookSelection,
+    pub hash_size: usize,
+    pub ponder: bool,
+    pub deterministic: bool,
+    pub quiescence_depth: usize,
+    pub aspiration_window: Option<f32>,
+    /// A clock for the AI to keep for its own moves.
+    pub time_control: Option<TimeControl>,
 }
 
 impl Ai {
     fn help() -> String {
         r#"  depth=int         - The maximum depth of the move search.
   time=int          - The maximum number of seconds to spend considering a response.
-  early_stop=bool   - Stop the search early if the next depth is predicted to take longer
-                      than the time limit. `time` or `tc` must be set. (false or true)
+  early_stop=bool   - Stop the search early when the best move is stable or forced, or if
+                      the next depth is predicted to take longer than the time limit. With
+                      `tc`, unstable searches may also take longer. `time` or `tc` must be
+                      set. (false or true)
   exact=bool        - If true, don't use search enhancements that produce inexact results.
                       This generally makes a search slower and a bot weaker, but can be used
                       if the accuracy of results is a priority over playing strength.
   threads=int       - The number of worker threads to spawn for analysis.
-  model=path        - The path of a JSON file to load as the evaluator model."#
+  model=path        - The path of a JSON file to load as the evaluator model.
+  book=path         - The path of an opening book to play from while it covers the position.
+  book_pick=string  - How to pick a ply from the opening book. (best or weighted)
+  hash=int          - The size of the transposition table in megabytes.
+  ponder=bool       - Keep searching on the opponent's time, expecting the reply from the
+                      principal variation. (false or true)
+  deterministic=bool - Always give the same result for the same position and options, using
+                      a single thread and ignoring time limits. (false or true)
+  quiescence=int    - How many plies past the search depth to keep responding to tak before
+                      evaluating a position. 0 turns this off.
+  aspiration=float  - How far on either side of the last evaluation each search depth starts
+                      looking. 0 turns this off, searching with a full window.
+  tc=string         - A time control to budget the AI's own thinking time by, in minutes:
+                      10 for sudden death, 10+5 for a 5 second increment, 10d5 for a simple
+                      delay, 10b5 for a Bronstein delay, 10y30 for 30 seconds of byo-yomi,
+                      or 40/90+30 for 90 minutes every 40 moves. The game's clock is
+                      used instead when it has one."#
             .to_owned()
     }
 
@@ -306,6 +424,14 @@ impl Ai {
                 exact_eval: false,
                 threads: 1,
                 model_file: None,
+                book_file: None,
+                book_selection: BookSelection::default(),
+                hash_size: DEFAULT_TABLE_SIZE,
+                ponder: false,
+                deterministic: false,
+                quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+                aspiration_window: Some(DEFAULT_ASPIRATION_WINDOW),
+                time_control: None,
             };
 
             for option in options {
@@ -362,6 +488,78 @@ impl Ai {
                     "model" => {
                         ai.model_file = Some(value.to_owned());
                     }
+                    "book" => {
+                        ai.book_file = Some(value.to_owned());
+                    }
+                    "book_pick" => {
+                        ai.book_selection = match value {
+                            "best" => BookSelection::Best,
+                            "weighted" => BookSelection::Weighted,
+                            _ => {
+                                return Err(clap::Error::raw(
+                                    ClapErrorKind::InvalidValue,
+                                    format!("invalid value for book_pick: {value:?}"),
+                                ))
+                            }
+                        };
+                    }
+                    "hash" => {
+                        ai.hash_size = value
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .ok_or_else(|| {
+                                clap::Error::raw(
+                                    ClapErrorKind::InvalidValue,
+                                    format!("invalid value for hash: {value:?}"),
+                                )
+                            })?;
+                    }
+                    "ponder" => {
+                        ai.ponder = value.parse::<bool>().map_err(|_| {
+                            clap::Error::raw(
+                                ClapErrorKind::InvalidValue,
+                                format!("invalid value for ponder: {value:?}"),
+                            )
+                        })?;
+                    }
+                    "quiescence" => {
+                        ai.quiescence_depth = value.parse::<usize>().map_err(|_| {
+                            clap::Error::raw(
+                                ClapErrorKind::InvalidValue,
+                                format!("invalid value for quiescence: {value:?}"),
+                            )
+                        })?;
+                    }
+                    "aspiration" => {
+                        let width = value
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|width| *width >= 0.0)
+                            .ok_or_else(|| {
+                                clap::Error::raw(
+                                    ClapErrorKind::InvalidValue,
+                                    format!("invalid value for aspiration: {value:?}"),
+                                )
+                            })?;
+                        ai.aspiration_window = (width > 0.0).then_some(width);
+                    }
+                    "deterministic" => {
+                        ai.deterministic = value.parse::<bool>().map_err(|_| {
+                            clap::Error::raw(
+                                ClapErrorKind::InvalidValue,
+                                format!("invalid value for deterministic: {value:?}"),
+                            )
+                        })?;
+                    }
+                    "tc" => {
+                        ai.time_control = Some(value.parse::<TimeControl>().map_err(|_| {
+                            clap::Error::raw(
+                                ClapErrorKind::InvalidValue,
+                                format!("invalid value for tc: {value:?}"),
+                            )
+                        })?);
+                    }
                     _ => (),
                 }
             }
@@ -382,6 +580,14 @@ impl Default for Ai {
             exact_eval: false,
             threads: 1,
             model_file: None,
+            book_file: None,
+            book_selection: BookSelection::default(),
+            hash_size: DEFAULT_TABLE_SIZE,
+            ponder: false,
+            deterministic: false,
+            quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+            aspiration_window: Some(DEFAULT_ASPIRATION_WINDOW),
+            time_control: None,
         }
     }
 }
@@ -452,6 +658,14 @@ impl FromArgMatches for TeiConfig {
                 exact_eval: false,
                 threads: 1,
                 model_file: None,
+                book_file: None,
+                book_selection: BookSelection::default(),
+                hash_size: DEFAULT_TABLE_SIZE,
+                ponder: false,
+                deterministic: false,
+                quiescence_depth: DEFAULT_QUIESCENCE_DEPTH,
+                aspiration_window: Some(DEFAULT_ASPIRATION_WINDOW),
+                time_control: None,
             },
         )
         .map(|ai| Self { ai })
@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use tracing::{error, warn};
+
+use analysis::OpeningBook;
+
+use crate::analyze::read_games;
+use crate::args::BookConfig;
+
+pub fn run_book(config: BookConfig) {
+    match config.size {
+        3 => run_book_sized::<3>(config),
+        4 => run_book_sized::<4>(config),
+        5 => run_book_sized::<5>(config),
+        6 => run_book_sized::<6>(config),
+        7 => run_book_sized::<7>(config),
+        8 => run_book_sized::<8>(config),
+        size => error!(?size, "Invalid board size."),
+    }
+}
+
+fn run_book_sized<const N: usize>(config: BookConfig) {
+    let start_time = Instant::now();
+
+    let mut book = OpeningBook::<N>::new(config.canonical);
+    let mut added = 0;
+    let mut skipped = 0;
+
+    for filename in &config.files {
+        let games = match read_games(filename) {
+            Ok(games) => games,
+            Err(err) => {
+                error!(file = filename, error = ?err, "Could not open PTN file.");
+                return;
+            }
+        };
+
+        for (i, game) in games.enumerate() {
+            let result = game.and_then(|game| {
+                if game.get_size() == Some(N) {
+                    book.add_game(&game, config.plies)
+                } else {
+                    Ok(false)
+                }
+            });
+
+            match result {
+                Ok(true) => added += 1,
+                Ok(false) => skipped += 1,
+                Err(err) => {
+                    warn!(file = filename, game = i + 1, error = ?err, "Invalid PTN game.");
+                    skipped += 1;
+                }
+            }
+        }
+    }
+
+    book.prune(config.min_games);
+
+    if let Err(err) = book.to_file(&config.output) {
+        error!(error = ?err, "Could not write opening book.");
+        return;
+    }
+
+    println!("Games: {added}");
+    println!("Skipped: {skipped}");
+    println!("Positions: {}", book.len());
+    println!("Time: {:.3}s", start_time.elapsed().as_secs_f64());
+}
@@ -6,14 +6,20 @@ use tracing_subscriber::fmt::format;
 
 use self::analyze::run_analysis;
 use self::args::{Args, Command};
+use self::book::run_book;
+use self::perft::run_perft;
 use self::play::run_game;
 use self::tei::run_tei;
+use self::tinue::run_tinue;
 
 mod analyze;
 mod args;
+mod book;
+mod perft;
 mod play;
 mod player;
 mod tei;
+mod tinue;
 
 fn main() {
     let args = Args::parse();
@@ -38,6 +44,9 @@ fn main() {
         Command::Play(config) => run_game(config),
         Command::Analyze(config) => run_analysis(config),
         Command::Tei(config) => run_tei(config),
+        Command::Perft(config) => run_perft(config),
+        Command::Book(config) => run_book(config),
+        Command::Tinue(config) => run_tinue(config),
     }
 }
 
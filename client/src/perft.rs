@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+use tracing::error;
+
+use tak::{perft, perft_divide, State, Tps};
+
+use crate::args::PerftConfig;
+
+pub fn run_perft(config: PerftConfig) {
+    let tps = match (&config.tps, config.size) {
+        (Some(tps_string), None) => match tps_string.parse::<Tps>() {
+            Ok(tps) => tps,
+            Err(err) => {
+                error!(error = ?err, "Invalid TPS string.");
+                return;
+            }
+        },
+        (None, Some(size)) => {
+            let size = size as usize;
+            let empty_row = format!("x{size}");
+            let board = vec![empty_row; size].join("/");
+            format!("{board} 1 1")
+                .parse()
+                .expect("empty board TPS is valid")
+        }
+        _ => unreachable!(),
+    };
+
+    match tps.size() {
+        3 => run_perft_sized::<3>(tps, config.depth),
+        4 => run_perft_sized::<4>(tps, config.depth),
+        5 => run_perft_sized::<5>(tps, config.depth),
+        6 => run_perft_sized::<6>(tps, config.depth),
+        7 => run_perft_sized::<7>(tps, config.depth),
+        8 => run_perft_sized::<8>(tps, config.depth),
+        size => error!(?size, "Invalid board size."),
+    }
+}
+
+fn run_perft_sized<const N: usize>(tps: Tps, depth: usize) {
+    let mut state: State<N> = match tps.try_into() {
+        Ok(state) => state,
+        Err(err) => {
+            error!(error = ?err, "Could not create state.");
+            return;
+        }
+    };
+
+    let start_time = Instant::now();
+
+    let mut divide: Vec<_> = perft_divide(&mut state, depth)
+        .into_iter()
+        .map(|(ply, count)| (format!("{ply:?}"), count))
+        .collect();
+    divide.sort();
+
+    // The divide is empty at depth 0, but perft still counts the position itself.
+    let total = if depth == 0 {
+        perft(&mut state, 0)
+    } else {
+        divide.iter().map(|(_, count)| count).sum()
+    };
+
+    let elapsed = start_time.elapsed();
+
+    for (ply, count) in &divide {
+        println!("{ply}: {count}");
+    }
+
+    println!();
+    println!("Plies: {}", divide.len());
+    println!("Nodes: {total}");
+    println!(
+        "Time: {:.3}s, {} nodes/s",
+        elapsed.as_secs_f64(),
+        (total as f64 / elapsed.as_secs_f64()) as u64,
+    );
+}
@@ -1,13 +1,16 @@
 use std::mem;
+use std::time::{Duration, Instant};
 
 use async_std::prelude::*;
-use async_std::task::{self, JoinHandle};
+use async_std::task;
 use futures::channel::mpsc::{self, UnboundedReceiver as Receiver, UnboundedSender as Sender};
-use futures::{select, FutureExt, SinkExt};
+use futures::{future, select, FutureExt, SinkExt};
 use tracing::{debug, error, instrument, trace, warn};
 
+use analysis::{GameClock, TimeControl};
 use tak::{
-    Color, Komi, Ply, PlyError, PtnError, PtnGame, PtnHeader, Resolution, State, StateError,
+    Color, Game, History, Komi, Ply, PlyError, PtnGame, PtnHeader, RepetitionRule, Resolution,
+    State, StateError,
 };
 
 use crate::args::{PlayConfig, Player as PlayerArgs};
@@ -17,7 +20,6 @@ use crate::player::{ai, human};
 pub struct Player<const N: usize> {
     pub name: Option<String>,
     pub to_player: Sender<Message<N>>,
-    pub task: JoinHandle<()>,
     pub color_select: Option<Color>,
 }
 
@@ -29,14 +31,19 @@ impl<T, const N: usize> PlayerInitializer<N> for T where T: Fn(Sender<Message<N>
 pub enum Message<const N: usize> {
     GameStart(Color),
     GameEnd(GameEnd),
-    MoveRequest(State<N>),
+    /// Asks for a move, along with the clock of the player to move if the game is timed.
+    MoveRequest(State<N>, History, Option<TimeControl>),
     MoveResponse(Ply<N>),
     UndoRequest,
     UndoRequestWithdrawal,
-    UndoResponse { accept: bool },
+    UndoResponse {
+        accept: bool,
+    },
     DrawRequest,
     DrawRequestWithdrawal,
-    DrawResponse { accept: bool },
+    DrawResponse {
+        accept: bool,
+    },
 }
 
 use self::GameEnd as GameEndType;
@@ -44,9 +51,21 @@ use self::GameEnd as GameEndType;
 #[derive(Debug)]
 pub enum GameEnd {
     Resolution(Resolution),
+    /// The player ran out of time.
+    Time(Color),
     //Resignation(Color),
 }
 
+impl GameEnd {
+    /// The player who won the game, if it wasn't a draw.
+    pub fn winner(&self) -> Option<Color> {
+        match *self {
+            GameEnd::Resolution(resolution) => resolution.color(),
+            GameEnd::Time(color) => Some(color.other()),
+        }
+    }
+}
+
 pub fn run_game(mut config: PlayConfig) {
     let game = if let Some(load) = &config.load {
         match PtnGame::from_file(load) {
@@ -71,6 +90,16 @@ pub fn run_game(mut config: PlayConfig) {
                     }
                 }
 
+                if let Some(clock) = game.get_header("Clock").map(|h| &h.value) {
+                    match clock.parse::<TimeControl>() {
+                        Ok(time_control) => config.time_control = Some(time_control),
+                        Err(err) => {
+                            error!(error = %err, "Could not read PTN file.");
+                            return;
+                        }
+                    }
+                }
+
                 Some(game)
             }
             Err(err) => {
@@ -95,12 +124,13 @@ pub fn run_game(mut config: PlayConfig) {
 
 fn run_game_sized<const N: usize>(config: PlayConfig, game: Option<PtnGame>) {
     // Make sure any state we load from a file is valid.
-    if let Some(game) = &game {
-        if let Err(err) = game.validate::<N>() {
+    let game: Option<Game<N>> = match game.map(Game::try_from).transpose() {
+        Ok(game) => game,
+        Err(err) => {
             error!(error = ?err, "Game state is invalid.");
             return;
         }
-    }
+    };
 
     let p1_initialize = initialize_player::<N>(&config.p1);
     let p2_initialize = initialize_player::<N>(&config.p2);
@@ -114,18 +144,22 @@ fn run_game_sized<const N: usize>(config: PlayConfig, game: Option<PtnGame>) {
     mem::drop(p1_initialize);
     mem::drop(p2_initialize);
 
-    let mut game = game.unwrap_or_else(|| PtnGame {
-        headers: vec![
+    let mut game = game.unwrap_or_else(|| {
+        let mut game = Game::default();
+        game.headers = vec![
             PtnHeader::new("Site", "Local"),
             PtnHeader::new("Player1", p1.name.as_deref().unwrap_or("Anonymous")),
             PtnHeader::new("Player2", p2.name.as_deref().unwrap_or("Anonymous")),
-        ],
-        ..Default::default()
+        ];
+        game
     });
 
     // Ensure that all games have valid Size and Komi headers.
     game.add_header("Size", N);
     game.add_header("Komi", config.game.komi);
+    if let Some(time_control) = config.time_control {
+        game.add_header("Clock", time_control);
+    }
 
     task::block_on(game_handler(p1, from_p1, p2, from_p2, config, game));
 }
@@ -136,15 +170,8 @@ fn initialize_player<const N: usize>(player: &PlayerArgs) -> impl PlayerInitiali
             Box::new(|to_game| human::initialize(Some(config.name.clone()), to_game))
                 as Box<dyn PlayerInitializer<N>>
         }
-        PlayerArgs::Ai(config) => Box::new(|to_game| {
-            ai::initialize(
-                config.depth_limit,
-                config.time_limit,
-                config.early_stop,
-                config.threads,
-                to_game,
-            )
-        }) as Box<dyn PlayerInitializer<N>>,
+        PlayerArgs::Ai(config) => Box::new(|to_game| ai::initialize(config.clone(), to_game))
+            as Box<dyn PlayerInitializer<N>>,
     }
 }
 
@@ -163,12 +190,8 @@ impl PlayerToken {
     }
 }
 
-fn state_from_game<const N: usize>(game: &PtnGame) -> State<N> {
-    game.clone().try_into().expect("cannot retrieve state")
-}
-
-fn plies_from_game<const N: usize>(game: &PtnGame) -> Vec<Ply<N>> {
-    game.get_plies().expect("cannot retrieve plies")
+fn history_from_game<const N: usize>(game: &Game<N>) -> History {
+    game.history(RepetitionRule::default())
 }
 
 #[instrument(level = "trace", skip_all)]
@@ -178,7 +201,7 @@ async fn game_handler<const N: usize>(
     mut p2: Player<N>,
     from_p2: Receiver<Message<N>>,
     config: PlayConfig,
-    mut game: PtnGame,
+    mut game: Game<N>,
 ) {
     use Message::*;
     use PlayerToken::*;
@@ -199,7 +222,7 @@ async fn game_handler<const N: usize>(
     macro_rules! save_game {
         () => {{
             if let Some(filename) = &config.file {
-                if let Err(err) = game.to_file(filename) {
+                if let Err(err) = PtnGame::from(&game).to_file(filename) {
                     error!(error = ?err, "Could not save PTN file.");
                 } else {
                     debug!(?filename, "PTN file saved.");
@@ -227,7 +250,14 @@ async fn game_handler<const N: usize>(
     send!(Player1, GameStart(p1_color));
     send!(Player2, GameStart(p2_color));
 
-    print_board::<N>(&game);
+    // Each player's clock, indexed by color.
+    let mut clocks = config
+        .time_control
+        .map(|time_control| [GameClock::new(time_control); 2]);
+    // When the player to move was asked for their move.
+    let mut move_started;
+
+    print_board(&game, clocks.as_ref());
 
     macro_rules! player_to_move {
         ($state:expr) => {{
@@ -247,22 +277,68 @@ async fn game_handler<const N: usize>(
         }};
     }
 
+    macro_rules! time_loss {
+        ($color:expr) => {{
+            let color = $color;
+            let result = match color {
+                Color::White => "0-1",
+                Color::Black => "1-0",
+            };
+            game.add_header("Result", result);
+            game.result = Some(result.to_owned());
+
+            println!("\nGame over.");
+            println!("\n{color:?} loses on time: {result}");
+            send!(Player1, GameEnd(GameEndType::Time(color)));
+            send!(Player2, GameEnd(GameEndType::Time(color)));
+        }};
+    }
+
+    // Starts the clock of the player to move, and asks them for their move.
+    macro_rules! request_move {
+        ($state:expr, $history:expr) => {{
+            let state = $state;
+            let player_to_move = player_to_move!(state);
+            let time_control =
+                clocks.map(|clocks| clocks[clock_index(state.to_move())].time_control());
+            move_started = Instant::now();
+            send!(player_to_move, MoveRequest(state, $history, time_control));
+        }};
+    }
+
     {
-        let state = state_from_game(&game);
+        let state = game.state().clone();
+        let history = history_from_game(&game);
 
-        if let Some(resolution) = state.resolution() {
+        if let Some(resolution) = history.resolution(&state) {
             game_resolution!(resolution);
             return;
         }
 
-        let player_to_move = player_to_move!(state);
-        send!(player_to_move, MoveRequest(state));
+        request_move!(state, history);
     }
 
     loop {
-        let (from, message) = select! {
-            message = from_p1.next().fuse() => (Player1, message),
-            message = from_p2.next().fuse() => (Player2, message),
+        // Wakes up once the player to move has run out of time.
+        let flag = match &clocks {
+            Some(clocks) => {
+                let allowance = clocks[clock_index(game.state().to_move())].allowance();
+                future::Either::Left(task::sleep(
+                    allowance.saturating_sub(move_started.elapsed()),
+                ))
+            }
+            None => future::Either::Right(future::pending()),
+        };
+
+        let received = select! {
+            message = from_p1.next().fuse() => Some((Player1, message)),
+            message = from_p2.next().fuse() => Some((Player2, message)),
+            () = flag.fuse() => None,
+        };
+
+        let Some((from, message)) = received else {
+            time_loss!(game.state().to_move());
+            break;
         };
 
         let message = if let Some(message) = message {
@@ -276,26 +352,48 @@ async fn game_handler<const N: usize>(
 
         match message {
             MoveResponse(ply) => {
-                if from != player_to_move!(state_from_game::<N>(&game)) {
+                if from != player_to_move!(game.state()) {
                     warn!("Received a move response from the wrong player.");
                     continue;
                 }
 
+                let color = game.state().to_move();
+                let used = move_started.elapsed();
+
+                // The move may have raced the flag.
+                if clocks.is_some_and(|clocks| used > clocks[clock_index(color)].allowance()) {
+                    time_loss!(color);
+                    break;
+                }
+
                 if handle_ply(&mut game, ply).is_ok() {
+                    if let Some(clocks) = &mut clocks {
+                        clocks[clock_index(color)].finish_move(used);
+                    }
+
+                    let state = game.state().clone();
+                    let history = history_from_game(&game);
+                    let resolution = history.resolution(&state);
+                    game.set_result(resolution);
                     save_game!();
-                    print_board::<N>(&game);
-                    let state = state_from_game(&game);
-                    if let Some(resolution) = state.resolution() {
+                    print_board(&game, clocks.as_ref());
+                    if let Some(resolution) = resolution {
                         game_resolution!(resolution);
                         break;
                     }
-                    send!(from.other(), MoveRequest(state));
+                    request_move!(state, history);
                 } else {
-                    send!(from, MoveRequest(state_from_game(&game)));
+                    // The clock keeps running.
+                    let time_control =
+                        clocks.map(|clocks| clocks[clock_index(color)].time_control_after(used));
+                    send!(
+                        from,
+                        MoveRequest(game.state().clone(), history_from_game(&game), time_control)
+                    );
                 }
             }
             UndoRequest => {
-                if plies_from_game::<N>(&game).is_empty() {
+                if game.position() == 0 {
                     // No history to undo, so reject the request.
                     send!(from, UndoResponse { accept: false });
                 } else {
@@ -305,16 +403,16 @@ async fn game_handler<const N: usize>(
             UndoRequestWithdrawal => send!(from.other(), UndoRequestWithdrawal),
             UndoResponse { accept } => {
                 if accept {
-                    if plies_from_game::<N>(&game).is_empty() {
+                    if !game.back() {
                         error!("Undo request was accepted, but there is no more history.");
                     } else {
-                        game.remove_last_ply::<N>()
-                            .expect("could not remove last ply");
+                        game.truncate();
+                        game.set_result(None);
                         save_game!();
-                        let state = state_from_game(&game);
-                        let player_to_move = player_to_move!(state);
+                        let state = game.state().clone();
+                        let history = history_from_game(&game);
                         send!(from.other(), message);
-                        send!(player_to_move, MoveRequest(state));
+                        request_move!(state, history);
                     }
                 } else {
                     send!(from.other(), message);
@@ -343,34 +441,37 @@ async fn game_handler<const N: usize>(
     save_game!();
 }
 
-fn handle_ply<const N: usize>(game: &mut PtnGame, ply: Ply<N>) -> Result<(), StateError> {
-    if let Err(err) = game.add_ply(ply) {
-        match err {
-            PtnError::StateError(err) => {
-                let message = match err {
-                    StateError::PlyError(PlyError::InvalidDrops(message)) => message,
-                    StateError::PlyError(PlyError::OutOfBounds) => "Out of bounds.",
-                    StateError::InvalidPlace(message) => message,
-                    StateError::InvalidSpread(message) => message,
-                    StateError::NoPreviousPlies => unreachable!(),
-                };
-                println!("\nError: {message}");
-                return Err(err);
-            }
-            _ => panic!("cannot add ply"),
-        }
+fn handle_ply<const N: usize>(game: &mut Game<N>, ply: Ply<N>) -> Result<(), StateError> {
+    if let Err(err) = game.play(ply) {
+        let message = match err {
+            StateError::PlyError(PlyError::InvalidDrops(message)) => message,
+            StateError::PlyError(PlyError::OutOfBounds) => "Out of bounds.",
+            StateError::PlyError(PlyError::InvalidPieceType) => "Invalid piece type.",
+            StateError::InvalidPlace(message) => message,
+            StateError::InvalidSpread(message) => message,
+            StateError::NoPreviousPlies => unreachable!(),
+        };
+        println!("\nError: {message}");
+        return Err(err);
     }
     Ok(())
 }
 
-fn print_board<const N: usize>(game: &PtnGame) {
-    let state = state_from_game::<N>(game);
+fn clock_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+fn print_board<const N: usize>(game: &Game<N>, clocks: Option<&[GameClock; 2]>) {
+    let state = game.state();
 
     println!("\n--------------------------------------------------");
 
     println!("\n{state}");
 
-    if let Some(last_turn) = game.turns.last() {
+    if let Some(last_turn) = PtnGame::from(game).turns.last() {
         let turn_number = format!("{}.", last_turn.number);
 
         let p1_move = match &last_turn.p1_move.ply {
@@ -392,6 +493,19 @@ fn print_board<const N: usize>(game: &PtnGame) {
     } else {
         println!("\n  1.   --");
     }
+
+    if let Some([white, black]) = clocks {
+        println!(
+            "\n  White: {}  Black: {}",
+            format_clock(white.remaining()),
+            format_clock(black.remaining())
+        );
+    }
+}
+
+fn format_clock(time: Duration) -> String {
+    let tenths = time.as_millis() / 100;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
 }
 
 fn print_resolution(resolution: Resolution) {
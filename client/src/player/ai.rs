@@ -1,6 +1,6 @@
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use async_std::prelude::*;
 use async_std::task;
@@ -8,53 +8,233 @@ use futures::channel::mpsc::{self, UnboundedReceiver as Receiver, UnboundedSende
 use futures::{select, FutureExt, SinkExt};
 use tracing::{error, trace, warn};
 
-use analysis::{self, analyze, AnalysisConfig, PersistentState};
+use analysis::{
+    self, analyze, Analysis, AnalysisConfig, GameClock, OpeningBook, PersistentState, TimeControl,
+};
+use tak::{History, State};
 
+use crate::analyze::load_book;
+use crate::args::Ai;
 use crate::play::{Message, Player};
 
-pub fn initialize<const N: usize>(
-    depth_limit: Option<u32>,
-    time_limit: Option<Duration>,
-    early_stop: bool,
-    threads: usize,
-    to_game: Sender<Message<N>>,
-) -> Player<N> {
+pub fn initialize<const N: usize>(config: Ai, to_game: Sender<Message<N>>) -> Player<N> {
     let name = Some(format!("Takkerus v{}", analysis::version()));
 
     trace!(?name, "Initializing an AI player.");
 
     let (to_player, from_game) = mpsc::unbounded();
 
+    task::spawn(message_handler::<N>(config, to_game, from_game));
+
     Player {
         name,
         to_player,
-        task: task::spawn(message_handler::<N>(
+        color_select: None,
+    }
+}
+
+/// Runs searches in the background, sending each finished analysis back tagged with the
+/// id of the search it came from.
+struct Searcher<const N: usize> {
+    config: Ai,
+    persistent_state: Arc<PersistentState<N>>,
+    book: Arc<Option<OpeningBook<N>>>,
+    sender: Sender<(usize, Analysis<N>)>,
+    next_id: usize,
+    /// The AI's own clock, if it was given a time control.
+    clock: Option<GameClock>,
+    /// The clock kept by the game as of the last move request, if the game is timed.
+    game_time_control: Option<TimeControl>,
+}
+
+/// A search running in the background.
+struct Search {
+    id: usize,
+    /// The history up to the searched position.
+    history: History,
+    interrupted: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    /// The time control to switch to on a ponder hit.
+    ponder_hit_time_control: Arc<Mutex<Option<TimeControl>>>,
+}
+
+/// A search of the position expected after the opponent's reply, run on their time.
+struct Ponder<const N: usize> {
+    search: Search,
+    state: State<N>,
+    /// The result, if the search finished before the opponent moved.
+    analysis: Option<Analysis<N>>,
+}
+
+impl<const N: usize> Searcher<N> {
+    fn spawn(&mut self, state: State<N>, history: History, ponder: bool) -> Search {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let Ai {
             depth_limit,
             time_limit,
             early_stop,
             threads,
-            to_game,
-            from_game,
-        )),
-        color_select: None,
+            book_selection,
+            deterministic,
+            quiescence_depth,
+            aspiration_window,
+            ..
+        } = self.config;
+
+        // While pondering, the game's clock is from before the last move, so the search
+        // switches to the clock from the next move request on a ponder hit.
+        let time_control = self.time_control();
+
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let pondering = Arc::new(AtomicBool::new(ponder));
+        let ponder_hit_time_control = Arc::new(Mutex::new(None));
+
+        let search = Search {
+            id,
+            history: history.clone(),
+            interrupted: interrupted.clone(),
+            pondering: pondering.clone(),
+            ponder_hit_time_control: ponder_hit_time_control.clone(),
+        };
+
+        let persistent_state = self.persistent_state.clone();
+        let book = self.book.clone();
+        let mut sender = self.sender.clone();
+
+        task::spawn_blocking(move || {
+            if !ponder {
+                println!("\nAnalyzing...");
+            }
+
+            let analysis = {
+                let analysis_config = AnalysisConfig {
+                    depth_limit,
+                    time_limit,
+                    early_stop,
+                    time_control,
+                    interrupted,
+                    pondering,
+                    ponder_hit_time_control,
+                    persistent_state: Some(&persistent_state),
+                    threads,
+                    history: Some(&history),
+                    book: book.as_ref().as_ref(),
+                    book_selection,
+                    deterministic,
+                    quiescence_depth,
+                    aspiration_window: aspiration_window.map(Into::into),
+                    ..Default::default()
+                };
+
+                trace!(ponder, "Analyzing state.");
+                analyze(analysis_config, &state)
+            };
+
+            let _result = task::block_on(sender.send((id, analysis)));
+        });
+
+        search
+    }
+
+    /// Starts pondering on the position reached if the opponent plays the expected reply
+    /// to the first ply of the analysis.
+    fn ponder(&mut self, analysis: &Analysis<N>, mut history: History) -> Option<Ponder<N>> {
+        let [ply, reply, ..] = analysis.principal_variation[..] else {
+            return None;
+        };
+
+        let mut state = analysis.state.clone();
+        for ply in [ply, reply] {
+            state.execute_ply(ply).ok()?;
+            history.push(ply, &state);
+        }
+
+        if state.resolution().is_some() {
+            return None;
+        }
+
+        trace!(?reply, "Pondering on the expected reply.");
+
+        Some(Ponder {
+            search: self.spawn(state.clone(), history, true),
+            state,
+            analysis: None,
+        })
+    }
+
+    /// The clock to search with: the game's, or else the AI's own, which only runs on its
+    /// own moves.
+    fn time_control(&self) -> Option<TimeControl> {
+        self.game_time_control
+            .or_else(|| self.clock.map(|clock| clock.time_control()))
+    }
+
+    /// Charges the clock, if there is one, for a move that the AI started thinking about
+    /// at the given time.
+    fn finish_move(&mut self, started: Instant) {
+        if self.game_time_control.is_some() {
+            return;
+        }
+
+        if let Some(clock) = &mut self.clock {
+            if clock.finish_move(started.elapsed()) {
+                trace!(remaining = ?clock.remaining(), "Clock updated.");
+            } else {
+                warn!("The AI ran out of time.");
+            }
+        }
+    }
+}
+
+async fn send_move<const N: usize>(to_game: &mut Sender<Message<N>>, analysis: &Analysis<N>) {
+    if let Some(&next_move) = analysis.principal_variation.first() {
+        if let Err(err) = to_game.send(Message::MoveResponse(next_move)).await {
+            error!(?err, "Could not send message to game.");
+        }
+    } else {
+        error!("Returned analysis contained no moves.");
     }
 }
 
 async fn message_handler<const N: usize>(
-    depth_limit: Option<u32>,
-    time_limit: Option<Duration>,
-    early_stop: bool,
-    threads: usize,
+    config: Ai,
     mut to_game: Sender<Message<N>>,
     from_game: Receiver<Message<N>>,
 ) {
     use Message::*;
 
-    let persistent_state = Arc::new(PersistentState::<N>::default());
+    let persistent_state = Arc::new(PersistentState::<N>::with_table_size(config.hash_size));
+    let book = match config.book_file.as_deref().map(load_book::<N>).transpose() {
+        Ok(book) => book,
+        Err(err) => {
+            error!(error = ?err, "Could not load opening book; playing without one.");
+            None
+        }
+    };
+    let book = Arc::new(book);
+    let pondering_enabled = config.ponder;
+    let clock = config.time_control.map(GameClock::new);
 
-    let mut interrupt: Option<Arc<AtomicBool>> = None;
     let (analysis_sender, analysis_receiver) = mpsc::unbounded();
 
+    let mut searcher = Searcher {
+        config,
+        persistent_state,
+        book,
+        sender: analysis_sender,
+        next_id: 0,
+        clock,
+        game_time_control: None,
+    };
+
+    // The search for the move to play.
+    let mut search: Option<Search> = None;
+    let mut ponder: Option<Ponder<N>> = None;
+    // When the AI was asked for its current move.
+    let mut move_started = Instant::now();
+
     let mut from_game = from_game.fuse();
     let mut analysis_receiver = analysis_receiver.fuse();
 
@@ -66,48 +246,52 @@ async fn message_handler<const N: usize>(
                         trace!(assigned_color = ?color, "Game start received.");
                     }
                     Some(GameEnd(end)) => {
-                        trace!(?end, "Game end received; exiting.");
+                        trace!(winner = ?end.winner(), "Game end received; exiting.");
 
-                        if interrupt.is_some() {
+                        if let Some(search) = search {
                             warn!("Analysis was in progress when the game ended.");
-                            interrupt.unwrap().store(true, Ordering::Relaxed);
+                            search.interrupted.store(true, Ordering::Relaxed);
+                        }
+                        if let Some(ponder) = ponder {
+                            ponder.search.interrupted.store(true, Ordering::Relaxed);
                         }
 
                         break;
                     }
-                    Some(MoveRequest(state)) => {
-                        trace!("Move request received.");
-                        if interrupt.is_some() {
+                    Some(MoveRequest(state, history, time_control)) => {
+                        trace!(?time_control, "Move request received.");
+                        move_started = Instant::now();
+                        searcher.game_time_control = time_control;
+
+                        if search.is_some() {
                             error!("Move request received while analyzing.");
                         }
 
-                        let persistent_state = persistent_state.clone();
-
-                        let interrupted = Arc::new(AtomicBool::new(false));
-                        interrupt = Some(interrupted.clone());
+                        match ponder.take() {
+                            Some(mut ponder) if ponder.state == state => {
+                                trace!("Ponder hit.");
+                                *ponder.search.ponder_hit_time_control.lock().unwrap() =
+                                    searcher.time_control();
+                                ponder.search.pondering.store(false, Ordering::Release);
+                                ponder.search.history = history;
 
-                        let mut analysis_sender = analysis_sender.clone();
+                                if let Some(analysis) = ponder.analysis {
+                                    send_move(&mut to_game, &analysis).await;
+                                    searcher.finish_move(move_started);
+                                } else {
+                                    println!("\nAnalyzing...");
+                                    search = Some(ponder.search);
+                                }
+                            }
+                            missed => {
+                                if let Some(ponder) = missed {
+                                    trace!("Ponder miss.");
+                                    ponder.search.interrupted.store(true, Ordering::Relaxed);
+                                }
 
-                        task::spawn_blocking(move || {
-                            println!("\nAnalyzing...");
-
-                            let analysis = {
-                                let analysis_config = AnalysisConfig {
-                                    depth_limit,
-                                    time_limit,
-                                    early_stop,
-                                    interrupted,
-                                    persistent_state: Some(&persistent_state),
-                                    threads,
-                                    ..Default::default()
-                                };
-
-                                trace!("Analyzing state.");
-                                analyze(analysis_config, &state)
-                            };
-
-                            let _result = task::block_on(analysis_sender.send(analysis));
-                        });
+                                search = Some(searcher.spawn(state, history, false));
+                            }
+                        }
                     }
                     _ => (),
                 }
@@ -117,18 +301,22 @@ async fn message_handler<const N: usize>(
                     error!("Analysis sender died?");
                 }
 
-                let next_analysis = next_analysis.unwrap();
+                let (id, next_analysis) = next_analysis.unwrap();
+
+                if search.as_ref().is_some_and(|search| search.id == id) {
+                    let finished = search.take().unwrap();
+                    send_move(&mut to_game, &next_analysis).await;
+                    searcher.finish_move(move_started);
 
-                if let Some(&next_move) = next_analysis.principal_variation.first() {
-                    let message = MoveResponse(next_move);
-                    if let Err(err) = to_game.send(message).await {
-                        error!(?err, "Could not send message to game.");
+                    if pondering_enabled {
+                        ponder = searcher.ponder(&next_analysis, finished.history);
                     }
+                } else if let Some(ponder) = ponder.as_mut().filter(|ponder| ponder.search.id == id) {
+                    trace!("Pondering finished before the opponent moved.");
+                    ponder.analysis = Some(next_analysis);
                 } else {
-                    error!("Returned analysis contained no moves.");
+                    trace!(id, "Discarding the analysis of an abandoned search.");
                 }
-
-                interrupt = None;
             }
         }
     }
@@ -50,10 +50,11 @@ pub fn initialize<const N: usize>(name: Option<String>, to_game: Sender<Message<
         focus_sender: setup.focus_sender.clone().unwrap(),
     };
 
+    task::spawn(message_handler::<N>(player_info, to_game, from_game));
+
     Player {
         name,
         to_player,
-        task: task::spawn(message_handler::<N>(player_info, to_game, from_game)),
         color_select: None,
     }
 }
@@ -133,10 +134,10 @@ async fn message_handler<const N: usize>(
                         trace!(assigned_color = ?color, "Game start received.");
                     }
                     Some(GameEnd(end)) => {
-                        trace!(?end, "Game end received; exiting.");
+                        trace!(winner = ?end.winner(), "Game end received; exiting.");
                         break;
                     }
-                    Some(MoveRequest(_state)) => {
+                    Some(MoveRequest(_state, _history, _time_control)) => {
                         trace!("Move request received.");
                         move_status = Some(AwaitingInput);
 
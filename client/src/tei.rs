@@ -1,23 +1,29 @@
-//! A limited TEI implementation, suitable for running analysis via (RaceTrack)[https://github.com/MortenLohne/racetrack].
+//! An implementation of TEI, for running analysis via [RaceTrack](https://github.com/MortenLohne/racetrack)
+//! and other engine managers.
+//!
+//! Invalid input is reported with `info string` messages instead of ending the engine.
 
 use std::fmt::Write;
 use std::io;
-use std::sync::Mutex;
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use async_std::channel::{self, Sender};
+use async_std::channel::{self, Receiver, Sender};
 use async_std::io::{prelude::BufReadExt, stdin, BufReader};
 use async_std::prelude::*;
-use async_std::task;
-use once_cell::sync::Lazy;
+use async_std::task::{self, JoinHandle};
+use once_cell::sync::{Lazy, OnceCell};
 use tracing::error;
 
 use analysis::{
-    analyze, version, Analysis, AnalysisConfig, PersistentState, Sender as SenderTrait, TimeControl,
+    analyze, version, Analysis, AnalysisConfig, OpeningBook, PersistentState,
+    Sender as SenderTrait, TimeControl,
 };
-use tak::{Color, Komi, PtnGame, PtnPly, State, Tps};
+use tak::{Color, Komi, Ply, PtnGame, PtnPly, RepetitionRule, State, Tps};
 
-use crate::analyze::load_model;
+use crate::analyze::{load_book, load_model};
 use crate::args::{Ai, TeiConfig};
 
 pub fn run_tei(config: TeiConfig) {
@@ -26,18 +32,88 @@ pub fn run_tei(config: TeiConfig) {
     task::block_on(listen_spawner(ai));
 }
 
-async fn listen_spawner(ai: Ai) {
+/// The limits of a search, from a `go` command.
+#[derive(Clone, Debug, Default)]
+struct Go {
+    clocks: Clocks,
+    depth_limit: Option<u32>,
+    node_limit: Option<u64>,
+    move_time: Option<Duration>,
+    /// Search until `stop`, and only report the best move then.
+    infinite: bool,
+    /// Search the position after the expected reply, without using any time until
+    /// `ponderhit`, and only report the best move after `ponderhit` or `stop`.
+    ponder: bool,
+    /// Only search these plies, in PTN, if any are given.
+    search_plies: Vec<String>,
+}
+
+/// The white and black clocks from a `go` command, each indexed by color.
+#[derive(Clone, Debug, Default)]
+struct Clocks {
+    time: [Option<Duration>; 2],
+    increment: [Duration; 2],
+    /// A simple delay.
+    delay: [Duration; 2],
+    byo_yomi: Option<Duration>,
+    moves_to_go: Option<u32>,
+}
+
+impl Clocks {
+    /// The time control of the given player, if their time was given.
+    fn time_control(&self, color: Color) -> Option<TimeControl> {
+        let index = match color {
+            Color::White => 0,
+            Color::Black => 1,
+        };
+        let time = self.time[index]?;
+        let increment = self.increment[index];
+        let delay = self.delay[index];
+
+        Some(match (self.moves_to_go, self.byo_yomi) {
+            (Some(moves_to_go), _) => TimeControl::MovesToGo {
+                time,
+                moves_to_go,
+                increment,
+            },
+            (None, Some(period)) => TimeControl::ByoYomi { time, period },
+            (None, None) if !delay.is_zero() => TimeControl::Delay { time, delay },
+            (None, None) if !increment.is_zero() => TimeControl::Increment { time, increment },
+            (None, None) => TimeControl::SuddenDeath { time },
+        })
+    }
+}
+
+/// A search running in the background.
+struct Search {
+    interrupted: Arc<AtomicBool>,
+    /// Releases an infinite or ponder search to report its best move, or not.
+    release: Sender<bool>,
+    /// Set while a ponder search is waiting for `ponderhit`.
+    pondering: Option<Arc<AtomicBool>>,
+    /// Finishes once the best move has been reported, and the search has let go of the
+    /// persistent state.
+    task: JoinHandle<()>,
+}
+
+impl Search {
+    /// Ends the search and waits for it to finish, reporting its best move if `report`
+    /// is set. Searches with limits always report their best move.
+    async fn stop(self, report: bool) {
+        self.interrupted.store(true, Ordering::Relaxed);
+        let _ = self.release.try_send(report);
+        self.task.await;
+    }
+}
+
+async fn listen_spawner(mut ai: Ai) {
     let mut size = 6;
     let mut komi = Komi::default();
+    let mut multi_pv = 1;
+    let mut debug = false;
 
-    let mut game;
-    macro_rules! new_game {
-        () => {{
-            game = PtnGame::default();
-            game.add_header("Komi", komi);
-        }};
-    }
-    new_game!();
+    let mut game = new_game(komi);
+    let mut search: Option<Search> = None;
 
     let mut input = BufReader::new(stdin()).lines();
 
@@ -46,94 +122,294 @@ async fn listen_spawner(ai: Ai) {
 
         let mut parts = message.split_whitespace();
 
-        match parts.next().expect("no input") {
+        let Some(command) = parts.next() else {
+            continue;
+        };
+
+        let result = match command {
             "tei" => {
                 println!("id name Takkerus {}", version());
                 println!("id author Christopher Foster");
                 println!("option name HalfKomi type spin default 0 min -10 max 10");
-                println!("teiok")
+                println!(
+                    "option name Hash type spin default {} min 1 max 65536",
+                    ai.hash_size
+                );
+                println!(
+                    "option name Threads type spin default {} min 1 max 256",
+                    ai.threads
+                );
+                println!(
+                    "option name Model type string default {}",
+                    ai.model_file.as_deref().unwrap_or("<empty>")
+                );
+                println!("option name Exact type check default {}", ai.exact_eval);
+                println!("option name Ponder type check default {}", ai.ponder);
+                println!("option name MultiPV type spin default 1 min 1 max 256");
+                println!("teiok");
+                Ok(())
             }
-            "isready" => println!("readyok"),
+            "isready" => {
+                println!("readyok");
+                Ok(())
+            }
+            "debug" => match parts.next() {
+                Some("on") => {
+                    debug = true;
+                    Ok(())
+                }
+                Some("off") => {
+                    debug = false;
+                    Ok(())
+                }
+                value => Err(format!("Invalid debug value: {value:?}.")),
+            },
             "setoption" => {
-                assert_eq!(parts.next().unwrap(), "name");
+                let is_hash = parts.clone().nth(1) == Some("Hash");
+                let result = set_option(&mut parts, &mut ai, &mut komi, &mut multi_pv, size);
+
+                // Resizing the hash replaces the persistent state, which a running search
+                // holds until it's stopped. Invalid values leave both alone.
+                if is_hash && result.is_ok() {
+                    if let Some(search) = search.take() {
+                        search.stop(false).await;
+                    }
+                    reset_persistent_state(size, ai.hash_size);
+                }
 
-                match parts.next().expect("no option name") {
-                    "HalfKomi" => {
-                        assert_eq!(parts.next().unwrap(), "value");
+                result
+            }
+            "teinewgame" => match parse_value("size", parts.next()) {
+                Ok(new_size) if (3..=8).contains(&new_size) => {
+                    if let Some(search) = search.take() {
+                        search.stop(false).await;
+                    }
 
-                        let half_komi = parts
-                            .next()
-                            .expect("no half-komi value")
-                            .parse::<i8>()
-                            .expect("invalid half-komi value");
+                    size = new_size;
+                    game = new_game(komi);
+                    reset_persistent_state(size, ai.hash_size);
+                    Ok(())
+                }
+                Ok(new_size) => Err(format!("Invalid size: {new_size}.")),
+                Err(err) => Err(err),
+            },
+            "position" => parse_position(&mut parts, size, komi).map(|position| game = position),
+            "go" => match parse_go(&mut parts) {
+                Ok(go) => {
+                    // A search that's still running is stopped as if by `stop`, and the new
+                    // one waits for it to finish with the persistent state.
+                    if let Some(search) = search.take() {
+                        search.stop(true).await;
+                    }
 
-                        komi = Komi::from_half_komi(half_komi);
+                    if debug {
+                        println!("info string Searching with {go:?}");
                     }
-                    x => error!(option = ?x, "Unknown option."),
+
+                    begin_analysis(size, &game, ai.clone(), go, multi_pv)
+                        .map(|new| search = Some(new))
+                }
+                Err(err) => Err(err),
+            },
+            "stop" => {
+                if let Some(search) = search.take() {
+                    search.stop(true).await;
                 }
+                Ok(())
             }
-            "teinewgame" => {
-                size = parts
-                    .next()
-                    .expect("no size parameter")
-                    .parse::<usize>()
-                    .expect("invalid size parameter");
-
-                if !(3..=8).contains(&size) {
-                    panic!("invalid size parameter");
+            "ponderhit" => match search.as_mut().and_then(|search| search.pondering.take()) {
+                Some(pondering) => {
+                    // The search carries on with its time limits starting now, and reports
+                    // its best move when it's done.
+                    pondering.store(false, Ordering::Relaxed);
+                    let _ = search.as_ref().unwrap().release.try_send(true);
+                    Ok(())
+                }
+                None => Err("Not pondering.".to_owned()),
+            },
+            "quit" => {
+                if let Some(search) = search.take() {
+                    search.stop(false).await;
                 }
+                break;
+            }
+            _ => Err(format!("Unknown command: {command:?}.")),
+        };
+
+        if let Err(message) = result {
+            error!(input = ?message, "Invalid input.");
+            println!("info string Error: {message}");
+        }
+    }
+}
+
+fn new_game(komi: Komi) -> PtnGame {
+    let mut game = PtnGame::default();
+    game.add_header("Komi", komi);
+    game
+}
+
+fn parse_value<T: FromStr>(name: &str, value: Option<&str>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {name}."))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {name}: {value:?}."))
+}
+
+fn set_option(
+    parts: &mut SplitWhitespace,
+    ai: &mut Ai,
+    komi: &mut Komi,
+    multi_pv: &mut usize,
+    size: usize,
+) -> Result<(), String> {
+    if parts.next() != Some("name") {
+        return Err("Expected an option name.".to_owned());
+    }
+
+    let name = parts
+        .by_ref()
+        .take_while(|&part| part != "value")
+        .collect::<Vec<_>>()
+        .join(" ");
+    let value = parts.collect::<Vec<_>>().join(" ");
+    let value = Some(value.as_str()).filter(|value| !value.is_empty());
+
+    match name.as_str() {
+        "HalfKomi" => {
+            let half_komi: i8 = parse_value(&name, value)?;
+            *komi = Komi::from_half_komi(half_komi);
+        }
+        "Hash" => {
+            let hash_size: usize = parse_value(&name, value)?;
+            if hash_size == 0 {
+                return Err("Hash must be at least 1.".to_owned());
+            }
 
-                clear_persistent_state(size);
+            // The caller resizes the table, once any running search is stopped.
+            ai.hash_size = hash_size;
+        }
+        "Threads" => {
+            let threads: usize = parse_value(&name, value)?;
+            if threads == 0 {
+                return Err("Threads must be at least 1.".to_owned());
             }
-            "position" => {
-                match parts.next().expect("no position") {
-                    "startpos" => new_game!(),
-                    "tps" => {
-                        let tps = parts
-                            .next()
-                            .expect("no tps string")
-                            .parse::<Tps>()
-                            .expect("invalid tps string");
-
-                        new_game!();
-                        game.add_header("TPS", tps);
-                    }
-                    _ => panic!("invalid position"),
-                }
 
-                assert_eq!(parts.next().expect("no moves"), "moves");
+            ai.threads = threads;
+        }
+        "Model" => {
+            let model_file = value.filter(|&value| value != "<empty>");
+            if let Some(model_file) = model_file {
+                check_model(size, model_file)?;
+            }
 
-                for ply in parts {
-                    add_ply(size, &mut game, ply);
-                }
+            ai.model_file = model_file.map(ToOwned::to_owned);
+        }
+        "Exact" => ai.exact_eval = parse_value(&name, value)?,
+        "Ponder" => ai.ponder = parse_value(&name, value)?,
+        "MultiPV" => {
+            let lines: usize = parse_value(&name, value)?;
+            if lines == 0 {
+                return Err("MultiPV must be at least 1.".to_owned());
             }
-            "go" => {
-                let mut time_controls = (TimeControl::default(), TimeControl::default());
-
-                while let Some(part) = parts.next() {
-                    let time = match part {
-                        "wtime" => &mut time_controls.0.time,
-                        "btime" => &mut time_controls.1.time,
-                        "winc" => &mut time_controls.0.increment,
-                        "binc" => &mut time_controls.1.increment,
-                        _ => panic!("unexpected value"),
-                    };
-
-                    *time = Duration::from_millis(
-                        parts
-                            .next()
-                            .expect("no time value")
-                            .parse()
-                            .expect("invalid time value"),
-                    );
-                }
 
-                begin_analysis(size, &game, ai.clone(), time_controls).await;
+            *multi_pv = lines;
+        }
+        _ => return Err(format!("Unknown option: {name:?}.")),
+    }
+
+    Ok(())
+}
+
+/// Makes sure a model can be loaded for the current size before it's used in a search.
+fn check_model(size: usize, model_file: &str) -> Result<(), String> {
+    match size {
+        3 => load_model::<3>(model_file).map(drop),
+        4 => load_model::<4>(model_file).map(drop),
+        5 => load_model::<5>(model_file).map(drop),
+        6 => load_model::<6>(model_file).map(drop),
+        7 => load_model::<7>(model_file).map(drop),
+        8 => load_model::<8>(model_file).map(drop),
+        _ => unreachable!(),
+    }
+}
+
+fn parse_position(parts: &mut SplitWhitespace, size: usize, komi: Komi) -> Result<PtnGame, String> {
+    let mut game = new_game(komi);
+
+    match parts.next() {
+        Some("startpos") => match parts.next() {
+            Some("moves") | None => (),
+            Some(part) => return Err(format!("Expected moves, found {part:?}.")),
+        },
+        Some("tps") => {
+            let tps = parts
+                .by_ref()
+                .take_while(|&part| part != "moves")
+                .collect::<Vec<_>>()
+                .join(" ")
+                .parse::<Tps>()
+                .map_err(|err| format!("Invalid TPS: {err:?}."))?;
+
+            game.add_header("TPS", tps);
+        }
+        position => return Err(format!("Invalid position: {position:?}.")),
+    }
+
+    for ply in parts {
+        add_ply(size, &mut game, ply).map_err(|err| format!("Invalid ply {ply:?}: {err}"))?;
+    }
+
+    Ok(game)
+}
+
+fn parse_go(parts: &mut SplitWhitespace) -> Result<Go, String> {
+    let mut go = Go::default();
+    // Plies follow `searchmoves` until the next parameter.
+    let mut reading_plies = false;
+
+    while let Some(part) = parts.next() {
+        match part {
+            "wtime" | "btime" | "winc" | "binc" | "wdelay" | "bdelay" | "byoyomi" => {
+                let time = Duration::from_millis(parse_value(part, parts.next())?);
+                let clocks = &mut go.clocks;
+
+                match part {
+                    "wtime" => clocks.time[0] = Some(time),
+                    "btime" => clocks.time[1] = Some(time),
+                    "winc" => clocks.increment[0] = time,
+                    "binc" => clocks.increment[1] = time,
+                    "wdelay" => clocks.delay[0] = time,
+                    "bdelay" => clocks.delay[1] = time,
+                    _ => clocks.byo_yomi = Some(time),
+                }
             }
-            "quit" => break,
-            x => error!(input = ?x, "Unexpected input."),
+            "movestogo" => {
+                let moves_to_go: u32 = parse_value(part, parts.next())?;
+                go.clocks.moves_to_go = Some(moves_to_go.max(1));
+            }
+            "depth" => go.depth_limit = Some(parse_value(part, parts.next())?),
+            "nodes" => go.node_limit = Some(parse_value(part, parts.next())?),
+            "movetime" => {
+                go.move_time = Some(Duration::from_millis(parse_value(part, parts.next())?));
+            }
+            "infinite" => go.infinite = true,
+            "ponder" => go.ponder = true,
+            "searchmoves" => {
+                reading_plies = true;
+                continue;
+            }
+            _ if reading_plies => {
+                go.search_plies.push(part.to_owned());
+                continue;
+            }
+            _ => return Err(format!("Unknown go parameter: {part:?}.")),
         }
+
+        reading_plies = false;
     }
+
+    Ok(go)
 }
 
 static PERSISTENT_STATE_3S: Lazy<Mutex<PersistentState<3>>> = Lazy::new(Default::default);
@@ -143,35 +419,50 @@ static PERSISTENT_STATE_6S: Lazy<Mutex<PersistentState<6>>> = Lazy::new(Default:
 static PERSISTENT_STATE_7S: Lazy<Mutex<PersistentState<7>>> = Lazy::new(Default::default);
 static PERSISTENT_STATE_8S: Lazy<Mutex<PersistentState<8>>> = Lazy::new(Default::default);
 
-fn clear_persistent_state(size: usize) {
-    fn sized<const N: usize>(persistent_state: &'static Mutex<PersistentState<N>>) {
+// Opening books are loaded the first time they're needed for each size.
+static BOOK_3S: OnceCell<Option<OpeningBook<3>>> = OnceCell::new();
+static BOOK_4S: OnceCell<Option<OpeningBook<4>>> = OnceCell::new();
+static BOOK_5S: OnceCell<Option<OpeningBook<5>>> = OnceCell::new();
+static BOOK_6S: OnceCell<Option<OpeningBook<6>>> = OnceCell::new();
+static BOOK_7S: OnceCell<Option<OpeningBook<7>>> = OnceCell::new();
+static BOOK_8S: OnceCell<Option<OpeningBook<8>>> = OnceCell::new();
+
+/// Clears the persistent state for a size, resizing its transposition table if needed.
+fn reset_persistent_state(size: usize, hash_size: usize) {
+    fn sized<const N: usize>(
+        persistent_state: &'static Mutex<PersistentState<N>>,
+        hash_size: usize,
+    ) {
         let mut guard = persistent_state.lock().unwrap();
-        *guard = PersistentState::default();
+        guard.resize_table(hash_size);
     }
 
     match size {
-        3 => sized(&PERSISTENT_STATE_3S),
-        4 => sized(&PERSISTENT_STATE_4S),
-        5 => sized(&PERSISTENT_STATE_5S),
-        6 => sized(&PERSISTENT_STATE_6S),
-        7 => sized(&PERSISTENT_STATE_7S),
-        8 => sized(&PERSISTENT_STATE_8S),
+        3 => sized(&PERSISTENT_STATE_3S, hash_size),
+        4 => sized(&PERSISTENT_STATE_4S, hash_size),
+        5 => sized(&PERSISTENT_STATE_5S, hash_size),
+        6 => sized(&PERSISTENT_STATE_6S, hash_size),
+        7 => sized(&PERSISTENT_STATE_7S, hash_size),
+        8 => sized(&PERSISTENT_STATE_8S, hash_size),
         _ => unreachable!(),
     }
 }
 
-async fn begin_analysis(
+fn begin_analysis(
     size: usize,
     game: &PtnGame,
     ai: Ai,
-    time_controls: (TimeControl, TimeControl),
-) {
-    async fn sized<const N: usize>(
+    go: Go,
+    multi_pv: usize,
+) -> Result<Search, String> {
+    fn sized<const N: usize>(
         game: &PtnGame,
         ai: Ai,
-        time_controls: (TimeControl, TimeControl),
+        go: Go,
+        multi_pv: usize,
         persistent_state: &'static Mutex<PersistentState<N>>,
-    ) {
+        book: &'static OnceCell<Option<OpeningBook<N>>>,
+    ) -> Result<Search, String> {
         let Ai {
             depth_limit,
             time_limit,
@@ -179,9 +470,36 @@ async fn begin_analysis(
             exact_eval,
             threads,
             model_file,
-        } = ai;
-
-        let state: State<N> = game.clone().try_into().expect("could not create state");
+            book_file,
+            book_selection,
+            deterministic,
+            quiescence_depth,
+            aspiration_window,
+            ..
+        } = ai.clone();
+
+        let state: State<N> = game
+            .clone()
+            .try_into()
+            .map_err(|err| format!("Could not create state: {err:?}."))?;
+        let history = game
+            .get_history::<N>(RepetitionRule::default())
+            .map_err(|err| format!("Could not create history: {err:?}."))?;
+        let search_plies = go
+            .search_plies
+            .iter()
+            .map(|ply| {
+                ply.parse::<Ply<N>>()
+                    .map_err(|err| format!("{err:?}"))
+                    .and_then(|parsed| {
+                        state
+                            .validate_ply(parsed)
+                            .map_err(|err| format!("{err:?}"))?;
+                        Ok(parsed)
+                    })
+                    .map_err(|err| format!("Invalid search ply {ply:?}: {err}."))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
         struct AnalysisSender<const M: usize>(Sender<Analysis<M>>);
 
@@ -189,7 +507,7 @@ async fn begin_analysis(
             fn send(&self, value: Analysis<M>) -> Result<(), io::Error> {
                 self.0
                     .try_send(value)
-                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "could not send analysis"))
+                    .map_err(|_| io::Error::other("could not send analysis"))
             }
         }
 
@@ -198,95 +516,180 @@ async fn begin_analysis(
             (AnalysisSender::<N>(s), r)
         };
 
-        task::spawn_blocking(move || {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let (release, released) = channel::bounded(1);
+
+        // Infinite searches ignore the configured limits. Both they and ponder searches
+        // keep their best move until they're stopped, or the ponder is a hit.
+        let hold = go.infinite || go.ponder;
+        let limited = !go.infinite;
+        let pondering = Arc::new(AtomicBool::new(go.ponder));
+
+        let analysis_config = AnalysisConfig {
+            depth_limit: go.depth_limit.or(depth_limit.filter(|_| limited)),
+            time_limit: go.move_time.or(time_limit.filter(|_| limited)),
+            early_stop: early_stop && go.move_time.is_none(),
+            time_control: go.clocks.time_control(state.to_move()).filter(|_| limited),
+            interrupted: interrupted.clone(),
+            pondering: pondering.clone(),
+            exact_eval,
+            threads,
+            node_limit: go.node_limit,
+            book_selection,
+            multi_pv,
+            included_plies: (!search_plies.is_empty()).then_some(search_plies),
+            deterministic,
+            quiescence_depth,
+            aspiration_window: aspiration_window.map(Into::into),
+            ..Default::default()
+        };
+
+        let analysis = task::spawn_blocking(move || {
             let guard = persistent_state.lock().unwrap();
 
-            let evaluator = model_file.as_deref().map(load_model);
+            let evaluator = match model_file.as_deref().map(load_model).transpose() {
+                Ok(evaluator) => evaluator,
+                Err(err) => {
+                    println!("info string Error: {err} Searching without it.");
+                    None
+                }
+            };
+            let book = book.get_or_init(|| match book_file.as_deref().map(load_book).transpose() {
+                Ok(book) => book,
+                Err(err) => {
+                    println!("info string Error: Could not load opening book: {err:?}.");
+                    None
+                }
+            });
 
             let analysis_config = AnalysisConfig {
-                depth_limit,
-                time_limit,
-                early_stop,
-                time_control: Some(match state.to_move() {
-                    Color::White => time_controls.0,
-                    Color::Black => time_controls.1,
-                }),
                 persistent_state: Some(&*guard),
-                exact_eval,
                 evaluator: evaluator.as_deref(),
                 interim_analysis_sender: Some(Box::new(sender)),
-                threads,
-                ..Default::default()
+                history: Some(&history),
+                book: book.as_ref(),
+                ..analysis_config
             };
 
-            analyze(analysis_config, &state);
+            analyze(analysis_config, &state)
         });
 
-        task::spawn(async move {
-            let mut last_analysis = None;
-
-            let mut iteration = 0;
-
-            while let Ok(analysis) = receiver.recv().await {
-                iteration += 1;
-                // Or something.
-                let centiflats = (f32::from(analysis.evaluation) * 1000.0) as i32;
-                let nps = (analysis.stats.visited as f64 / analysis.time.as_secs_f64()) as u64;
-                let mut state = analysis.state.clone();
-
-                let mut info = String::from("info");
-                write!(info, " depth {iteration}").unwrap();
-                write!(info, " seldepth {}", analysis.depth).unwrap();
-                write!(info, " score cp {centiflats}").unwrap();
-                write!(info, " time {}", analysis.time.as_millis()).unwrap();
-                write!(info, " nodes {}", analysis.stats.visited).unwrap();
-                write!(info, " nps {nps}",).unwrap();
-                write!(info, " pv").unwrap();
-                for &ply in &analysis.principal_variation {
-                    let validation = state.execute_ply(ply).expect("invalid ply in pv");
-                    let ptn: PtnPly = (ply, validation).into();
-                    write!(info, " {ptn}").unwrap();
-                }
-                println!("{info}");
+        let task = task::spawn(report_analysis(
+            receiver,
+            analysis,
+            hold.then_some(released),
+            ai.ponder,
+        ));
+
+        Ok(Search {
+            interrupted,
+            release,
+            pondering: go.ponder.then_some(pondering),
+            task,
+        })
+    }
 
-                last_analysis = Some(analysis);
-            }
+    match size {
+        3 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_3S, &BOOK_3S),
+        4 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_4S, &BOOK_4S),
+        5 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_5S, &BOOK_5S),
+        6 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_6S, &BOOK_6S),
+        7 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_7S, &BOOK_7S),
+        8 => sized(game, ai, go, multi_pv, &PERSISTENT_STATE_8S, &BOOK_8S),
+        _ => unreachable!(),
+    }
+}
 
-            if let Some(analysis) = last_analysis {
-                if let Some(&ply) = analysis.principal_variation.first() {
-                    let validation = analysis
-                        .state
-                        .validate_ply(ply)
-                        .expect("invalid ply bestmove");
-                    let ptn: PtnPly = (ply, validation).into();
-                    println!("bestmove {ptn}");
-                } else {
-                    error!(?analysis, "No PV returned from search.");
-                }
+/// Prints each iteration of an analysis as it arrives, and then the best move from the
+/// last completed iteration, along with the expected reply if `ponder` is set. If `hold`
+/// is given, the best move is only printed once it receives true.
+async fn report_analysis<const N: usize>(
+    receiver: Receiver<Analysis<N>>,
+    analysis: JoinHandle<Analysis<N>>,
+    hold: Option<Receiver<bool>>,
+    ponder: bool,
+) {
+    let mut iteration = 0;
+
+    while let Ok(analysis) = receiver.recv().await {
+        iteration += 1;
+        let nps = (analysis.stats.visited as f64 / analysis.time.as_secs_f64()) as u64;
+
+        let lines = if analysis.lines.is_empty() {
+            vec![(analysis.evaluation, &analysis.principal_variation)]
+        } else {
+            analysis
+                .lines
+                .iter()
+                .map(|line| (line.evaluation, &line.principal_variation))
+                .collect()
+        };
+        let multi_pv = lines.len() > 1;
+
+        for (i, (evaluation, principal_variation)) in lines.into_iter().enumerate() {
+            // Or something.
+            let centiflats = (f32::from(evaluation) * 1000.0) as i32;
+            let mut state = analysis.state.clone();
+
+            let mut info = String::from("info");
+            write!(info, " depth {iteration}").unwrap();
+            write!(info, " seldepth {}", analysis.depth).unwrap();
+            if multi_pv {
+                write!(info, " multipv {}", i + 1).unwrap();
             }
-        });
+            write!(info, " score cp {centiflats}").unwrap();
+            write!(info, " time {}", analysis.time.as_millis()).unwrap();
+            write!(info, " nodes {}", analysis.stats.visited).unwrap();
+            write!(info, " nps {nps}",).unwrap();
+            write!(info, " hashfull {}", analysis.hashfull).unwrap();
+            write!(info, " pv").unwrap();
+            for &ply in principal_variation {
+                let validation = state.execute_ply(ply).expect("invalid ply in pv");
+                let ptn: PtnPly = (ply, validation).into();
+                write!(info, " {ptn}").unwrap();
+            }
+            println!("{info}");
+        }
     }
 
-    match size {
-        3 => sized(game, ai, time_controls, &PERSISTENT_STATE_3S).await,
-        4 => sized(game, ai, time_controls, &PERSISTENT_STATE_4S).await,
-        5 => sized(game, ai, time_controls, &PERSISTENT_STATE_5S).await,
-        6 => sized(game, ai, time_controls, &PERSISTENT_STATE_6S).await,
-        7 => sized(game, ai, time_controls, &PERSISTENT_STATE_7S).await,
-        8 => sized(game, ai, time_controls, &PERSISTENT_STATE_8S).await,
-        _ => unreachable!(),
+    let analysis = analysis.await;
+
+    if let Some(hold) = hold {
+        if !hold.recv().await.unwrap_or(true) {
+            return;
+        }
+    }
+
+    if !analysis.principal_variation.is_empty() {
+        let mut state = analysis.state.clone();
+        let mut plies = analysis.principal_variation.iter().map(|&ply| {
+            let validation = state.execute_ply(ply).expect("invalid ply in pv");
+            PtnPly::from((ply, validation))
+        });
+
+        let mut bestmove = format!("bestmove {}", plies.next().unwrap());
+        if let Some(reply) = plies.next().filter(|_| ponder) {
+            write!(bestmove, " ponder {reply}").unwrap();
+        }
+        println!("{bestmove}");
+    } else {
+        error!(?analysis, "No PV returned from search.");
     }
 }
 
-fn add_ply(size: usize, game: &mut PtnGame, ply: &str) {
+fn add_ply(size: usize, game: &mut PtnGame, ply: &str) -> Result<(), String> {
+    fn sized<const N: usize>(game: &mut PtnGame, ply: &str) -> Result<(), String> {
+        let ply: Ply<N> = ply.parse().map_err(|err| format!("{err:?}."))?;
+        game.add_ply(ply).map_err(|err| format!("{err:?}."))
+    }
+
     match size {
-        3 => game.add_ply::<3>(ply.parse().expect("invalid ply string")),
-        4 => game.add_ply::<4>(ply.parse().expect("invalid ply string")),
-        5 => game.add_ply::<5>(ply.parse().expect("invalid ply string")),
-        6 => game.add_ply::<6>(ply.parse().expect("invalid ply string")),
-        7 => game.add_ply::<7>(ply.parse().expect("invalid ply string")),
-        8 => game.add_ply::<8>(ply.parse().expect("invalid ply string")),
+        3 => sized::<3>(game, ply),
+        4 => sized::<4>(game, ply),
+        5 => sized::<5>(game, ply),
+        6 => sized::<6>(game, ply),
+        7 => sized::<7>(game, ply),
+        8 => sized::<8>(game, ply),
         _ => unreachable!(),
     }
-    .expect("could not add ply");
 }
@@ -0,0 +1,83 @@
+use std::time::Instant;
+
+use tracing::error;
+
+use analysis::{solve_tinue, TinueResult};
+use tak::{PtnGame, PtnHeader, State, Tps};
+
+use crate::args::TinueConfig;
+
+pub fn run_tinue(config: TinueConfig) {
+    let tps = match config.tps.parse::<Tps>() {
+        Ok(tps) => tps,
+        Err(err) => {
+            error!(error = ?err, "Invalid TPS string.");
+            return;
+        }
+    };
+
+    match tps.size() {
+        3 => run_tinue_sized::<3>(tps, config.moves),
+        4 => run_tinue_sized::<4>(tps, config.moves),
+        5 => run_tinue_sized::<5>(tps, config.moves),
+        6 => run_tinue_sized::<6>(tps, config.moves),
+        7 => run_tinue_sized::<7>(tps, config.moves),
+        8 => run_tinue_sized::<8>(tps, config.moves),
+        size => error!(?size, "Invalid board size."),
+    }
+}
+
+fn run_tinue_sized<const N: usize>(tps: Tps, moves: usize) {
+    let state: State<N> = match tps.clone().try_into() {
+        Ok(state) => state,
+        Err(err) => {
+            error!(error = ?err, "Could not create state.");
+            return;
+        }
+    };
+
+    let start_time = Instant::now();
+    let result = solve_tinue(&state, moves);
+    let elapsed = start_time.elapsed();
+
+    println!("{state}");
+
+    match result {
+        TinueResult::Win(line) => {
+            let mut game = PtnGame {
+                headers: vec![PtnHeader::new("TPS", tps)],
+                ..Default::default()
+            };
+
+            for &ply in &line {
+                game.add_ply(ply).expect("could not add tinue ply");
+            }
+
+            let winning_moves = line.len().div_ceil(2);
+            println!(
+                "\nTinuë for {:?} in {winning_moves} {}:",
+                state.to_move(),
+                moves_noun(winning_moves),
+            );
+            for turn in &game.turns {
+                println!("  {turn:<7}");
+            }
+            if let Some(result) = &game.result {
+                println!("  {result}");
+            }
+        }
+        TinueResult::NoTinue => {
+            println!("\nNo tinuë within {moves} {}.", moves_noun(moves));
+        }
+    }
+
+    println!("\nTime: {:.3}s", elapsed.as_secs_f64());
+}
+
+fn moves_noun(moves: usize) -> &'static str {
+    if moves == 1 {
+        "move"
+    } else {
+        "moves"
+    }
+}
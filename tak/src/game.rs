@@ -0,0 +1,671 @@
+use std::convert::TryFrom;
+use std::mem;
+
+use crate::history::{History, RepetitionRule};
+use crate::piece::Color;
+use crate::ply::Ply;
+use crate::ptn::{PtnError, PtnGame, PtnHeader, PtnMove, PtnPly, PtnTurn};
+use crate::state::{PlyValidation, Resolution, State, StateError};
+
+/// A game record: the starting state, the plies played from it, and any side variations,
+/// along with a cursor marking the current position.
+///
+/// Each node caches the state after its ply, so moving the cursor never replays plies.
+/// The first continuation of a node is its main line, and the rest are variations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Game<const N: usize> {
+    pub headers: Vec<PtnHeader>,
+    pub opening_comments: Vec<String>,
+    pub result: Option<String>,
+    pub closing_comments: Vec<String>,
+    root: Node<N>,
+    /// The continuation indices leading from the root to the current node.
+    cursor: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Node<const N: usize> {
+    ply: Option<Ply<N>>,
+    state: State<N>,
+    continuations: Vec<Node<N>>,
+    /// The PTN annotations written after the ply, such as `*` or `!?`.
+    pub annotations: Option<String>,
+    /// Numeric annotation glyphs, such as 1 for a good move.
+    pub nags: Vec<u8>,
+    pub comments: Vec<String>,
+    /// The comments on the `--` placeholder before the ply, when it starts a line with
+    /// Black to move.
+    pub placeholder_comments: Vec<String>,
+}
+
+impl<const N: usize> Node<N> {
+    fn new(ply: Option<Ply<N>>, state: State<N>) -> Self {
+        Self {
+            ply,
+            state,
+            continuations: Vec::new(),
+            annotations: None,
+            nags: Vec::new(),
+            comments: Vec::new(),
+            placeholder_comments: Vec::new(),
+        }
+    }
+
+    /// The ply leading to this node, or none for the starting position.
+    pub fn ply(&self) -> Option<Ply<N>> {
+        self.ply
+    }
+
+    /// The state after this node's ply.
+    pub fn state(&self) -> &State<N> {
+        &self.state
+    }
+
+    /// The plies played from this node, starting with the main line.
+    pub fn continuations(&self) -> &[Node<N>] {
+        &self.continuations
+    }
+}
+
+impl<const N: usize> Default for Game<N> {
+    fn default() -> Self {
+        Self::new(State::default())
+    }
+}
+
+impl<const N: usize> Game<N> {
+    pub fn new(state: State<N>) -> Self {
+        Self {
+            headers: Vec::new(),
+            opening_comments: Vec::new(),
+            result: None,
+            closing_comments: Vec::new(),
+            root: Node::new(None, state),
+            cursor: Vec::new(),
+        }
+    }
+
+    pub fn get_header(&self, key: &str) -> Option<&PtnHeader> {
+        self.headers.iter().find(|h| h.key == key)
+    }
+
+    pub fn add_header(&mut self, key: &str, value: impl std::fmt::Display) {
+        let header = PtnHeader::new(key, value);
+        if let Some(existing) = self.headers.iter_mut().find(|h| h.key == key) {
+            *existing = header;
+        } else {
+            self.headers.push(header);
+        }
+    }
+
+    pub fn remove_header(&mut self, key: &str) {
+        self.headers.retain(|h| h.key != key);
+    }
+
+    /// Records the result of the game in both the header and the move text.
+    pub fn set_result(&mut self, resolution: Option<Resolution>) {
+        if let Some(resolution) = resolution {
+            self.add_header("Result", resolution);
+            self.result = Some(resolution.to_string());
+        } else {
+            self.remove_header("Result");
+            self.result = None;
+        }
+    }
+
+    pub fn start_state(&self) -> &State<N> {
+        &self.root.state
+    }
+
+    /// The node at the cursor.
+    pub fn node(&self) -> &Node<N> {
+        self.cursor
+            .iter()
+            .fold(&self.root, |node, &i| &node.continuations[i])
+    }
+
+    pub fn node_mut(&mut self) -> &mut Node<N> {
+        self.cursor
+            .iter()
+            .fold(&mut self.root, |node, &i| &mut node.continuations[i])
+    }
+
+    /// The state at the cursor.
+    pub fn state(&self) -> &State<N> {
+        &self.node().state
+    }
+
+    /// The number of plies from the starting state to the cursor.
+    pub fn position(&self) -> usize {
+        self.cursor.len()
+    }
+
+    /// The number of plies from the starting state to the end of the line the cursor is on.
+    pub fn line_len(&self) -> usize {
+        let mut node = self.node();
+        let mut len = self.cursor.len();
+
+        while let Some(next) = node.continuations.first() {
+            node = next;
+            len += 1;
+        }
+
+        len
+    }
+
+    /// The nodes from the start of the game to the cursor, excluding the starting position.
+    fn path(&self) -> impl Iterator<Item = &Node<N>> {
+        self.cursor.iter().scan(&self.root, |node, &i| {
+            *node = &node.continuations[i];
+            Some(*node)
+        })
+    }
+
+    /// The plies played from the starting state to the cursor.
+    pub fn plies(&self) -> Vec<Ply<N>> {
+        self.path().filter_map(|node| node.ply).collect()
+    }
+
+    /// The positions reached from the starting state to the cursor.
+    pub fn history(&self, rule: RepetitionRule) -> History {
+        let mut history = History::new(rule, &self.root.state);
+        for node in self.path() {
+            history.push(node.ply.unwrap(), &node.state);
+        }
+        history
+    }
+
+    /// Plays a ply from the cursor and moves the cursor to it. If the ply has already been
+    /// played from here, the existing line is followed. Otherwise, it becomes a new variation,
+    /// or the main line if there are no continuations yet.
+    pub fn play(&mut self, ply: Ply<N>) -> Result<PlyValidation<N>, StateError> {
+        let node = self.node_mut();
+        let validation = node.state.validate_ply(ply)?;
+
+        let index = match node.continuations.iter().position(|n| n.ply == Some(ply)) {
+            Some(index) => index,
+            None => {
+                let mut state = node.state.clone();
+                state.execute_ply_unchecked(ply);
+
+                let mut next = Node::new(Some(ply), state);
+                next.annotations = validation.is_crush.then(|| "*".to_owned());
+
+                node.continuations.push(next);
+                node.continuations.len() - 1
+            }
+        };
+
+        self.cursor.push(index);
+
+        Ok(validation)
+    }
+
+    /// Moves the cursor back one ply. Returns false if it was already at the start.
+    pub fn back(&mut self) -> bool {
+        self.cursor.pop().is_some()
+    }
+
+    /// Moves the cursor forward one ply along the current line. Returns false if it was
+    /// already at the end.
+    pub fn forward(&mut self) -> bool {
+        if self.node().continuations.is_empty() {
+            false
+        } else {
+            self.cursor.push(0);
+            true
+        }
+    }
+
+    pub fn to_start(&mut self) {
+        self.cursor.clear();
+    }
+
+    pub fn to_end(&mut self) {
+        while self.forward() {}
+    }
+
+    /// Moves the cursor to the given number of plies from the starting state, along the
+    /// current line. Returns false, leaving the cursor unchanged, if the line is too short.
+    pub fn jump_to(&mut self, position: usize) -> bool {
+        if position > self.line_len() {
+            return false;
+        }
+
+        self.cursor.truncate(position);
+        while self.cursor.len() < position {
+            self.forward();
+        }
+
+        true
+    }
+
+    /// Moves the cursor to one of the continuations from the current node, where 0 is the
+    /// main line. Returns false if there is no such continuation.
+    pub fn enter_variation(&mut self, index: usize) -> bool {
+        if index < self.node().continuations.len() {
+            self.cursor.push(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Makes the variation containing the cursor the main line at the point where it
+    /// branches off. Returns false if the cursor is already on the main line.
+    pub fn promote_variation(&mut self) -> bool {
+        let Some(depth) = self.cursor.iter().rposition(|&i| i != 0) else {
+            return false;
+        };
+
+        let index = self.cursor[depth];
+        let parent = self.cursor[..depth]
+            .iter()
+            .fold(&mut self.root, |node, &i| &mut node.continuations[i]);
+
+        let variation = parent.continuations.remove(index);
+        parent.continuations.insert(0, variation);
+        self.cursor[depth] = 0;
+
+        true
+    }
+
+    /// Removes every continuation after the cursor.
+    pub fn truncate(&mut self) {
+        self.node_mut().continuations.clear();
+    }
+}
+
+/// Leaves the cursor at the end of the main line.
+impl<const N: usize> TryFrom<PtnGame> for Game<N> {
+    type Error = PtnError;
+
+    fn try_from(ptn: PtnGame) -> Result<Self, Self::Error> {
+        // Check the headers, turn numbers, plies and result of the main line all agree.
+        State::<N>::try_from(ptn.clone())?;
+
+        let mut game = Self::new(ptn.get_state_at_ply(0)?);
+        game.import_line(ptn.turns)?;
+
+        game.headers = ptn.headers;
+        game.opening_comments = ptn.opening_comments;
+        game.result = ptn.result;
+        game.closing_comments = ptn.closing_comments;
+
+        Ok(game)
+    }
+}
+
+impl<const N: usize> Game<N> {
+    /// Plays the turns from the cursor, adding their variations along the way.
+    fn import_line(&mut self, turns: Vec<PtnTurn>) -> Result<(), PtnError> {
+        let moves = turns.into_iter().flat_map(|t| {
+            [
+                (t.number, Color::White, t.p1_move),
+                (t.number, Color::Black, t.p2_move),
+            ]
+        });
+
+        let mut placeholder_comments = Vec::new();
+
+        for (number, color, ptn_move) in moves {
+            let PtnMove {
+                ply,
+                nags,
+                comments,
+                variations,
+            } = ptn_move;
+
+            let Some(ptn_ply) = ply else {
+                // Only the placeholder for a missing first move can have comments, which
+                // are kept with the ply after it.
+                placeholder_comments.extend(comments);
+                continue;
+            };
+
+            let state = self.state();
+            let current_turn = state.ply_count as u32 / 2 + 1;
+            if number != current_turn {
+                return Err(PtnError::IncorrectTurn(format!(
+                    "Stated turn is {number} but should be {current_turn}."
+                )));
+            }
+            if state.to_move() != color {
+                return Err(PtnError::InvalidPly("Incorrect player to move.".to_owned()));
+            }
+
+            let annotations = match &ptn_ply {
+                PtnPly::Place { annotations, .. } | PtnPly::Spread { annotations, .. } => {
+                    annotations.clone()
+                }
+            };
+
+            self.play(ptn_ply.try_into()?)?;
+
+            let node = self.node_mut();
+            node.annotations = annotations;
+            node.nags = nags;
+            node.comments = comments;
+            node.placeholder_comments = mem::take(&mut placeholder_comments);
+
+            if !variations.is_empty() {
+                let cursor = self.cursor.clone();
+                for variation in variations {
+                    self.cursor.truncate(cursor.len() - 1);
+                    self.import_line(variation)?;
+                }
+                self.cursor = cursor;
+            }
+        }
+
+        // A line with no plies has nowhere else to keep them.
+        self.node_mut().comments.extend(placeholder_comments);
+
+        Ok(())
+    }
+}
+
+/// Converts the main line of the game, with every variation.
+impl<const N: usize> From<&Game<N>> for PtnGame {
+    fn from(game: &Game<N>) -> Self {
+        let turns = export_line(&game.root, 0);
+
+        Self {
+            headers: game.headers.clone(),
+            opening_comments: game.opening_comments.clone(),
+            turns,
+            result: game.result.clone(),
+            closing_comments: game.closing_comments.clone(),
+        }
+    }
+}
+
+/// Converts the line starting with the given continuation of `parent`, then following the
+/// main line. Variations branching off the first ply belong to the line containing `parent`.
+fn export_line<const N: usize>(parent: &Node<N>, first: usize) -> Vec<PtnTurn> {
+    let mut turns = Vec::new();
+
+    if parent.state.to_move() == Color::Black {
+        let comments = parent
+            .continuations
+            .get(first)
+            .map(|next| next.placeholder_comments.clone())
+            .unwrap_or_default();
+
+        turns.push(PtnTurn {
+            number: parent.state.ply_count as u32 / 2 + 1,
+            p1_move: PtnMove {
+                comments,
+                ..Default::default()
+            },
+            p2_move: PtnMove::default(),
+        });
+    }
+
+    let mut node = parent;
+    let mut index = first;
+
+    while let Some(next) = node.continuations.get(index) {
+        let variations = if index == 0 {
+            (1..node.continuations.len())
+                .map(|i| export_line(node, i))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let ptn_move = PtnMove {
+            ply: next.ply.map(|ply| ptn_ply(ply, next.annotations.clone())),
+            nags: next.nags.clone(),
+            comments: next.comments.clone(),
+            variations,
+        };
+
+        match node.state.to_move() {
+            Color::White => turns.push(PtnTurn {
+                number: node.state.ply_count as u32 / 2 + 1,
+                p1_move: ptn_move,
+                p2_move: PtnMove::default(),
+            }),
+            Color::Black => turns.last_mut().unwrap().p2_move = ptn_move,
+        }
+
+        node = next;
+        index = 0;
+    }
+
+    turns
+}
+
+fn ptn_ply<const N: usize>(ply: Ply<N>, annotations: Option<String>) -> PtnPly {
+    match ply {
+        Ply::Place { x, y, piece_type } => PtnPly::Place {
+            x,
+            y,
+            piece_type,
+            annotations,
+        },
+        Ply::Spread {
+            x,
+            y,
+            direction,
+            drops,
+        } => PtnPly::Spread {
+            x,
+            y,
+            direction,
+            drops: drops.iter().collect(),
+            annotations,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ply<const N: usize>(ptn: &str) -> Ply<N> {
+        ptn.parse().unwrap()
+    }
+
+    fn play<const N: usize>(game: &mut Game<N>, plies: &str) {
+        for p in plies.split_whitespace() {
+            game.play(ply(p)).unwrap();
+        }
+    }
+
+    #[test]
+    fn ptn_round_trip() {
+        let text = r#"[Size "5"]
+[Komi "2"]
+[Player1 "Alice"]
+
+{An opening comment.}
+
+1. a1 e5 {Corner for corner.}
+2. b2? c3!?
+3. Cc2 {Capstone.} Sc1
+4. c2-* {Crush.} b3
+5. a4
+
+{A closing comment.}"#;
+
+        let ptn: PtnGame = text.parse().unwrap();
+        let game: Game<5> = ptn.clone().try_into().unwrap();
+
+        assert_eq!(game.position(), 9);
+        assert_eq!(game.line_len(), 9);
+        assert_eq!(PtnGame::from(&game), ptn);
+    }
+
+    #[test]
+    fn ptn_round_trip_black_first() {
+        let text = r#"[Size "5"]
+[TPS "x5/x5/x5/x5/2,1,x3 2 2"]
+
+2. -- {White passed?} b2 {Black replies.}
+3. c2 c3
+4. d3
+
+1/2-1/2"#;
+
+        let ptn: PtnGame = text.parse().unwrap();
+        let game: Game<5> = ptn.clone().try_into().unwrap();
+
+        assert_eq!(game.start_state().to_move(), Color::Black);
+        assert_eq!(game.line_len(), 4);
+        assert_eq!(PtnGame::from(&game), ptn);
+    }
+
+    #[test]
+    fn ptn_variations_round_trip() {
+        let text = r#"[Size "5"]
+[TPS "x5/x5/x5/x5/2,1,x3 2 2"]
+
+2. -- {White passed?} b2 (2. -- c3 3. c2 (3. d2 {Or d2.}) 3... d3) (2. -- e5)
+3. c2 $1 c3 {A {nested} comment.} (3. -- d3 $6)
+4. d3"#;
+
+        let ptn: PtnGame = text.parse().unwrap();
+        let mut game: Game<5> = ptn.clone().try_into().unwrap();
+
+        assert_eq!(game.line_len(), 4);
+        assert_eq!(game.root.continuations.len(), 3);
+        assert_eq!(PtnGame::from(&game), ptn);
+        assert_eq!(PtnGame::from(&game).to_string(), text);
+
+        game.to_start();
+        assert!(game.enter_variation(0));
+        assert!(game.enter_variation(0));
+        assert_eq!(game.node().nags, vec![1]);
+        assert!(game.enter_variation(1));
+        assert_eq!(game.node().ply(), Some(ply("d3")));
+        assert_eq!(game.node().nags, vec![6]);
+    }
+
+    #[test]
+    fn ptn_placeholder_comments_round_trip() {
+        let text = r#"[Size "5"]
+[TPS "x5/x5/x5/x5/2,1,x3 2 2"]
+
+2. -- {White passed?} b2 (2. -- {Or c3.} c3 3. c2 d3 (3. -- {Nested.} d4)) (2. -- e5)
+3. c2 c3 (3. -- {Or d3.} d3)
+4. d3"#;
+
+        let ptn: PtnGame = text.parse().unwrap();
+        let mut game: Game<5> = ptn.clone().try_into().unwrap();
+
+        assert_eq!(game.root.comments, Vec::<String>::new());
+        assert_eq!(PtnGame::from(&game), ptn);
+        assert_eq!(PtnGame::from(&game).to_string(), text);
+
+        game.to_start();
+        assert!(game.enter_variation(1));
+        assert_eq!(game.node().placeholder_comments, vec!["Or c3."]);
+        play(&mut game, "c2");
+        assert!(game.enter_variation(1));
+        assert_eq!(game.node().ply(), Some(ply("d4")));
+        assert_eq!(game.node().placeholder_comments, vec!["Nested."]);
+    }
+
+    #[test]
+    fn ptn_variation_with_wrong_turn() {
+        // The parser rejects these, so the variations are changed after parsing.
+        let mut ptn: PtnGame = "1. a1 (1. e1) e5".parse().unwrap();
+        ptn.turns[0].p1_move.variations[0][0].number = 2;
+        assert!(Game::<5>::try_from(ptn).is_err());
+
+        let mut ptn: PtnGame = "1. a1 (1. e1) e5".parse().unwrap();
+        let variation = &mut ptn.turns[0].p1_move.variations[0][0];
+        variation.p2_move = mem::take(&mut variation.p1_move);
+        assert!(Game::<5>::try_from(ptn).is_err());
+    }
+
+    #[test]
+    fn navigation() {
+        let mut game = Game::<5>::default();
+        play(&mut game, "a1 e5 b2 d4");
+        assert_eq!(game.position(), 4);
+        assert_eq!(game.state().ply_count, 4);
+
+        assert!(game.back());
+        assert!(game.back());
+        assert_eq!(game.position(), 2);
+        assert_eq!(game.line_len(), 4);
+        assert_eq!(game.plies(), vec![ply("a1"), ply("e5")]);
+
+        assert!(game.forward());
+        assert_eq!(game.node().ply(), Some(ply("b2")));
+
+        game.to_end();
+        assert_eq!(game.position(), 4);
+        assert!(!game.forward());
+
+        assert!(game.jump_to(1));
+        assert_eq!(game.state().ply_count, 1);
+        assert!(!game.jump_to(5));
+        assert_eq!(game.position(), 1);
+
+        game.to_start();
+        assert!(!game.back());
+        assert_eq!(game.state(), game.start_state());
+    }
+
+    #[test]
+    fn variations() {
+        let mut game = Game::<5>::default();
+        play(&mut game, "a1 e5 b2 d4");
+
+        // Replaying the main line follows it instead of adding a variation.
+        game.jump_to(2);
+        play(&mut game, "b2");
+        assert_eq!(game.position(), 3);
+        assert_eq!(game.line_len(), 4);
+
+        game.back();
+        play(&mut game, "c3 c4");
+        assert_eq!(game.line_len(), 4);
+        assert!(game.promote_variation());
+        assert_eq!(game.position(), 4);
+        assert_eq!(game.node().ply(), Some(ply("c4")));
+        assert!(!game.promote_variation());
+
+        game.to_start();
+        game.to_end();
+        assert_eq!(
+            game.plies(),
+            vec![ply("a1"), ply("e5"), ply("c3"), ply("c4")]
+        );
+
+        game.jump_to(2);
+        let continuations: Vec<_> = game
+            .node()
+            .continuations()
+            .iter()
+            .map(|n| n.ply().unwrap())
+            .collect();
+        assert_eq!(continuations, vec![ply("c3"), ply("b2")]);
+
+        assert!(game.enter_variation(1));
+        assert_eq!(game.node().ply(), Some(ply("b2")));
+        assert!(!game.enter_variation(1));
+
+        game.truncate();
+        assert_eq!(game.line_len(), 3);
+
+        assert!(game.play(ply("b2")).is_err());
+    }
+
+    #[test]
+    fn history() {
+        let mut game = Game::<5>::default();
+        play(&mut game, "a1 e5 a2 e4 a2- e4+ a1+ e5- a2- e4+ a1+ e5-");
+
+        let history = game.history(RepetitionRule::default());
+        assert_eq!(history.resolution(game.state()), Some(Resolution::Draw));
+
+        game.back();
+        let history = game.history(RepetitionRule::default());
+        assert_eq!(history.resolution(game.state()), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::ply::Ply;
+use crate::state::{Resolution, State};
+use crate::zobrist::ZobristHash;
+
+/// Determines when repeating a position ends the game in a draw.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RepetitionRule {
+    /// Repeated positions never end the game.
+    Disabled,
+    /// The game is drawn once the same position has occurred this many times.
+    Count(usize),
+}
+
+impl Default for RepetitionRule {
+    fn default() -> Self {
+        Self::Count(3)
+    }
+}
+
+/// The positions reached over the course of a game, identified by their Zobrist hashes.
+///
+/// A placement can never be undone, so no position before one can occur again. Only
+/// positions since the last placement are compared when looking for repetitions.
+#[derive(Clone, Debug)]
+pub struct History {
+    pub rule: RepetitionRule,
+    positions: Vec<Position>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    hash: ZobristHash,
+    /// Whether no earlier position can occur again.
+    irreversible: bool,
+}
+
+impl History {
+    /// Creates a history starting at the given state.
+    pub fn new<const N: usize>(rule: RepetitionRule, state: &State<N>) -> Self {
+        Self {
+            rule,
+            positions: vec![Position {
+                hash: state.metadata.hash,
+                irreversible: true,
+            }],
+        }
+    }
+
+    /// Records the state reached by executing `ply`.
+    pub fn push<const N: usize>(&mut self, ply: Ply<N>, state: &State<N>) {
+        self.positions.push(Position {
+            hash: state.metadata.hash,
+            irreversible: matches!(ply, Ply::Place { .. }),
+        });
+    }
+
+    /// Records a position that can't repeat any earlier position, such as one reached
+    /// by a null move.
+    pub fn push_irreversible(&mut self, hash: ZobristHash) {
+        self.positions.push(Position {
+            hash,
+            irreversible: true,
+        });
+    }
+
+    /// Forgets the most recently recorded position.
+    pub fn pop(&mut self) {
+        self.positions.pop();
+    }
+
+    /// Returns how many plies ago the current position last occurred, if it occurred before.
+    pub fn plies_since_repetition(&self) -> Option<usize> {
+        let (current, earlier) = self.positions.split_last()?;
+
+        if current.irreversible {
+            return None;
+        }
+
+        for (plies_ago, position) in earlier.iter().rev().enumerate() {
+            if position.hash == current.hash {
+                return Some(plies_ago + 1);
+            }
+
+            if position.irreversible {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the number of times the current position has occurred, including now.
+    pub fn repetitions(&self) -> usize {
+        let Some((current, earlier)) = self.positions.split_last() else {
+            return 0;
+        };
+
+        let mut repetitions = 1;
+
+        if !current.irreversible {
+            for position in earlier.iter().rev() {
+                if position.hash == current.hash {
+                    repetitions += 1;
+                }
+
+                if position.irreversible {
+                    break;
+                }
+            }
+        }
+
+        repetitions
+    }
+
+    /// Returns whether the game is drawn by repetition under the current rule.
+    pub fn is_draw(&self) -> bool {
+        match self.rule {
+            RepetitionRule::Disabled => false,
+            RepetitionRule::Count(count) => self.repetitions() >= count,
+        }
+    }
+
+    /// Like [`State::resolution`], but also detects draws by repetition. The state must be
+    /// the most recently recorded position.
+    pub fn resolution<const N: usize>(&self, state: &State<N>) -> Option<Resolution> {
+        debug_assert_eq!(
+            self.positions.last().map(|p| p.hash),
+            Some(state.metadata.hash)
+        );
+
+        state
+            .resolution()
+            .or_else(|| self.is_draw().then_some(Resolution::Draw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play<const N: usize>(state: &mut State<N>, history: &mut History, plies: &str) {
+        for ply in plies.split_whitespace() {
+            let ply: Ply<N> = ply.parse().unwrap();
+            state.execute_ply(ply).unwrap();
+            history.push(ply, state);
+        }
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut state: State<5> = "x5/x5/x5/x5/x5 1 1".parse().unwrap();
+        let mut history = History::new(RepetitionRule::default(), &state);
+
+        play(&mut state, &mut history, "a1 e5 a2 e4");
+        assert_eq!(history.repetitions(), 1);
+
+        play(&mut state, &mut history, "a2- e4+ a1+ e5-");
+        assert_eq!(history.repetitions(), 2);
+        assert_eq!(history.plies_since_repetition(), Some(4));
+        assert_eq!(history.resolution(&state), None);
+
+        play(&mut state, &mut history, "a2- e4+ a1+ e5-");
+        assert_eq!(history.repetitions(), 3);
+        assert_eq!(history.resolution(&state), Some(Resolution::Draw));
+
+        history.pop();
+        assert_eq!(history.repetitions(), 2);
+    }
+
+    #[test]
+    fn placements_reset_repetitions() {
+        let mut state: State<5> = "x5/x5/x5/x5/x5 1 1".parse().unwrap();
+        let mut history = History::new(RepetitionRule::Count(2), &state);
+
+        play(&mut state, &mut history, "a1 e5 a2 e4 a2- e4+ a1+ e5-");
+        assert!(history.is_draw());
+
+        play(&mut state, &mut history, "c3");
+        assert_eq!(history.repetitions(), 1);
+        assert_eq!(history.plies_since_repetition(), None);
+
+        play(&mut state, &mut history, "e4+ a2- e5- a1+");
+        assert_eq!(history.repetitions(), 2);
+        assert!(history.is_draw());
+
+        history.push_irreversible(state.metadata.hash);
+        assert_eq!(history.repetitions(), 1);
+    }
+
+    #[test]
+    fn disabled_rule() {
+        let mut state: State<5> = "x5/x5/x5/x5/x5 1 1".parse().unwrap();
+        let mut history = History::new(RepetitionRule::Disabled, &state);
+
+        play(
+            &mut state,
+            &mut history,
+            "a1 e5 a2 e4 a2- e4+ a1+ e5- a2- e4+ a1+ e5-",
+        );
+        assert_eq!(history.repetitions(), 3);
+        assert!(!history.is_draw());
+        assert_eq!(history.resolution(&state), None);
+    }
+}
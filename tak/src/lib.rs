@@ -1,21 +1,29 @@
 pub use self::bitmap::{board_mask, center_mask, edge_masks, Bitmap, GroupIter};
+pub use self::game::{Game, Node};
+pub use self::history::{History, RepetitionRule};
 pub use self::metadata::Metadata;
+pub use self::perft::{perft, perft_divide};
 pub use self::piece::{Color, Piece, PieceType};
 pub use self::ply::{generation, Direction, Drops, Ply, PlyError};
-pub use self::ptn::{PtnError, PtnGame, PtnHeader, PtnMove, PtnPly, PtnTurn};
+pub use self::ptn::{PtnError, PtnGame, PtnHeader, PtnMove, PtnPly, PtnReader, PtnTurn, PtnWriter};
 pub use self::stack::{Stack, StackBitmap, StackIter};
-pub use self::state::{Komi, Resolution, State, StateError};
+pub use self::state::{Komi, Resolution, State, StateError, UndoInfo};
+pub use self::symmetry::Symmetry;
 pub use self::tps::{Tps, TpsError};
 pub use self::zobrist::{
-    zobrist_advance_move, zobrist_hash_stack, zobrist_hash_state, ZobristHash,
+    zobrist_advance_move, zobrist_hash_stack, zobrist_hash_state, zobrist_seed, ZobristHash,
 };
 
 mod bitmap;
+mod game;
+mod history;
 mod metadata;
+mod perft;
 mod piece;
 mod ply;
 mod ptn;
 mod stack;
 mod state;
+mod symmetry;
 mod tps;
 mod zobrist;
@@ -0,0 +1,213 @@
+use crate::ply::{generation, Ply};
+use crate::state::State;
+
+/// Counts the positions reachable from `state` in exactly `depth` plies.
+/// Positions where the game has ended have no legal plies, so they only
+/// count when they are reached at the final depth.
+pub fn perft<const N: usize>(state: &mut State<N>, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    if state.resolution().is_some() {
+        return 0;
+    }
+
+    let plies = generation::legal_plies(state);
+
+    if depth == 1 {
+        return plies.len() as u64;
+    }
+
+    let mut count = 0;
+    for ply in plies {
+        let undo_info = state.execute_ply_unchecked(ply);
+        count += perft(state, depth - 1);
+        state.undo_ply(ply, undo_info);
+    }
+
+    count
+}
+
+/// Like `perft`, but splits the count by the first ply played. Useful for
+/// narrowing down which subtree a move generation bug is in.
+pub fn perft_divide<const N: usize>(state: &mut State<N>, depth: usize) -> Vec<(Ply<N>, u64)> {
+    if depth == 0 || state.resolution().is_some() {
+        return Vec::new();
+    }
+
+    generation::legal_plies(state)
+        .into_iter()
+        .map(|ply| {
+            let undo_info = state.execute_ply_unchecked(ply);
+            let count = perft(state, depth - 1);
+            state.undo_ply(ply, undo_info);
+            (ply, count)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceType;
+    use crate::ply::{Direction, Drops};
+
+    /// Every ply on the board that `validate_ply` accepts, found without
+    /// using the move generator.
+    fn brute_force_plies<const N: usize>(state: &State<N>) -> Vec<Ply<N>> {
+        use Direction::*;
+        use PieceType::*;
+
+        let mut plies = Vec::new();
+
+        for x in 0..N as u8 {
+            for y in 0..N as u8 {
+                for piece_type in [Flatstone, StandingStone, Capstone] {
+                    plies.push(Ply::Place { x, y, piece_type });
+                }
+
+                for direction in [North, East, South, West] {
+                    for value in 1..=(u8::MAX >> (8 - N)) {
+                        if let Ok(drops) = Drops::new::<N>(value) {
+                            plies.push(Ply::Spread {
+                                x,
+                                y,
+                                direction,
+                                drops,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        plies.retain(|&ply| ply.validate().is_ok() && state.validate_ply(ply).is_ok());
+        plies
+    }
+
+    fn brute_force_perft<const N: usize>(state: &State<N>, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        if state.resolution().is_some() {
+            return 0;
+        }
+
+        brute_force_plies(state)
+            .into_iter()
+            .map(|ply| {
+                let mut state = state.clone();
+                state.execute_ply_unchecked(ply);
+                brute_force_perft(&state, depth - 1)
+            })
+            .sum()
+    }
+
+    fn check_counts<const N: usize>(tps: &str, counts: &[u64]) {
+        let mut state: State<N> = tps.parse().unwrap();
+        let original = state.clone();
+
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(perft(&mut state, depth), count, "perft({depth}) of {tps}");
+            assert_eq!(state, original, "perft({depth}) of {tps} changed the state");
+        }
+    }
+
+    #[test]
+    fn start_positions() {
+        check_counts::<3>("x3/x3/x3 1 1", &[1, 9, 72, 1200, 17792, 271812]);
+        check_counts::<4>("x4/x4/x4/x4 1 1", &[1, 16, 240, 7440, 216464]);
+        check_counts::<5>("x5/x5/x5/x5/x5 1 1", &[1, 25, 600, 43320, 2999784]);
+        check_counts::<6>("x6/x6/x6/x6/x6/x6 1 1", &[1, 36, 1260, 132720, 13586048]);
+        check_counts::<7>("x7/x7/x7/x7/x7/x7/x7 1 1", &[1, 49, 2352, 339696]);
+        check_counts::<8>("x8/x8/x8/x8/x8/x8/x8/x8 1 1", &[1, 64, 4032, 764064]);
+    }
+
+    #[test]
+    fn midgame_positions() {
+        check_counts::<3>("2,x,1/x,21,x/1,x,2 1 3", &[1, 20, 228, 3680, 42560, 647316]);
+        check_counts::<4>(
+            "2,x,21,1/x,12S,1,x/2,x,2,x/x2,1,x 1 6",
+            &[1, 32, 1088, 34219, 1091631],
+        );
+        check_counts::<5>(
+            "x2,2,x2/x,2,1S,21C,x/12,x,1,12C,x/x,2,2S,1,x/x,1,x3 1 8",
+            &[1, 42, 2329, 99830, 5317276],
+        );
+        check_counts::<6>(
+            "2,x,1,1,x2/x,2,221S,x,1,x/x,2,12C,1,21C,x/x2,2S,1,x2/1,x,2,x3/x6 2 10",
+            &[1, 65, 5503, 370006],
+        );
+        check_counts::<7>(
+            "x2,2,x4/x,1,x,21,x3/x,2,12C,1,x3/x2,1S,2C,x3/x3,1,x3/x7/x7 1 6",
+            &[1, 141, 13863, 1920677],
+        );
+        check_counts::<8>(
+            "x3,2,x4/x2,1,21,x4/x,2,12C,1,x4/x3,2C,1S,x3/x4,1,x3/x8/x8/x8 1 6",
+            &[1, 186, 24231, 4456877],
+        );
+    }
+
+    #[test]
+    fn ended_game_has_no_plies() {
+        let mut state: State<3> = "1,1,1/2,2,x/x3 2 3".parse().unwrap();
+        assert!(state.resolution().is_some());
+        assert_eq!(perft(&mut state, 0), 1);
+        assert_eq!(perft(&mut state, 1), 0);
+        assert_eq!(perft(&mut state, 2), 0);
+        assert!(perft_divide(&mut state, 1).is_empty());
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut state: State<5> = "x2,2,x2/x,2,1S,21C,x/12,x,1,12C,x/x,2,2S,1,x/x,1,x3 1 8"
+            .parse()
+            .unwrap();
+        let divide = perft_divide(&mut state, 2);
+        assert_eq!(
+            divide.iter().map(|(_, count)| count).sum::<u64>(),
+            perft(&mut state, 2)
+        );
+    }
+
+    fn generator_matches_brute_force_sized<const N: usize>(tps: &str, depth: usize) {
+        let mut state: State<N> = tps.parse().unwrap();
+
+        let mut generated = generation::legal_plies(&state);
+        let mut brute_forced = brute_force_plies(&state);
+        generated.sort_by_key(|ply| format!("{ply:?}"));
+        brute_forced.sort_by_key(|ply| format!("{ply:?}"));
+        assert_eq!(generated, brute_forced, "legal plies of {tps}");
+
+        assert_eq!(
+            perft(&mut state, depth),
+            brute_force_perft(&state, depth),
+            "perft({depth}) of {tps}"
+        );
+    }
+
+    #[test]
+    fn generator_matches_brute_force() {
+        generator_matches_brute_force_sized::<3>("x3/x3/x3 1 1", 4);
+        generator_matches_brute_force_sized::<3>("2,x,1/x,21,x/1,x,2 1 3", 3);
+        generator_matches_brute_force_sized::<4>("2,x,21,1/x,12S,1,x/2,x,2,x/x2,1,x 1 6", 2);
+        generator_matches_brute_force_sized::<5>(
+            "x2,2,x2/x,2,1S,21C,x/12,x,1,12C,x/x,2,2S,1,x/x,1,x3 1 8",
+            2,
+        );
+        generator_matches_brute_force_sized::<6>(
+            "2,x,1,1,x2/x,2,221S,x,1,x/x,2,12C,1,21C,x/x2,2S,1,x2/1,x,2,x3/x6 2 10",
+            1,
+        );
+        generator_matches_brute_force_sized::<7>(
+            "x2,2,x4/x,1,x,21,x3/x,2,12C,1,x3/x2,1S,2C,x3/x3,1,x3/x7/x7 1 6",
+            1,
+        );
+        generator_matches_brute_force_sized::<8>(
+            "x3,2,x4/x2,1,21,x4/x,2,12C,1,x4/x3,2C,1S,x3/x4,1,x3/x8/x8/x8 1 6",
+            1,
+        );
+    }
+}
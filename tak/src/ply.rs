@@ -5,7 +5,7 @@ use std::ops::RangeInclusive;
 use once_cell::sync::Lazy;
 use tracing::{instrument, trace};
 
-use crate::bitmap::Bitmap;
+use crate::bitmap::{board_mask, Bitmap};
 use crate::piece::{Color, Piece, PieceType};
 use crate::ptn::PtnPly;
 use crate::stack::{Stack, StackBitmap};
@@ -126,7 +126,7 @@ impl Drops {
             return Err(PlyError::InvalidDrops("Must specify at least one drop."));
         }
 
-        if drops.iter().any(|d| *d == 0) {
+        if drops.contains(&0) {
             return Err(PlyError::InvalidDrops("Invalid drop amount."));
         }
 
@@ -211,6 +211,7 @@ impl From<Drops> for u8 {
 pub enum PlyError {
     OutOfBounds,
     InvalidDrops(&'static str),
+    InvalidPieceType,
 }
 
 pub mod generation {
@@ -218,6 +219,41 @@ pub mod generation {
 
     pub(crate) use self::spread_maps::spread_map;
 
+    /// Generates every legal ply for the player to move. Positions where the
+    /// game has already ended are not treated specially.
+    pub fn legal_plies<const N: usize>(state: &State<N>) -> Vec<Ply<N>> {
+        use PieceType::*;
+
+        let m = &state.metadata;
+        let empty = board_mask() ^ m.p1_pieces ^ m.p2_pieces;
+
+        let mut plies = Vec::new();
+
+        // Only the opponent's flatstones can be placed on the first turn.
+        if state.ply_count < 2 {
+            plies.extend(placements(empty, Flatstone));
+            return plies;
+        }
+
+        let (flatstones, capstones, stacks) = match state.to_move() {
+            Color::White => (state.p1_flatstones, state.p1_capstones, m.p1_pieces),
+            Color::Black => (state.p2_flatstones, state.p2_capstones, m.p2_pieces),
+        };
+
+        if flatstones > 0 {
+            plies.extend(placements(empty, Flatstone));
+            plies.extend(placements(empty, StandingStone));
+        }
+
+        if capstones > 0 {
+            plies.extend(placements(empty, Capstone));
+        }
+
+        plies.extend(spreads(state, stacks));
+
+        plies
+    }
+
     pub fn placements<const N: usize>(
         locations: Bitmap<N>,
         piece_type: PieceType,
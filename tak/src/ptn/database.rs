@@ -0,0 +1,242 @@
+//! Reading and writing files containing many games, such as tournament archives, where
+//! each game starts with its headers.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error as IoError, Write};
+use std::path::Path;
+
+use super::{PtnError, PtnGame};
+
+/// Reads the games in a PTN database one at a time, without loading the whole file.
+///
+/// A game that fails to parse is returned as an error, and reading continues with the next
+/// game. Line numbers in parse errors count from the start of the database.
+pub struct PtnReader<R> {
+    reader: R,
+    /// The number of lines read so far.
+    line: usize,
+    /// The first line of the next game, which was read while looking for the end of the
+    /// previous one.
+    pending: Option<(usize, String)>,
+    done: bool,
+}
+
+impl PtnReader<BufReader<File>> {
+    pub fn open(filename: impl AsRef<Path>) -> Result<Self, IoError> {
+        Ok(Self::new(BufReader::new(File::open(filename)?)))
+    }
+}
+
+impl<R: BufRead> PtnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Reads the text of the next game, along with the line it starts on.
+    fn read_game(&mut self) -> Result<Option<(usize, String)>, IoError> {
+        let (mut start, mut text) = self
+            .pending
+            .take()
+            .unwrap_or((self.line + 1, String::new()));
+
+        let mut in_move_text = false;
+        let mut after_blank = false;
+        let mut comment_depth = 0usize;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                self.done = true;
+                break;
+            }
+            self.line += 1;
+
+            let trimmed = line.trim();
+            let is_header = comment_depth == 0 && trimmed.starts_with('[');
+
+            if text.trim().is_empty() {
+                // Skip blank lines between games.
+                if trimmed.is_empty() {
+                    text.clear();
+                    start = self.line + 1;
+                    continue;
+                }
+            } else if is_header && (in_move_text || after_blank) {
+                self.pending = Some((self.line, line));
+                break;
+            }
+
+            if !is_header {
+                for c in trimmed.chars() {
+                    match c {
+                        '{' => comment_depth += 1,
+                        '}' => comment_depth = comment_depth.saturating_sub(1),
+                        _ => (),
+                    }
+                }
+                in_move_text |= !trimmed.is_empty();
+            }
+            after_blank = trimmed.is_empty();
+
+            text.push_str(&line);
+        }
+
+        if text.trim().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((start, text)))
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PtnReader<R> {
+    type Item = Result<PtnGame, PtnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done && self.pending.is_none() {
+            return None;
+        }
+
+        let (start, text) = match self.read_game() {
+            Ok(game) => game?,
+            Err(err) => {
+                self.done = true;
+                self.pending = None;
+                return Some(Err(err.into()));
+            }
+        };
+
+        Some(text.parse().map_err(|err| match err {
+            PtnError::Parse {
+                line,
+                column,
+                error,
+            } => PtnError::Parse {
+                line: line + start - 1,
+                column,
+                error,
+            },
+            err => err,
+        }))
+    }
+}
+
+/// Writes games one after another, separated by blank lines, in a form `PtnReader` can read.
+pub struct PtnWriter<W> {
+    writer: W,
+    /// Whether anything has been written before the next game.
+    separate: bool,
+}
+
+impl PtnWriter<File> {
+    /// Opens a database for adding games to the end of it, creating it if necessary.
+    pub fn append(filename: impl AsRef<Path>) -> Result<Self, IoError> {
+        let file = File::options().append(true).create(true).open(filename)?;
+        let separate = file.metadata()?.len() > 0;
+
+        Ok(Self {
+            writer: file,
+            separate,
+        })
+    }
+}
+
+impl<W: Write> PtnWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            separate: false,
+        }
+    }
+
+    pub fn write_game(&mut self, game: &PtnGame) -> Result<(), IoError> {
+        if self.separate {
+            writeln!(self.writer)?;
+        }
+        writeln!(self.writer, "{game}")?;
+        self.separate = true;
+
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE: &str = r#"[Size "3"]
+[Player1 "Alice"]
+
+1. a1 c3
+2. b2 {A comment with
+[brackets] across lines.} b3
+0-R
+
+[Size "3"]
+1. a1 c3 2. z9
+
+[Size "5"]
+[Player1 "Bob"]
+
+
+
+[Size "4"]
+1. a1 d4
+"#;
+
+    #[test]
+    fn read_games() {
+        let games: Vec<_> = PtnReader::new(DATABASE.as_bytes()).collect();
+        assert_eq!(games.len(), 4);
+
+        let first = games[0].as_ref().unwrap();
+        assert_eq!(first.get_header("Player1").unwrap().value, "Alice");
+        assert_eq!(first.get_ply_len(), 4);
+        assert_eq!(first.result.as_deref(), Some("0-R"));
+
+        match &games[1] {
+            Err(PtnError::Parse { line, column, .. }) => assert_eq!((*line, *column), (10, 13)),
+            result => panic!("expected a parse error, got {result:?}"),
+        }
+
+        let third = games[2].as_ref().unwrap();
+        assert_eq!(third.get_size(), Some(5));
+        assert!(third.turns.is_empty());
+
+        let fourth = games[3].as_ref().unwrap();
+        assert_eq!(fourth.get_size(), Some(4));
+        assert_eq!(fourth.get_ply_len(), 2);
+    }
+
+    #[test]
+    fn write_then_read() {
+        let games: Vec<PtnGame> = PtnReader::new(DATABASE.as_bytes())
+            .filter_map(Result::ok)
+            .collect();
+
+        let mut writer = PtnWriter::new(Vec::new());
+        for game in &games {
+            writer.write_game(game).unwrap();
+        }
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+
+        let read: Vec<PtnGame> = PtnReader::new(written.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, games);
+    }
+
+    #[test]
+    fn empty_database() {
+        assert_eq!(PtnReader::new("\n\n  \n".as_bytes()).count(), 0);
+    }
+}
@@ -8,11 +8,19 @@ use std::str::FromStr;
 use once_cell::sync::Lazy;
 use regex::Regex;
 
+use crate::history::{History, RepetitionRule};
 use crate::piece::{Color, PieceType};
 use crate::ply::{Direction, Drops, Ply, PlyError};
 use crate::state::{Komi, PlyValidation, State, StateError};
 use crate::tps::{Tps, TpsError};
 
+pub use self::database::{PtnReader, PtnWriter};
+
+use self::parser::Parser;
+
+mod database;
+mod parser;
+
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct PtnGame {
     pub headers: Vec<PtnHeader>,
@@ -178,6 +186,19 @@ impl PtnGame {
 
         game.try_into()
     }
+
+    /// Returns the positions reached over the course of the game, for detecting repetitions.
+    pub fn get_history<const N: usize>(&self, rule: RepetitionRule) -> Result<History, PtnError> {
+        let mut state = self.get_state_at_ply::<N>(0)?;
+        let mut history = History::new(rule, &state);
+
+        for ply in self.get_plies::<N>()? {
+            state.execute_ply(ply)?;
+            history.push(ply, &state);
+        }
+
+        Ok(history)
+    }
 }
 
 impl PtnGame {
@@ -200,52 +221,7 @@ impl FromStr for PtnGame {
     type Err = PtnError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut text = s.trim();
-
-        // Parse headers.
-        let mut headers = Vec::new();
-        if let Some(header_section) = HEADER_SECTION.find(text) {
-            for line in header_section.as_str().lines() {
-                headers.push(line.trim().parse()?);
-            }
-            text = &text[header_section.end()..];
-        }
-
-        // Parse opening comments.
-        let mut opening_comments = Vec::new();
-        while let Some(comment) = BODY_COMMENT.captures(text) {
-            opening_comments.push(comment.name("comment").unwrap().as_str().to_owned());
-            text = &text[comment.get(0).unwrap().end()..];
-        }
-
-        // Parse turns.
-        let mut turns = Vec::new();
-        while let Some(turn) = TURN.find(text) {
-            turns.push(turn.as_str().parse()?);
-            text = &text[turn.end()..];
-        }
-
-        // Parse result.
-        let mut result = None;
-        if let Some(r) = RESULT.find(text) {
-            result = Some(r.as_str().to_owned());
-            text = &text[r.end()..];
-        }
-
-        // Parse closing comments.
-        let mut closing_comments = Vec::new();
-        while let Some(comment) = BODY_COMMENT.captures(text) {
-            closing_comments.push(comment.name("comment").unwrap().as_str().to_owned());
-            text = &text[comment.get(0).unwrap().end()..];
-        }
-
-        Ok(Self {
-            headers,
-            opening_comments,
-            turns,
-            result,
-            closing_comments,
-        })
+        Parser::new(s).parse_game()
     }
 }
 
@@ -430,51 +406,7 @@ impl FromStr for PtnTurn {
     type Err = PtnError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let invalid = || PtnError::InvalidHeader(s.to_owned());
-
-        let c = TURN.captures(s).ok_or_else(invalid)?;
-
-        // p1's move can have comments without a ply.
-        let p1_move = {
-            let ply = c.name("p1_move").map(|m| m.as_str().parse()).transpose()?;
-            let comments = c
-                .name("p1_move_comments")
-                .map(|m| COMMENT.captures_iter(m.as_str()))
-                .map(|i| {
-                    i.map(|x| x.name("comment").unwrap().as_str().to_owned())
-                        .collect()
-                })
-                .unwrap_or_else(Vec::new);
-            PtnMove { ply, comments }
-        };
-
-        // p2's move must have a ply to have comments.
-        let p2_move = if let Some(m) = c.name("p2_move") {
-            PtnMove {
-                ply: Some(m.as_str().parse()?),
-                comments: c
-                    .name("p2_move_comments")
-                    .map(|m| COMMENT.captures_iter(m.as_str()))
-                    .map(|i| {
-                        i.map(|x| x.name("comment").unwrap().as_str().to_owned())
-                            .collect()
-                    })
-                    .unwrap_or_else(Vec::new),
-            }
-        } else {
-            PtnMove::default()
-        };
-
-        Ok(Self {
-            number: c
-                .name("turn_number")
-                .ok_or_else(invalid)?
-                .as_str()
-                .parse()
-                .unwrap(),
-            p1_move,
-            p2_move,
-        })
+        Parser::new(s).parse_turn()
     }
 }
 
@@ -492,7 +424,12 @@ impl fmt::Display for PtnTurn {
         f.pad(&p1_move)?;
 
         if p2_move.ply.is_some() {
-            write!(f, " ")?;
+            // Variations on p1's move interrupt the turn, so it needs to be resumed.
+            if !self.p1_move.variations.is_empty() {
+                write!(f, " {number}... ")?;
+            } else {
+                write!(f, " ")?;
+            }
             let p2_move = format!("{p2_move}");
             f.pad(&p2_move)?;
         }
@@ -504,7 +441,11 @@ impl fmt::Display for PtnTurn {
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct PtnMove {
     pub ply: Option<PtnPly>,
+    /// Numeric annotation glyphs, written as `$n`.
+    pub nags: Vec<u8>,
     pub comments: Vec<String>,
+    /// Alternatives to this move, each starting in the same turn.
+    pub variations: Vec<Vec<PtnTurn>>,
 }
 
 impl fmt::Display for PtnMove {
@@ -517,10 +458,25 @@ impl fmt::Display for PtnMove {
             write!(b, "--")?;
         }
 
+        for nag in &self.nags {
+            write!(b, " ${nag}")?;
+        }
+
         for comment in &self.comments {
             write!(b, " {{{comment}}}")?;
         }
 
+        for variation in &self.variations {
+            write!(b, " (")?;
+            for (i, turn) in variation.iter().enumerate() {
+                if i > 0 {
+                    write!(b, " ")?;
+                }
+                write!(b, "{turn}")?;
+            }
+            write!(b, ")")?;
+        }
+
         f.pad(&b)
     }
 }
@@ -594,6 +550,12 @@ impl FromStr for PtnPly {
                 return Err(invalid());
             }
 
+            // A crush is only possible when a capstone is dropped by itself.
+            let is_crush = annotations.as_deref().is_some_and(|a| a.contains('*'));
+            if is_crush && drops.last() != Some(&1) {
+                return Err(invalid());
+            }
+
             Ok(PtnPly::Spread {
                 x: file_number,
                 y: rank_number,
@@ -760,6 +722,13 @@ pub enum PtnError {
     TpsError(TpsError),
     StateError(StateError),
     PlyError(PlyError),
+    InvalidSyntax(String),
+    /// An error at a position in PTN text, where lines and columns start at 1.
+    Parse {
+        line: usize,
+        column: usize,
+        error: Box<PtnError>,
+    },
 }
 
 impl From<IoError> for PtnError {
@@ -786,46 +755,17 @@ impl From<PlyError> for PtnError {
     }
 }
 
-static HEADER_SECTION: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^(?:\[[^\]\n]+\](?:(?:\r?\n)|$))+").unwrap());
-
 static HEADER: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"^\[(?P<key>[^\s]+) "(?P<value>.+)"\]$"#).unwrap());
 
-static COMMENT_PATTERN: &str = r"\s*\{(?P<comment>[^}]*)\}";
-static COMMENTS_PATTERN: &str = r"(?:\s*\{[^}]*\})+";
-
-static BODY_COMMENT: Lazy<Regex> =
-    Lazy::new(|| Regex::new(&format!(r"^{COMMENT_PATTERN}")).unwrap());
-
-static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(COMMENT_PATTERN).unwrap());
-
-static TURN: Lazy<Regex> = Lazy::new(|| {
-    let turn_number = r"^\s*(?P<turn_number>\d+)\.";
-    // p1's move can have comments even if there is no ply.
-    let p1_move = format!(
-        r"(?:\.\.|\s+--|\s+(?P<p1_move>[FSCa-h1-8<>+\-?!*]+))(?P<p1_move_comments>{COMMENTS_PATTERN})?"
-    );
-    // p2's move must have a ply in order for there to be comments.
-    let p2_move = format!(
-        r"(?:\s+(?:--|(?P<p2_move>[FSCa-h1-8<>+\-?!*]+))(?P<p2_move_comments>{COMMENTS_PATTERN})?)?"
-    );
-    let end = r"(?:\s+|$)";
-
-    Regex::new(&format!(r"{turn_number}{p1_move}{p2_move}{end}")).unwrap()
-});
-
 static PLY: Lazy<Regex> = Lazy::new(|| {
     let place = r"(?P<place_type>[FSC])?(?P<place_file>[a-h])(?P<place_rank>[1-8])";
     let spread = r"(?P<carry>\d)?(?P<spread_file>[a-h])(?P<spread_rank>[1-8])(?P<direction>[><+-])(?P<drops>\d+)?(?P<spread_type>[FSC])?";
-    let annotations = r"(?P<annotations>[?!*]+)?";
+    let annotations = r"(?P<annotations>['?!*]+)?";
 
     Regex::new(&format!("^(?:{place}|{spread}){annotations}$")).unwrap()
 });
 
-static RESULT: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^(?:R-0|0-R|F-0|0-F|1-0|0-1|1/2-1/2)").unwrap());
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1080,4 +1020,82 @@ mod tests {
 3. a2+"#,
         );
     }
+
+    #[test]
+    fn variations_round_trip() {
+        let ptn = r#"[Size "5"]
+
+1. a1 (1. e1 a1 2. c3 (2. b2 {Or this.}) 2... b3) 1... e5 {Corner.}
+2. b2' $1 (2. c3 c2 (2. -- d3 3. d4)) 2... d4
+3. c3 {A {nested} comment.}
+1/2-1/2"#;
+
+        let game: PtnGame = ptn.parse().unwrap();
+        assert_eq!(game.get_ply_len(), 5);
+        assert_eq!(game.result.as_deref(), Some("1/2-1/2"));
+
+        let b2 = &game.turns[1].p1_move;
+        assert_eq!(b2.nags, vec![1]);
+        assert_eq!(b2.variations.len(), 1);
+        assert_eq!(b2.variations[0][0].p2_move.variations.len(), 1);
+        assert_eq!(game.turns[2].p1_move.comments, vec!["A {nested} comment."]);
+
+        assert_eq!(game.to_string(), ptn);
+        assert_eq!(game.to_string().parse::<PtnGame>().unwrap(), game);
+
+        let state: State<5> = game.try_into().unwrap();
+        assert_eq!(state.ply_count, 5);
+    }
+
+    #[test]
+    fn comments_and_turn_numbers() {
+        let ptn = "{Before.} 1. a1 {After a1.} e5 2.b2 2... d4 c3 (3. c4) 3... c2 $2";
+
+        let game: PtnGame = ptn.parse().unwrap();
+        assert_eq!(game.opening_comments, vec!["Before."]);
+        assert_eq!(game.turns[0].p1_move.comments, vec!["After a1."]);
+        assert_eq!(game.turns.len(), 3);
+        assert_eq!(game.turns[2].number, 3);
+        assert_eq!(game.turns[2].p1_move.variations.len(), 1);
+        assert_eq!(game.turns[2].p2_move.nags, vec![2]);
+        assert_eq!(
+            game.to_string(),
+            "{Before.}\n\n1. a1 {After a1.} e5\n2. b2 d4\n3. c3 (3. c4) 3... c2 $2"
+        );
+    }
+
+    fn error_position(ptn: &str) -> (usize, usize) {
+        match ptn.parse::<PtnGame>() {
+            Err(PtnError::Parse { line, column, .. }) => (line, column),
+            result => panic!("expected a parse error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_position("[Size \"5\"]\n\n1. a1 e5\n2. b2 z9"), (4, 7));
+        assert_eq!(error_position("1. a1 (1. e5"), (1, 7));
+        assert_eq!(error_position("1. a1 e5)"), (1, 9));
+        assert_eq!(error_position("1. a1 {Unclosed {comment}"), (1, 7));
+        assert_eq!(error_position("[Size \"5\"\n1. a1"), (1, 1));
+        assert_eq!(error_position("(1. a1)"), (1, 1));
+        assert_eq!(error_position("1. a1 e5 1-0 2. b2"), (1, 14));
+        assert_eq!(error_position("1. a1 $x"), (1, 7));
+        assert_eq!(error_position("1. a1 ({Leading.} 1. e1)"), (1, 8));
+        assert_eq!(error_position("1. a1 e5 2. b2 (3. c3)"), (1, 16));
+        assert_eq!(error_position("1. a1 e5 2. b2 (2... c3)"), (1, 16));
+        assert_eq!(error_position("1. a1 e5 (1. c3)"), (1, 10));
+
+        match "1. a1\n  2. b2 3a3>22".parse::<PtnGame>() {
+            Err(PtnError::Parse {
+                line,
+                column,
+                error,
+            }) => {
+                assert_eq!((line, column), (2, 9));
+                assert!(matches!(*error, PtnError::InvalidPly(_)));
+            }
+            result => panic!("expected a parse error, got {result:?}"),
+        }
+    }
 }
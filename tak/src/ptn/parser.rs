@@ -0,0 +1,413 @@
+//! A hand-written PTN parser, which tracks positions so errors can point at the offending text.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::{PtnError, PtnGame, PtnHeader, PtnMove, PtnPly, PtnTurn};
+
+const RESULTS: [&str; 7] = ["R-0", "0-R", "F-0", "0-F", "1-0", "0-1", "1/2-1/2"];
+
+/// A line and column, both starting at 1.
+type Position = (usize, usize);
+
+pub(super) struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum Token {
+    Comment(String),
+    OpenVariation,
+    CloseVariation,
+    /// A move number such as `3.`, or `3...` if the next move is black's.
+    MoveNumber {
+        number: u32,
+        black: bool,
+    },
+    /// `--`, standing in for a move that wasn't made.
+    Placeholder,
+    Nag(u8),
+    Result(String),
+    Ply(PtnPly),
+}
+
+/// Which move of the current turn the next ply fills.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Slot {
+    P1,
+    P2,
+    Done,
+}
+
+/// Where the most recent move is, so comments, glyphs and variations can be attached to it.
+#[derive(Clone, Copy)]
+struct LastMove {
+    turn: usize,
+    p2: bool,
+}
+
+fn move_mut(turns: &mut [PtnTurn], last: LastMove) -> &mut PtnMove {
+    let turn = &mut turns[last.turn];
+    if last.p2 {
+        &mut turn.p2_move
+    } else {
+        &mut turn.p1_move
+    }
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn position(&self) -> Position {
+        (self.line, self.column)
+    }
+
+    fn error(&self, (line, column): Position, error: PtnError) -> PtnError {
+        PtnError::Parse {
+            line,
+            column,
+            error: Box::new(error),
+        }
+    }
+
+    fn syntax_error(&self, position: Position, message: &str) -> PtnError {
+        self.error(position, PtnError::InvalidSyntax(message.to_owned()))
+    }
+
+    pub fn parse_game(mut self) -> Result<PtnGame, PtnError> {
+        let mut game = PtnGame::default();
+
+        self.skip_whitespace();
+        while self.peek() == Some('[') {
+            game.headers.push(self.parse_header()?);
+            self.skip_whitespace();
+        }
+
+        let position = self.position();
+        game.turns = self.parse_line(Some(&mut game), position)?;
+
+        Ok(game)
+    }
+
+    /// Parses a string containing a single turn, with any comments and variations.
+    pub fn parse_turn(self) -> Result<PtnTurn, PtnError> {
+        let position = self.position();
+        let mut game = self.parse_game()?;
+
+        let is_turn = game.headers.is_empty()
+            && game.opening_comments.is_empty()
+            && game.result.is_none()
+            && game.turns.len() == 1;
+
+        if is_turn {
+            Ok(game.turns.pop().unwrap())
+        } else {
+            Err(PtnError::Parse {
+                line: position.0,
+                column: position.1,
+                error: Box::new(PtnError::InvalidSyntax(
+                    "Expected exactly one turn.".to_owned(),
+                )),
+            })
+        }
+    }
+
+    fn parse_header(&mut self) -> Result<PtnHeader, PtnError> {
+        let position = self.position();
+
+        let mut text = String::new();
+        let mut in_value = false;
+        loop {
+            match self.next() {
+                Some('\n') | None => {
+                    return Err(self.syntax_error(position, "Unterminated header."));
+                }
+                Some(c) => {
+                    text.push(c);
+                    match c {
+                        '"' => in_value = !in_value,
+                        ']' if !in_value => break,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        text.parse().map_err(|err| self.error(position, err))
+    }
+
+    /// Parses a comment, after its opening brace. Comments may contain nested braces.
+    fn parse_comment(&mut self, position: Position) -> Result<String, PtnError> {
+        let mut comment = String::new();
+        let mut depth = 0;
+
+        loop {
+            match self.next() {
+                Some('}') if depth == 0 => return Ok(comment),
+                Some(c) => {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    comment.push(c);
+                }
+                None => return Err(self.syntax_error(position, "Unterminated comment.")),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(Position, Token)>, PtnError> {
+        self.skip_whitespace();
+
+        let position = self.position();
+
+        let token = match self.peek() {
+            None => return Ok(None),
+            Some('{') => {
+                self.next();
+                Token::Comment(self.parse_comment(position)?)
+            }
+            Some('(') => {
+                self.next();
+                Token::OpenVariation
+            }
+            Some(')') => {
+                self.next();
+                Token::CloseVariation
+            }
+            Some(_) => {
+                let mut word = String::new();
+
+                // A move number may be written right up against the ply after it.
+                while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+                    word.push(c);
+                    self.next();
+                }
+
+                if !word.is_empty() && self.peek() == Some('.') {
+                    let mut dots = 0;
+                    while self.peek() == Some('.') {
+                        dots += 1;
+                        self.next();
+                    }
+
+                    let number = word
+                        .parse()
+                        .map_err(|_| self.syntax_error(position, "Invalid move number."))?;
+
+                    return Ok(Some((
+                        position,
+                        Token::MoveNumber {
+                            number,
+                            black: dots > 1,
+                        },
+                    )));
+                }
+
+                while let Some(c) = self
+                    .peek()
+                    .filter(|&c| !c.is_whitespace() && !"{}()".contains(c))
+                {
+                    word.push(c);
+                    self.next();
+                }
+
+                if word == "--" {
+                    Token::Placeholder
+                } else if let Some(nag) = word.strip_prefix('$') {
+                    Token::Nag(nag.parse().map_err(|_| {
+                        self.syntax_error(position, "Invalid numeric annotation glyph.")
+                    })?)
+                } else if RESULTS.contains(&word.as_str()) {
+                    Token::Result(word)
+                } else {
+                    Token::Ply(word.parse().map_err(|err| self.error(position, err))?)
+                }
+            }
+        };
+
+        Ok(Some((position, token)))
+    }
+
+    /// Parses a sequence of turns, up to the end of a variation, or to the end of the text
+    /// at the top level, where `game` receives the comments and result around the turns.
+    /// Errors about the whole line are reported at `start`.
+    fn parse_line(
+        &mut self,
+        mut game: Option<&mut PtnGame>,
+        start: Position,
+    ) -> Result<Vec<PtnTurn>, PtnError> {
+        let top_level = game.is_some();
+
+        let mut turns: Vec<PtnTurn> = Vec::new();
+        let mut slot = Slot::Done;
+        let mut last: Option<LastMove> = None;
+        let mut ended = false;
+
+        loop {
+            let Some((position, token)) = self.next_token()? else {
+                if top_level {
+                    break;
+                } else {
+                    return Err(self.syntax_error(start, "Unterminated variation."));
+                }
+            };
+
+            if ended && !matches!(token, Token::Comment(_)) {
+                return Err(self.syntax_error(position, "Unexpected text after the result."));
+            }
+
+            match token {
+                Token::Comment(comment) => {
+                    if let Some(game) = game.as_deref_mut().filter(|_| ended) {
+                        game.closing_comments.push(comment);
+                    } else if let Some(last) = last {
+                        move_mut(&mut turns, last).comments.push(comment);
+                    } else if let Some(game) = game.as_deref_mut() {
+                        game.opening_comments.push(comment);
+                    } else {
+                        // There's no move to attach it to until the variation's first ply.
+                        return Err(self.syntax_error(
+                            position,
+                            "Comment before the first move of a variation.",
+                        ));
+                    }
+                }
+                Token::OpenVariation => {
+                    let last = last
+                        .filter(|&last| move_mut(&mut turns, last).ply.is_some())
+                        .ok_or_else(|| {
+                            self.syntax_error(position, "Variation without a preceding move.")
+                        })?;
+
+                    let variation = self.parse_line(None, position)?;
+
+                    // The variation must start with another move for the same player on
+                    // the same turn.
+                    let first = &variation[0];
+                    let replaces_last = first.number == turns[last.turn].number
+                        && if last.p2 {
+                            first.p1_move.ply.is_none() && first.p2_move.ply.is_some()
+                        } else {
+                            first.p1_move.ply.is_some()
+                        };
+                    if !replaces_last {
+                        return Err(self.syntax_error(
+                            position,
+                            "Variation doesn't start with the move it replaces.",
+                        ));
+                    }
+
+                    move_mut(&mut turns, last).variations.push(variation);
+                }
+                Token::CloseVariation => {
+                    if top_level {
+                        return Err(self.syntax_error(position, "Unmatched parenthesis."));
+                    }
+                    if last.is_none() {
+                        return Err(self.syntax_error(start, "Empty variation."));
+                    }
+                    break;
+                }
+                Token::MoveNumber { number, black } => {
+                    let continues_turn = turns.last().is_some_and(|turn| {
+                        turn.number == number && turn.p2_move.ply.is_none() && slot != Slot::P1
+                    });
+
+                    if black && continues_turn {
+                        slot = Slot::P2;
+                    } else {
+                        turns.push(PtnTurn {
+                            number,
+                            ..Default::default()
+                        });
+                        slot = if black { Slot::P2 } else { Slot::P1 };
+                    }
+                }
+                Token::Placeholder => match slot {
+                    Slot::P1 => {
+                        last = Some(LastMove {
+                            turn: turns.len() - 1,
+                            p2: false,
+                        });
+                        slot = Slot::P2;
+                    }
+                    Slot::P2 => slot = Slot::Done,
+                    Slot::Done => {
+                        return Err(
+                            self.syntax_error(position, "Placeholder without a move number.")
+                        );
+                    }
+                },
+                Token::Nag(nag) => {
+                    let last = last.ok_or_else(|| {
+                        self.syntax_error(position, "Annotation glyph without a preceding move.")
+                    })?;
+                    move_mut(&mut turns, last).nags.push(nag);
+                }
+                Token::Result(result) => match game.as_deref_mut() {
+                    Some(game) => {
+                        game.result = Some(result);
+                        ended = true;
+                    }
+                    None => {
+                        return Err(self.syntax_error(position, "Result inside a variation."));
+                    }
+                },
+                Token::Ply(ply) => {
+                    if slot == Slot::Done {
+                        let number = turns.last().map_or(1, |turn| turn.number + 1);
+                        turns.push(PtnTurn {
+                            number,
+                            ..Default::default()
+                        });
+                        slot = Slot::P1;
+                    }
+
+                    let current = LastMove {
+                        turn: turns.len() - 1,
+                        p2: slot == Slot::P2,
+                    };
+
+                    let ptn_move = move_mut(&mut turns, current);
+                    ptn_move.ply = Some(ply);
+
+                    last = Some(current);
+                    slot = if current.p2 { Slot::Done } else { Slot::P2 };
+                }
+            }
+        }
+
+        Ok(turns)
+    }
+}
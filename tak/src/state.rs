@@ -11,7 +11,7 @@ use crate::piece::{Color, Piece, PieceType};
 use crate::ply::{generation, Direction, Ply, PlyError};
 use crate::stack::Stack;
 use crate::tps::Tps;
-use crate::zobrist::{zobrist_advance_move, zobrist_hash_stack, zobrist_hash_state};
+use crate::zobrist::{zobrist_advance_move, zobrist_hash_stack, zobrist_hash_state, ZobristHash};
 
 #[derive(Clone, Eq, PartialEq)]
 pub struct State<const N: usize> {
@@ -64,7 +64,7 @@ impl<const N: usize> State<N> {
     }
 
     pub fn to_move(&self) -> Color {
-        if self.ply_count % 2 == 0 {
+        if self.ply_count.is_multiple_of(2) {
             Color::White
         } else {
             Color::Black
@@ -88,6 +88,13 @@ impl<const N: usize> State<N> {
                     return Err(StateError::InvalidPlace("Board space is occupied."));
                 }
 
+                // Only flatstones can be placed on the first turn.
+                if self.ply_count < 2 && piece_type != Flatstone {
+                    return Err(StateError::InvalidPlace(
+                        "Only flatstones can be placed on the first turn.",
+                    ));
+                }
+
                 // Determine piece color.
                 let color = if self.ply_count >= 2 {
                     player_color
@@ -118,6 +125,13 @@ impl<const N: usize> State<N> {
                 direction,
                 drops,
             } => {
+                // Only placements are allowed on the first turn.
+                if self.ply_count < 2 {
+                    return Err(StateError::InvalidSpread(
+                        "Cannot spread on the first turn.",
+                    ));
+                }
+
                 // Board space must not be empty.
                 let stack = &self.board[x as usize][y as usize];
                 if stack.is_empty() {
@@ -180,14 +194,21 @@ impl<const N: usize> State<N> {
         Ok(validation)
     }
 
+    /// Executes a ply without validating it first. The returned [`UndoInfo`] can be
+    /// passed to [`State::undo_ply`] along with the same ply to restore the previous state.
     #[instrument(level = "trace", skip(self))]
-    pub fn execute_ply_unchecked(&mut self, ply: Ply<N>) {
+    pub fn execute_ply_unchecked(&mut self, ply: Ply<N>) -> UndoInfo {
         use Color::*;
         use PieceType::*;
 
         let player_color = self.to_move();
         let m = &mut self.metadata;
 
+        let mut undo_info = UndoInfo {
+            hash: m.hash,
+            is_crush: false,
+        };
+
         match ply {
             Ply::Place { x, y, piece_type } => {
                 let color = if self.ply_count >= 2 {
@@ -256,6 +277,12 @@ impl<const N: usize> State<N> {
                         ty as usize,
                     );
 
+                    // The only way to drop onto a standing stone is to crush it.
+                    if self.board[tx as usize][ty as usize].top_piece_type() == Some(StandingStone)
+                    {
+                        undo_info.is_crush = true;
+                    }
+
                     self.board[tx as usize][ty as usize].add(carry.drop(drop as usize));
 
                     m.hash ^= zobrist_hash_stack::<N>(
@@ -269,6 +296,92 @@ impl<const N: usize> State<N> {
 
         m.hash ^= zobrist_advance_move::<N>();
         self.ply_count += 1;
+
+        undo_info
+    }
+
+    /// Reverses a ply that was executed with [`State::execute_ply_unchecked`]. The ply
+    /// and undo info must be the ones from the most recent execution on this state.
+    #[instrument(level = "trace", skip(self))]
+    pub fn undo_ply(&mut self, ply: Ply<N>, undo_info: UndoInfo) {
+        use Color::*;
+        use PieceType::*;
+
+        self.ply_count -= 1;
+
+        let player_color = self.to_move();
+
+        match ply {
+            Ply::Place { x, y, piece_type } => {
+                let color = if self.ply_count >= 2 {
+                    player_color
+                } else {
+                    player_color.other()
+                };
+
+                let player_counts = match color {
+                    White => (&mut self.p1_flatstones, &mut self.p1_capstones),
+                    Black => (&mut self.p2_flatstones, &mut self.p2_capstones),
+                };
+
+                let selected_count = match piece_type {
+                    Capstone => player_counts.1,
+                    _ => player_counts.0,
+                };
+
+                // Return the piece to the reserves.
+                *selected_count += 1;
+
+                self.board[x as usize][y as usize] = Stack::default();
+                self.metadata
+                    .set_stack(Stack::default(), x as usize, y as usize);
+            }
+            Ply::Spread {
+                x,
+                y,
+                direction,
+                drops,
+            } => {
+                let mut drop_counts = [0; 8];
+                for (count, drop) in drop_counts.iter_mut().zip(drops.iter()) {
+                    *count = drop;
+                }
+
+                let (dx, dy) = direction.to_offset();
+                let (mut tx, mut ty) = (
+                    x as i8 + dx * drops.len() as i8,
+                    y as i8 + dy * drops.len() as i8,
+                );
+
+                // Pick the dropped pieces back up, starting from the end of the spread.
+                let mut carry = Stack::default();
+                for (i, &drop) in drop_counts[..drops.len()].iter().enumerate().rev() {
+                    let target = &mut self.board[tx as usize][ty as usize];
+
+                    let mut dropped = target.take(drop as usize);
+                    dropped.add(carry);
+                    carry = dropped;
+
+                    // Stand the crushed stone back up.
+                    if undo_info.is_crush && i == drops.len() - 1 {
+                        let mut piece = target.take(1).top().expect("no crushed stone");
+                        piece.set_piece_type(StandingStone);
+                        target.add_piece(piece);
+                    }
+
+                    self.metadata.set_stack(*target, tx as usize, ty as usize);
+
+                    tx -= dx;
+                    ty -= dy;
+                }
+
+                let source = &mut self.board[x as usize][y as usize];
+                source.add(carry);
+                self.metadata.set_stack(*source, x as usize, y as usize);
+            }
+        }
+
+        self.metadata.hash = undo_info.hash;
     }
 
     pub fn resolution(&self) -> Option<Resolution> {
@@ -320,6 +433,9 @@ impl<const N: usize> State<N> {
         }
     }
 
+    /// Whether the opponent of `color` could complete a road with their next ply, either
+    /// by placing a piece or by moving pieces, including single pieces that capture or
+    /// crush their way into a road.
     pub fn is_in_tak(&self, color: Color) -> bool {
         let m = &self.metadata;
 
@@ -340,16 +456,37 @@ impl<const N: usize> State<N> {
             Color::Black => m.p1_pieces,
         };
 
-        let stack_bits = opponent_pieces.bits().filter(|b| {
-            let (x, y) = b.coordinates();
-            self.board[x][y].len() > 1
-        });
+        for stack_bit in opponent_pieces.bits() {
+            let (x, y) = stack_bit.coordinates();
+            let stack = &self.board[x][y];
+
+            // A spread only changes the squares in its line, so stacks that couldn't complete
+            // a road even by covering every square they reach don't need their spreads checked.
+            let mut reach = stack_bit;
+            for direction in [
+                Direction::North,
+                Direction::East,
+                Direction::South,
+                Direction::West,
+            ] {
+                let (dx, dy) = direction.to_offset();
+                let (mut tx, mut ty) = (x as i8, y as i8);
 
-        for stack_bit in stack_bits {
-            for ply in generation::spreads(self, stack_bit) {
-                let (x, y) = stack_bit.coordinates();
-                let stack = &self.board[x][y];
+                for _ in 0..N.min(stack.len()) {
+                    tx += dx;
+                    ty += dy;
+                    if tx < 0 || ty < 0 || tx as usize >= N || ty as usize >= N {
+                        break;
+                    }
+                    reach.set(tx as usize, ty as usize);
+                }
+            }
 
+            if !spans_board(opponent_road_pieces | reach) {
+                continue;
+            }
+
+            for ply in generation::spreads(self, stack_bit) {
                 fn spread_creates_road<const N: usize>(
                     mut road_pieces: Bitmap<N>,
                     stack: &Stack,
@@ -524,6 +661,13 @@ pub struct PlyValidation<const N: usize> {
     pub is_crush: bool,
 }
 
+/// The information lost when executing a ply, which is needed to undo it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UndoInfo {
+    hash: ZobristHash,
+    is_crush: bool,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Resolution {
     Road(Color),
@@ -716,6 +860,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn first_turn_only_flatstones() {
+        let s = state::<5>("x5/x5/x5/x5/x5 1 1");
+        assert!(s.validate_ply(ply("a1")).is_ok());
+        assert_eq!(
+            s.validate_ply(ply("Sa1")),
+            Err(StateError::InvalidPlace(
+                "Only flatstones can be placed on the first turn."
+            )),
+        );
+        assert_eq!(
+            s.validate_ply(ply("Ca1")),
+            Err(StateError::InvalidPlace(
+                "Only flatstones can be placed on the first turn."
+            )),
+        );
+
+        let s = state::<5>("x5/x5/x5/x5/2,x4 2 1");
+        assert!(s.validate_ply(ply("b1")).is_ok());
+        assert_eq!(
+            s.validate_ply(ply("a1>")),
+            Err(StateError::InvalidSpread(
+                "Cannot spread on the first turn."
+            )),
+        );
+    }
+
     #[test]
     fn resolution() {
         let s = State::<5>::default();
@@ -819,6 +990,17 @@ mod tests {
         let s = state::<6>("x6/x6/x,1212121C,x4/1,1,x,x,1,1/x6/x6 1 1");
         assert!(!s.is_in_tak(Color::White));
         assert!(s.is_in_tak(Color::Black));
+
+        // A single flatstone capturing its way into a road.
+        let s = state::<6>("x6/x6/x3,2,x2/2,2,2,1,2,2/x6/x6 1 1");
+        assert!(s.is_in_tak(Color::White));
+
+        // A lone capstone crushing a standing stone to complete a road.
+        let s = state::<6>("x6/x6/x3,2C,x2/2,2,2,1S,2,2/x6/x6 1 1");
+        assert!(s.is_in_tak(Color::White));
+
+        let s = state::<6>("x6/x6/x3,2,x2/2,2,2,1S,2,2/x6/x6 1 1");
+        assert!(!s.is_in_tak(Color::White));
     }
 
     #[test]
@@ -858,4 +1040,59 @@ mod tests {
         s.execute_ply(ply("b1>")).unwrap();
         assert_eq!(s.metadata.hash, initial_hash);
     }
+
+    #[test]
+    fn undo_crush() {
+        let mut s = state::<5>("x5/x,212121C,212,2S,x/x5/x5/x5 1 2");
+        let original = s.clone();
+
+        let crush = ply("3b4>21");
+        let undo_info = s.execute_ply_unchecked(crush);
+        assert_eq!(s, state("x5/x,212,21212,21C,x/x5/x5/x5 2 2"));
+
+        s.undo_ply(crush, undo_info);
+        assert_eq!(s, original);
+    }
+
+    fn execute_and_undo_are_inverses_sized<const N: usize>() {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        let mut rng = StdRng::seed_from_u64(N as u64);
+
+        for _ in 0..20 {
+            let mut s = State::<N>::default();
+
+            while s.resolution().is_none() && s.ply_count < 120 {
+                let plies = generation::legal_plies(&s);
+
+                for &ply in &plies {
+                    let mut next = s.clone();
+                    let validation = next.validate_ply(ply).unwrap();
+                    let undo_info = next.execute_ply_unchecked(ply);
+                    assert_eq!(undo_info.is_crush, validation.is_crush);
+
+                    let mut recalculated = next.clone();
+                    recalculated.recalculate_metadata();
+                    assert_eq!(next.metadata, recalculated.metadata);
+
+                    next.undo_ply(ply, undo_info);
+                    assert_eq!(next, s, "undo of {ply:?} did not restore the state");
+                }
+
+                s.execute_ply(*plies.choose(&mut rng).unwrap()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn execute_and_undo_are_inverses() {
+        execute_and_undo_are_inverses_sized::<3>();
+        execute_and_undo_are_inverses_sized::<4>();
+        execute_and_undo_are_inverses_sized::<5>();
+        execute_and_undo_are_inverses_sized::<6>();
+        execute_and_undo_are_inverses_sized::<7>();
+        execute_and_undo_are_inverses_sized::<8>();
+    }
 }
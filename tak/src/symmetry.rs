@@ -0,0 +1,286 @@
+use crate::bitmap::Bitmap;
+use crate::piece::Color;
+use crate::ply::{Direction, Ply};
+use crate::state::State;
+use crate::zobrist::{zobrist_advance_move, zobrist_hash_stack, ZobristHash};
+
+/// One of the 8 ways to rotate or mirror a square board onto itself.
+///
+/// Rotations are clockwise, looking at the board with a1 in the bottom left corner.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Swaps the a and h files.
+    FlipFiles,
+    /// Swaps the 1st and 8th ranks.
+    FlipRanks,
+    /// Mirrors across the a1-h8 diagonal.
+    Transpose,
+    /// Mirrors across the a8-h1 diagonal.
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipFiles,
+        Symmetry::FlipRanks,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    /// The symmetry that undoes this one.
+    pub fn inverse(self) -> Self {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            symmetry => symmetry,
+        }
+    }
+
+    /// Transforms an offset relative to the center of the board.
+    fn offset(self, dx: i32, dy: i32) -> (i32, i32) {
+        match self {
+            Symmetry::Identity => (dx, dy),
+            Symmetry::Rotate90 => (dy, -dx),
+            Symmetry::Rotate180 => (-dx, -dy),
+            Symmetry::Rotate270 => (-dy, dx),
+            Symmetry::FlipFiles => (-dx, dy),
+            Symmetry::FlipRanks => (dx, -dy),
+            Symmetry::Transpose => (dy, dx),
+            Symmetry::AntiTranspose => (-dy, -dx),
+        }
+    }
+
+    pub fn coordinates<const N: usize>(self, x: usize, y: usize) -> (usize, usize) {
+        // Doubling the coordinates puts the center of the board on a whole number.
+        let n = N as i32 - 1;
+        let (dx, dy) = self.offset(2 * x as i32 - n, 2 * y as i32 - n);
+        (((dx + n) / 2) as usize, ((dy + n) / 2) as usize)
+    }
+
+    pub fn direction(self, direction: Direction) -> Direction {
+        let (dx, dy) = direction.to_offset();
+        match self.offset(dx as i32, dy as i32) {
+            (0, 1) => Direction::North,
+            (1, 0) => Direction::East,
+            (0, -1) => Direction::South,
+            (-1, 0) => Direction::West,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Transforms a board indexed by `[x][y]`, such as `State::board`.
+    pub fn board<T: Copy, const N: usize>(self, board: &[[T; N]; N]) -> [[T; N]; N] {
+        let mut transformed = *board;
+        for (x, column) in board.iter().enumerate() {
+            for (y, &square) in column.iter().enumerate() {
+                let (tx, ty) = self.coordinates::<N>(x, y);
+                transformed[tx][ty] = square;
+            }
+        }
+        transformed
+    }
+}
+
+impl<const N: usize> Bitmap<N> {
+    pub fn transform(self, symmetry: Symmetry) -> Self {
+        let mut transformed = Self::empty();
+        for bit in self.bits() {
+            let (x, y) = bit.coordinates();
+            let (x, y) = symmetry.coordinates::<N>(x, y);
+            transformed.set(x, y);
+        }
+        transformed
+    }
+}
+
+impl<const N: usize> Ply<N> {
+    pub fn transform(self, symmetry: Symmetry) -> Self {
+        let coordinates = |x: u8, y: u8| {
+            let (x, y) = symmetry.coordinates::<N>(x as usize, y as usize);
+            (x as u8, y as u8)
+        };
+
+        match self {
+            Ply::Place { x, y, piece_type } => {
+                let (x, y) = coordinates(x, y);
+                Ply::Place { x, y, piece_type }
+            }
+            Ply::Spread {
+                x,
+                y,
+                direction,
+                drops,
+            } => {
+                let (x, y) = coordinates(x, y);
+                Ply::Spread {
+                    x,
+                    y,
+                    direction: symmetry.direction(direction),
+                    drops,
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> State<N> {
+    pub fn transform(&self, symmetry: Symmetry) -> Self {
+        let mut state = self.clone();
+        state.board = symmetry.board(&self.board);
+        state.recalculate_metadata();
+        state
+    }
+
+    /// The Zobrist hash this state would have after the symmetry was applied.
+    pub fn transformed_hash(&self, symmetry: Symmetry) -> ZobristHash {
+        let mut hash = 0;
+
+        if self.to_move() == Color::Black {
+            hash ^= zobrist_advance_move::<N>();
+        }
+
+        for (x, column) in self.board.iter().enumerate() {
+            for (y, &stack) in column.iter().enumerate() {
+                let (x, y) = symmetry.coordinates::<N>(x, y);
+                hash ^= zobrist_hash_stack::<N>(stack, x, y);
+            }
+        }
+
+        hash
+    }
+
+    /// The symmetry that gives this state its canonical form, which is the same for
+    /// every rotation and reflection of the state.
+    pub fn canonical_symmetry(&self) -> Symmetry {
+        Symmetry::ALL
+            .into_iter()
+            .min_by_key(|&symmetry| self.transformed_hash(symmetry))
+            .unwrap()
+    }
+
+    /// A hash that is the same for every rotation and reflection of this state.
+    pub fn canonical_hash(&self) -> ZobristHash {
+        Symmetry::ALL
+            .into_iter()
+            .map(|symmetry| self.transformed_hash(symmetry))
+            .min()
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TPS: &str = "x2,2,x2/x,2,1S,21C,x/12,x,1,12C,x/x,2,2S,1,x/x,1,x3 1 8";
+
+    fn ply<const N: usize>(ptn: &str) -> Ply<N> {
+        ptn.parse().unwrap()
+    }
+
+    #[test]
+    fn coordinates() {
+        use Symmetry::*;
+
+        let expected = [
+            (Identity, "b1"),
+            (Rotate90, "a4"),
+            (Rotate180, "d5"),
+            (Rotate270, "e2"),
+            (FlipFiles, "d1"),
+            (FlipRanks, "b5"),
+            (Transpose, "a2"),
+            (AntiTranspose, "e4"),
+        ];
+
+        for (symmetry, square) in expected {
+            assert_eq!(
+                ply::<5>("b1").transform(symmetry),
+                ply(square),
+                "{symmetry:?}"
+            );
+        }
+
+        assert_eq!(Rotate90.coordinates::<4>(1, 1), (1, 2));
+        assert_eq!(Rotate90.coordinates::<3>(1, 1), (1, 1));
+    }
+
+    #[test]
+    fn spread_directions() {
+        assert_eq!(
+            ply::<5>("3c3>12").transform(Symmetry::Rotate90),
+            ply("3c3-12")
+        );
+        assert_eq!(ply::<5>("a1+").transform(Symmetry::Transpose), ply("a1>"));
+        assert_eq!(ply::<5>("b2<").transform(Symmetry::FlipFiles), ply("d2>"));
+    }
+
+    #[test]
+    fn inverses() {
+        let state: State<5> = TPS.parse().unwrap();
+
+        for symmetry in Symmetry::ALL {
+            let bitmap = state.metadata.p1_pieces;
+            assert_eq!(
+                bitmap.transform(symmetry).transform(symmetry.inverse()),
+                bitmap
+            );
+            assert_eq!(
+                state.transform(symmetry).transform(symmetry.inverse()),
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn transforms_commute_with_plies() {
+        let state: State<5> = TPS.parse().unwrap();
+
+        for symmetry in Symmetry::ALL {
+            let transformed = state.transform(symmetry);
+            assert_eq!(transformed.metadata.hash, state.transformed_hash(symmetry));
+            assert_eq!(
+                transformed.metadata.p2_pieces,
+                state.metadata.p2_pieces.transform(symmetry)
+            );
+
+            for ply in crate::generation::legal_plies(&state) {
+                let mut after = state.clone();
+                after.execute_ply(ply).unwrap();
+
+                let mut transformed_after = transformed.clone();
+                transformed_after
+                    .execute_ply(ply.transform(symmetry))
+                    .unwrap();
+
+                assert_eq!(transformed_after, after.transform(symmetry), "{ply:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_hash_is_invariant() {
+        let state: State<5> = TPS.parse().unwrap();
+        let hash = state.canonical_hash();
+
+        for symmetry in Symmetry::ALL {
+            let transformed = state.transform(symmetry);
+            assert_eq!(transformed.canonical_hash(), hash);
+
+            let canonical = transformed.transform(transformed.canonical_symmetry());
+            assert_eq!(canonical.metadata.hash, hash);
+        }
+
+        let mut other = state.clone();
+        other.execute_ply(ply("a1")).unwrap();
+        assert_ne!(other.canonical_hash(), hash);
+    }
+}
@@ -41,7 +41,7 @@ impl FromStr for Tps {
                 board
                     .last_mut()
                     .unwrap()
-                    .extend(std::iter::repeat(Stack::default()).take(count));
+                    .extend(std::iter::repeat_n(Stack::default(), count));
             } else if let Some(s) = c.name("stack") {
                 let mut stack = Stack::default();
                 let mut stones = s.as_str().chars().peekable();
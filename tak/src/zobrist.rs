@@ -29,16 +29,11 @@ impl<const N: usize> ZobristKeys<N> {
         let mut rng = {
             use rand::rngs::StdRng;
             use rand::SeedableRng;
-            use std::env;
             use std::sync::Mutex;
             use tracing::debug;
 
             static RNG: Lazy<Mutex<StdRng>> = Lazy::new(|| {
-                let seed = if let Ok(seed) = env::var("FIXED_RNG_SEED") {
-                    seed.parse::<u64>().expect("could not parse random seed")
-                } else {
-                    u64::from_be_bytes([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])
-                };
+                let seed = zobrist_seed().unwrap();
 
                 debug!(?seed, "Initializing Zobrist hashing rng.");
 
@@ -76,6 +71,27 @@ impl<const N: usize> Fill for ZobristKeys<N> {
     }
 }
 
+/// The seed the Zobrist keys are generated from, which can be set with the `FIXED_RNG_SEED`
+/// environment variable. Without the `fixed-rng` feature the keys are random, and differ
+/// between runs.
+pub fn zobrist_seed() -> Option<u64> {
+    #[cfg(feature = "fixed-rng")]
+    {
+        static SEED: Lazy<u64> = Lazy::new(|| {
+            if let Ok(seed) = std::env::var("FIXED_RNG_SEED") {
+                seed.parse::<u64>().expect("could not parse random seed")
+            } else {
+                u64::from_be_bytes([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])
+            }
+        });
+
+        Some(*SEED)
+    }
+
+    #[cfg(not(feature = "fixed-rng"))]
+    None
+}
+
 static ZOBRIST_KEYS_3S: Lazy<ZobristKeys<3>> = Lazy::new(ZobristKeys::<3>::new);
 static ZOBRIST_KEYS_4S: Lazy<ZobristKeys<4>> = Lazy::new(ZobristKeys::<4>::new);
 static ZOBRIST_KEYS_5S: Lazy<ZobristKeys<5>> = Lazy::new(ZobristKeys::<5>::new);